@@ -0,0 +1,17 @@
+-- classes open for online entries, entries are accepted until the deadline
+create table entry_classes
+(
+    class_name  TEXT primary key,
+    deadline    TEXT not null,
+    max_entries INTEGER
+);
+
+-- online entries, entered runner is stored in runs table with status EntryPending
+create table entries
+(
+    id         INTEGER primary key autoincrement,
+    run_id     INTEGER not null constraint entries_run_id unique,
+    club       TEXT,
+    entered_by TEXT not null,
+    created    TEXT not null
+);
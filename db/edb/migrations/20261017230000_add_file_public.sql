@@ -0,0 +1,2 @@
+-- public files are event bulletins, readable even if event files are not public
+alter table files add column public INTEGER not null default 0;
@@ -0,0 +1,9 @@
+create table sessions
+(
+    id           TEXT primary key,
+    user_name    TEXT not null,
+    user_email   TEXT not null,
+    user_picture TEXT not null,
+    created      TEXT not null,
+    last_seen    TEXT not null
+);
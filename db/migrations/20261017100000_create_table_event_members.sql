@@ -0,0 +1,21 @@
+create table event_members
+(
+    id         INTEGER primary key autoincrement,
+    event_id   INTEGER not null,
+    user_email TEXT not null,
+    role       TEXT not null,
+    created    TEXT default CURRENT_TIMESTAMP,
+    constraint event_members_event_user unique (event_id, user_email)
+);
+
+create table event_invitations
+(
+    id            INTEGER primary key autoincrement,
+    event_id      INTEGER not null,
+    token         TEXT not null constraint event_invitations_token unique,
+    role          TEXT not null,
+    invited_email TEXT,
+    invited_by    TEXT not null,
+    created       TEXT not null,
+    expires       TEXT not null
+);
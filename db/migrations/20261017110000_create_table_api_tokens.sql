@@ -0,0 +1,18 @@
+create table api_tokens
+(
+    id        INTEGER primary key autoincrement,
+    event_id  INTEGER not null,
+    name      TEXT not null,
+    token     TEXT not null constraint api_tokens_token unique,
+    scopes    TEXT not null,
+    created   TEXT not null,
+    expires   TEXT,
+    last_used TEXT,
+    revoked   INTEGER not null default 0
+);
+
+-- existing event tokens are kept as tokens with full access
+insert into api_tokens (event_id, name, token, scopes, created)
+select id, 'Default', api_token, '["qe-sync"]', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+from events
+where api_token is not null;
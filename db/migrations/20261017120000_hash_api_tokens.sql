@@ -0,0 +1,24 @@
+-- API tokens are stored as salted hashes, plaintext tokens are hashed and cleared at server startup
+create table api_tokens_new
+(
+    id           INTEGER primary key autoincrement,
+    event_id     INTEGER not null,
+    name         TEXT not null,
+    token_prefix TEXT not null,
+    token_salt   TEXT,
+    token_hash   TEXT,
+    token        TEXT,
+    scopes       TEXT not null,
+    created      TEXT not null,
+    expires      TEXT,
+    last_used    TEXT,
+    revoked      INTEGER not null default 0
+);
+insert into api_tokens_new (id, event_id, name, token_prefix, token, scopes, created, expires, last_used, revoked)
+select id, event_id, name, substr(token, 1, 8), token, scopes, created, expires, last_used, revoked
+from api_tokens;
+drop table api_tokens;
+alter table api_tokens_new rename to api_tokens;
+create index api_tokens_token_prefix on api_tokens (token_prefix);
+
+update events set api_token = null;
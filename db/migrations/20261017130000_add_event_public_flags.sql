@@ -0,0 +1,3 @@
+alter table events add column files_public integer not null default 1;
+alter table events add column runs_public integer not null default 1;
+alter table events add column changes_public integer not null default 1;
@@ -0,0 +1,22 @@
+create table audit_log
+(
+    id        INTEGER primary key autoincrement,
+    event_id  INTEGER not null,
+    created   TEXT not null,
+    actor     TEXT not null,
+    action    TEXT not null,
+    target    TEXT not null,
+    old_value TEXT,
+    new_value TEXT
+);
+create index audit_log_event_id on audit_log (event_id, id);
+
+-- audit log is append-only
+create trigger audit_log_no_update before update on audit_log
+begin
+    select raise(abort, 'audit log is append-only');
+end;
+create trigger audit_log_no_delete before delete on audit_log
+begin
+    select raise(abort, 'audit log is append-only');
+end;
@@ -0,0 +1,2 @@
+-- key of signed per-run change request links, generated when the first link is created
+alter table events add column run_link_secret TEXT;
@@ -0,0 +1,19 @@
+-- personal access tokens act on behalf of user, they are stored as salted hashes like API tokens
+create table personal_tokens
+(
+    id           INTEGER primary key autoincrement,
+    user_email   TEXT not null,
+    user_name    TEXT not null,
+    user_picture TEXT not null,
+    name         TEXT not null,
+    token_prefix TEXT not null,
+    token_salt   TEXT not null,
+    token_hash   TEXT not null,
+    scopes       TEXT not null,
+    created      TEXT not null,
+    expires      TEXT,
+    last_used    TEXT,
+    revoked      INTEGER not null default 0
+);
+create index personal_tokens_token_prefix on personal_tokens (token_prefix);
+create index personal_tokens_user_email on personal_tokens (user_email);
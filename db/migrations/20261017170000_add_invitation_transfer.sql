@@ -0,0 +1,2 @@
+-- invitation transferring event ownership to invited user
+alter table event_invitations add column transfer INTEGER not null default 0;
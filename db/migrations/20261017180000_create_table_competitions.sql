@@ -0,0 +1,13 @@
+-- competition groups stage events, overall results are aggregated from stage runs matched by registration
+create table competitions
+(
+    id          INTEGER primary key autoincrement,
+    name        TEXT not null,
+    owner       TEXT not null,
+    aggregation TEXT not null default 'TimeSum',
+    best_count  INTEGER,
+    created     TEXT default CURRENT_TIMESTAMP
+);
+
+alter table events add column competition_id INTEGER references competitions (id) on delete set null;
+create index events_competition_id on events (competition_id);
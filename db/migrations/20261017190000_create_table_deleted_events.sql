@@ -0,0 +1,11 @@
+-- deleted events can be restored until their DB file is purged from the trash directory
+create table deleted_events
+(
+    id         INTEGER primary key autoincrement,
+    event_id   INTEGER not null,
+    event      TEXT not null,
+    db_file    TEXT,
+    deleted    TEXT not null,
+    deleted_by TEXT not null
+);
+create index deleted_events_event_id on deleted_events (event_id);
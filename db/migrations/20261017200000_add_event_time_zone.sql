@@ -0,0 +1,2 @@
+-- IANA time zone of event, used for times without UTC offset and for display
+alter table events add column time_zone TEXT not null default 'Europe/Prague';
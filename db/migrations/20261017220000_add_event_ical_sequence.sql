@@ -0,0 +1,2 @@
+-- incremented on every event update, so that calendar clients replace the event in iCalendar feed
+alter table events add column ical_sequence INTEGER not null default 0;
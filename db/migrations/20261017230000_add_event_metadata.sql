@@ -0,0 +1,7 @@
+-- links and contacts are JSON arrays, description is Markdown
+alter table events add column organizer TEXT not null default '';
+alter table events add column description TEXT not null default '';
+alter table events add column links TEXT not null default '[]';
+alter table events add column latitude REAL;
+alter table events add column longitude REAL;
+alter table events add column contacts TEXT not null default '[]';
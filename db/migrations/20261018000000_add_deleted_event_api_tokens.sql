@@ -0,0 +1,2 @@
+-- API tokens of deleted event are revoked, JSON array of their IDs, they are enabled again when the event is restored
+alter table deleted_events add column api_token_ids TEXT not null default '[]';
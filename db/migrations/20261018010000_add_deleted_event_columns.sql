@@ -0,0 +1,4 @@
+-- columns of deleted event not present in its JSON, they are restored with the event
+alter table deleted_events add column run_link_secret TEXT;
+alter table deleted_events add column competition_id INTEGER;
+alter table deleted_events add column ical_sequence INTEGER not null default 0;
@@ -0,0 +1,153 @@
+use std::marker::PhantomData;
+use rocket::http::Status;
+use rocket::{request, State};
+use crate::apitoken::ApiScope;
+use crate::audit::Actor;
+use crate::db::DbPool;
+use crate::event::{load_event, user_info_opt, EventId, EventRecord};
+use crate::members::{load_event_role, EventRole};
+use crate::personaltoken::{resolve_bearer_session, PersonalScope};
+use crate::{resolve_session_id, QxApiToken, SharedQxState};
+
+/// Event data which can be set public or private per event
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResourceKind {
+    Files,
+    Runs,
+    Changes,
+}
+impl ResourceKind {
+    fn is_public(&self, event: &EventRecord) -> bool {
+        match self {
+            ResourceKind::Files => event.files_public,
+            ResourceKind::Runs => event.runs_public,
+            ResourceKind::Changes => event.changes_public,
+        }
+    }
+}
+
+pub trait EventResource: Send + Sync + 'static {
+    const KIND: ResourceKind;
+}
+pub struct Files;
+impl EventResource for Files {
+    const KIND: ResourceKind = ResourceKind::Files;
+}
+pub struct Runs;
+impl EventResource for Runs {
+    const KIND: ResourceKind = ResourceKind::Runs;
+}
+pub struct Changes;
+impl EventResource for Changes {
+    const KIND: ResourceKind = ResourceKind::Changes;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Access {
+    Read,
+    Write,
+}
+impl Access {
+    fn api_scope(&self) -> ApiScope {
+        match self {
+            Access::Read => ApiScope::Read,
+            Access::Write => ApiScope::QeSync,
+        }
+    }
+    fn personal_scope(&self) -> PersonalScope {
+        match self {
+            Access::Read => PersonalScope::Read,
+            Access::Write => PersonalScope::Manage,
+        }
+    }
+    fn is_granted_to(&self, role: &EventRole) -> bool {
+        match self {
+            Access::Read => true,
+            Access::Write => role.can_edit_event(),
+        }
+    }
+}
+
+/// Event ID from request path `.../event/<event_id>/...`
+fn event_id_from_path(request: &request::Request<'_>) -> Option<EventId> {
+    request.uri().path().segments()
+        .skip_while(|segment| *segment != "event")
+        .nth(1)
+        .and_then(|segment| segment.parse::<EventId>().ok())
+}
+
+/// Resolve if caller may access event resource, public resources can be read by anyone,
+/// otherwise the caller must present API token of the event with sufficient scope or be a member of the event
+async fn resolve_event_access(request: &request::Request<'_>, resource: ResourceKind, access: Access) -> request::Outcome<Actor, ()> {
+    let Some(event_id) = event_id_from_path(request) else {
+        return request::Outcome::Forward(Status::NotFound);
+    };
+    let (Some(state), Some(db)) = (
+        request.guard::<&State<SharedQxState>>().await.succeeded(),
+        request.guard::<&State<DbPool>>().await.succeeded(),
+    ) else {
+        return request::Outcome::Error((Status::InternalServerError, ()));
+    };
+    let Ok(event) = load_event(event_id, db).await else {
+        return request::Outcome::Error((Status::NotFound, ()));
+    };
+    if access == Access::Read && resource.is_public(&event) {
+        return request::Outcome::Success(Actor::Anonymous);
+    }
+    match request.guard::<QxApiToken>().await {
+        request::Outcome::Success(api_token) => {
+            return if api_token.event_id == event_id && api_token.scopes.contains(access.api_scope()) {
+                request::Outcome::Success(Actor::from(&api_token))
+            } else {
+                request::Outcome::Error((Status::Forbidden, ()))
+            }
+        }
+        request::Outcome::Error(e) => return request::Outcome::Error(e),
+        request::Outcome::Forward(_) => {}
+    }
+    let Some(session_id) = resolve_session_id(request, access.personal_scope()).await else {
+        let status = resolve_bearer_session(request, access.personal_scope()).await.err().unwrap_or(Status::Unauthorized);
+        return request::Outcome::Error((status, ()));
+    };
+    let user = match user_info_opt(Some(&session_id), state).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return request::Outcome::Error((Status::Unauthorized, ())),
+        Err(e) => {
+            error!("Get user info error: {e}");
+            return request::Outcome::Error((Status::InternalServerError, ()));
+        }
+    };
+    match load_event_role(&event, Some(&user), db).await {
+        Ok(Some(role)) if access.is_granted_to(&role) => request::Outcome::Success(Actor::from(&user)),
+        Ok(_) => request::Outcome::Error((Status::Forbidden, ())),
+        Err(e) => {
+            error!("Load event role error: {e}");
+            request::Outcome::Error((Status::InternalServerError, ()))
+        }
+    }
+}
+
+/// Caller may read event resource `R`
+pub struct EventRead<R: EventResource>(PhantomData<R>);
+
+#[rocket::async_trait]
+impl<'r, R: EventResource> request::FromRequest<'r> for EventRead<R> {
+    type Error = ();
+    async fn from_request(request: &'r request::Request<'_>) -> request::Outcome<Self, ()> {
+        resolve_event_access(request, R::KIND, Access::Read).await.map(|_| Self(PhantomData))
+    }
+}
+
+/// Caller may modify event resource `R`
+pub struct EventWrite<R: EventResource> {
+    pub actor: Actor,
+    resource: PhantomData<R>,
+}
+
+#[rocket::async_trait]
+impl<'r, R: EventResource> request::FromRequest<'r> for EventWrite<R> {
+    type Error = ();
+    async fn from_request(request: &'r request::Request<'_>) -> request::Outcome<Self, ()> {
+        resolve_event_access(request, R::KIND, Access::Write).await.map(|actor| Self { actor, resource: PhantomData })
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::response::status::Custom;
+use rocket::serde::Serialize;
+use rocket::{request, Build, Request, Response, Rocket, State};
+use rocket_dyn_templates::{context, Template};
+use sqlx::FromRow;
+use crate::audit::{audit, Actor, EVENT_OWNER};
+use crate::auth::UserInfo;
+use crate::db::DbPool;
+use crate::event::{event_drop, get_user_info, load_event_info, EventId, EventRecord};
+use crate::personaltoken::PersonalScope;
+use crate::qxdatetime::QxDateTime;
+use crate::util::{anyhow_to_custom_error, sqlx_to_custom_error};
+use crate::{resolve_session_id, QxSessionId, SharedQxState};
+
+const RECENT_ERRORS_CAPACITY: usize = 100;
+// error response body is truncated to this length
+const ERROR_MESSAGE_MAX_LEN: usize = 500;
+
+/// Logged-in user listed in `admins` config
+pub struct QxAdmin(UserInfo);
+
+#[rocket::async_trait]
+impl<'r> request::FromRequest<'r> for QxAdmin {
+    type Error = ();
+    async fn from_request(request: &'r request::Request<'_>) -> request::Outcome<Self, ()> {
+        // error instead of forward, static files route would answer 404 otherwise
+        let Some(session_id) = resolve_session_id(request, PersonalScope::Manage).await else {
+            return request::Outcome::Error((Status::Unauthorized, ()));
+        };
+        let Some(state) = request.guard::<&State<SharedQxState>>().await.succeeded() else {
+            return request::Outcome::Error((Status::InternalServerError, ()));
+        };
+        let Ok(Some(user)) = get_user_info(&session_id, state).await else {
+            return request::Outcome::Error((Status::Unauthorized, ()));
+        };
+        if !state.read().await.app_config.is_admin(&user.email) {
+            warn!("Admin access denied, user: {}", user.email);
+            return request::Outcome::Error((Status::Forbidden, ()));
+        }
+        request::Outcome::Success(QxAdmin(user))
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ErrorRecord {
+    time: QxDateTime,
+    method: String,
+    uri: String,
+    status: u16,
+    message: String,
+}
+
+/// Ring buffer of recent server error responses
+#[derive(Default)]
+pub struct RecentErrors(Mutex<VecDeque<ErrorRecord>>);
+impl RecentErrors {
+    fn push(&self, error: ErrorRecord) {
+        let mut errors = self.0.lock().expect("recent errors lock");
+        if errors.len() >= RECENT_ERRORS_CAPACITY {
+            errors.pop_front();
+        }
+        errors.push_back(error);
+    }
+    /// Errors, the most recent first
+    fn list(&self) -> Vec<ErrorRecord> {
+        self.0.lock().expect("recent errors lock").iter().rev().cloned().collect()
+    }
+}
+
+struct RecentErrorsFairing;
+
+#[rocket::async_trait]
+impl Fairing for RecentErrorsFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Recent errors recorder",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.status().class() != rocket::http::StatusClass::ServerError {
+            return;
+        }
+        let Some(recent_errors) = request.rocket().state::<RecentErrors>() else {
+            return;
+        };
+        let mut message = response.body_mut().to_string().await.unwrap_or_default();
+        response.set_sized_body(message.len(), std::io::Cursor::new(message.clone()));
+        if let Some((idx, _)) = message.char_indices().nth(ERROR_MESSAGE_MAX_LEN) {
+            message.truncate(idx);
+        }
+        recent_errors.push(ErrorRecord {
+            time: QxDateTime::now().trimmed_to_sec(),
+            method: request.method().to_string(),
+            uri: request.uri().to_string(),
+            status: response.status().code,
+            message,
+        });
+    }
+}
+
+#[derive(Serialize, FromRow, Debug)]
+struct SessionView {
+    // session ID is not shown to admin, row ID is used to force logout
+    rowid: i64,
+    user_name: String,
+    user_email: String,
+    created: QxDateTime,
+    last_seen: QxDateTime,
+}
+
+#[derive(Serialize, Debug)]
+struct OpenEventView {
+    event_id: EventId,
+    hit_count: u64,
+}
+
+#[get("/admin")]
+async fn get_admin(admin: QxAdmin, recent_errors: &State<RecentErrors>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    let events: Vec<EventRecord> = sqlx::query_as("SELECT * FROM events ORDER BY id")
+        .fetch_all(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    let sessions: Vec<SessionView> = sqlx::query_as("SELECT rowid, user_name, user_email, created, last_seen FROM sessions ORDER BY last_seen DESC")
+        .fetch_all(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    let mut open_events: Vec<OpenEventView> = state.read().await.open_events.iter()
+        .map(|(event_id, open_event)| OpenEventView {
+            event_id: *event_id,
+            hit_count: open_event.hit_count.load(std::sync::atomic::Ordering::Relaxed),
+        })
+        .collect();
+    open_events.sort_by_key(|oe| oe.event_id);
+    Ok(Template::render("admin", context! {
+        user: admin.0,
+        events,
+        sessions,
+        open_events,
+        errors: recent_errors.list(),
+    }))
+}
+
+#[derive(Debug, FromForm)]
+struct SessionFormValues {
+    rowid: i64,
+}
+#[post("/admin/sessions/logout", data = "<form>")]
+async fn force_logout(admin: QxAdmin, form: Form<SessionFormValues>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let session: Option<(String, String)> = sqlx::query_as("DELETE FROM sessions WHERE rowid=? RETURNING id, user_email")
+        .bind(form.rowid)
+        .fetch_optional(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    if let Some((session_id, user_email)) = session {
+        state.write().await.sessions.remove(&QxSessionId(session_id));
+        info!("Admin {} logged out user: {user_email}", admin.0.email);
+    }
+    Ok(Redirect::to("/admin"))
+}
+
+#[derive(Debug, FromForm)]
+struct OwnerFormValues<'v> {
+    event_id: EventId,
+    #[field(validate = contains('@'))]
+    owner: &'v str,
+}
+#[post("/admin/events/owner", data = "<form>")]
+async fn set_event_owner(admin: QxAdmin, form: Form<OwnerFormValues<'_>>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let event = load_event_info(form.event_id, gdb).await?;
+    let owner = form.owner.trim();
+    let mut tx = gdb.0.begin().await.map_err(sqlx_to_custom_error)?;
+    sqlx::query("UPDATE events SET owner=? WHERE id=?")
+        .bind(owner)
+        .bind(event.id)
+        .execute(&mut *tx)
+        .await.map_err(sqlx_to_custom_error)?;
+    audit(event.id, &Actor::from(&admin.0), EVENT_OWNER, &event.name, Some(event.owner.clone()), Some(owner.to_string()), &mut *tx).await
+        .map_err(anyhow_to_custom_error)?;
+    tx.commit().await.map_err(sqlx_to_custom_error)?;
+    info!("Admin {} changed owner of event id: {} from: {} to: {owner}", admin.0.email, event.id, event.owner);
+    Ok(Redirect::to("/admin"))
+}
+
+#[derive(Debug, FromForm)]
+struct EventFormValues {
+    event_id: EventId,
+}
+#[post("/admin/events/delete", data = "<form>")]
+async fn delete_event(admin: QxAdmin, form: Form<EventFormValues>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let event = load_event_info(form.event_id, gdb).await?;
+    event_drop(event.id, &Actor::from(&admin.0), state, gdb).await.map_err(anyhow_to_custom_error)?;
+    info!("Admin {} deleted event id: {}, name: {}, owner: {}", admin.0.email, event.id, event.name, event.owner);
+    Ok(Redirect::to("/admin"))
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket
+        .manage(RecentErrors::default())
+        .attach(RecentErrorsFairing)
+        .mount("/", routes![
+            get_admin,
+            force_logout,
+            set_event_owner,
+            delete_event,
+        ])
+}
+
+#[test]
+fn test_recent_errors() {
+    let recent_errors = RecentErrors::default();
+    for n in 0..RECENT_ERRORS_CAPACITY + 5 {
+        recent_errors.push(ErrorRecord {
+            time: QxDateTime::now(),
+            method: "GET".to_string(),
+            uri: format!("/{n}"),
+            status: 500,
+            message: "".to_string(),
+        });
+    }
+    let errors = recent_errors.list();
+    assert_eq!(errors.len(), RECENT_ERRORS_CAPACITY);
+    assert_eq!(errors.first().unwrap().uri, format!("/{}", RECENT_ERRORS_CAPACITY + 4));
+    assert_eq!(errors.last().unwrap().uri, "/5");
+}
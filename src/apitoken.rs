@@ -0,0 +1,285 @@
+use std::fmt::{Display, Formatter};
+use chrono::TimeDelta;
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::response::status::Custom;
+use rocket::{Build, Rocket, State};
+use rocket_dyn_templates::{context, Template};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Encode, FromRow, Sqlite, SqlitePool};
+use sqlx::sqlite::SqliteArgumentValue;
+use crate::auth::generate_random_string;
+use crate::db::DbPool;
+use crate::event::{load_event_info, user_info, EventId, EventRecord};
+use crate::members::{require_event_role, EventRole};
+use crate::qxdatetime::QxDateTime;
+use crate::util::{anyhow_to_custom_error, create_qrc, sqlx_to_anyhow, sqlx_to_custom_error};
+use crate::{impl_sqlx_json_text_type_encode_decode, QxApiToken, QxSessionId, SharedQxState};
+
+const API_TOKEN_LEN: usize = 40;
+// first characters of token are stored in plaintext to find token hash candidates
+const API_TOKEN_PREFIX_LEN: usize = 8;
+// last_used column is not updated more often than this
+pub(crate) const LAST_USED_UPDATE_INTERVAL_SEC: i64 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiScope {
+    /// read event data
+    Read,
+    /// upload OCheckList change sets only
+    OcUpload,
+    /// full QuickEvent synchronization, implies all other scopes
+    QeSync,
+}
+impl ApiScope {
+    pub const ALL: [ApiScope; 3] = [ApiScope::Read, ApiScope::OcUpload, ApiScope::QeSync];
+    fn try_from_str(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scope| scope.to_string() == s)
+    }
+}
+impl Display for ApiScope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiScope::Read => f.write_str("read"),
+            ApiScope::OcUpload => f.write_str("oc-upload"),
+            ApiScope::QeSync => f.write_str("qe-sync"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default, Debug)]
+pub struct ApiScopes(pub Vec<ApiScope>);
+impl_sqlx_json_text_type_encode_decode!(ApiScopes);
+impl ApiScopes {
+    pub fn contains(&self, scope: ApiScope) -> bool {
+        self.0.contains(&scope) || self.0.contains(&ApiScope::QeSync)
+    }
+}
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct ApiTokenRecord {
+    pub id: i64,
+    pub event_id: EventId,
+    pub name: String,
+    pub token_prefix: String,
+    #[serde(skip)]
+    token_salt: Option<String>,
+    #[serde(skip)]
+    token_hash: Option<String>,
+    pub scopes: ApiScopes,
+    pub created: QxDateTime,
+    pub expires: Option<QxDateTime>,
+    pub last_used: Option<QxDateTime>,
+    pub revoked: bool,
+}
+impl ApiTokenRecord {
+    fn is_valid(&self, now: &QxDateTime) -> bool {
+        !self.revoked && self.expires.is_none_or(|expires| expires.0 > now.0)
+    }
+    fn matches(&self, token: &str) -> bool {
+        match (&self.token_salt, &self.token_hash) {
+            (Some(salt), Some(hash)) => &hash_api_token(token, salt) == hash,
+            _ => false,
+        }
+    }
+}
+
+pub(crate) fn token_prefix(token: &str) -> String {
+    token.chars().take(API_TOKEN_PREFIX_LEN).collect()
+}
+pub(crate) fn hash_api_token(token: &str, salt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(token.as_bytes());
+    hex::encode(hasher.finalize())
+}
+pub(crate) fn generate_salt() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+pub fn generate_api_token() -> String {
+    generate_random_string(API_TOKEN_LEN)
+}
+
+pub async fn create_api_token(
+    event_id: EventId,
+    name: &str,
+    token: &str,
+    scopes: &ApiScopes,
+    expires: Option<QxDateTime>,
+    db: &State<DbPool>,
+) -> anyhow::Result<i64> {
+    let salt = generate_salt();
+    let id: (i64, ) = sqlx::query_as("INSERT INTO api_tokens (event_id, name, token_prefix, token_salt, token_hash, scopes, created, expires) VALUES (?, ?, ?, ?, ?, ?, ?, ?) RETURNING id")
+        .bind(event_id)
+        .bind(name)
+        .bind(token_prefix(token))
+        .bind(&salt)
+        .bind(hash_api_token(token, &salt))
+        .bind(scopes)
+        .bind(QxDateTime::now().trimmed_to_sec())
+        .bind(expires)
+        .fetch_one(&db.0)
+        .await.map_err(sqlx_to_anyhow)?;
+    Ok(id.0)
+}
+
+/// Resolve token sent in `qx-api-token` header, revoked and expired tokens are rejected
+pub async fn resolve_api_token(token: &str, db: &State<DbPool>) -> anyhow::Result<Option<QxApiToken>> {
+    let candidates: Vec<ApiTokenRecord> = sqlx::query_as("SELECT * FROM api_tokens WHERE token_prefix=?")
+        .bind(token_prefix(token))
+        .fetch_all(&db.0)
+        .await.map_err(sqlx_to_anyhow)?;
+    let now = QxDateTime::now();
+    let Some(rec) = candidates.into_iter().find(|rec| rec.matches(token) && rec.is_valid(&now)) else {
+        return Ok(None);
+    };
+    if rec.last_used.is_none_or(|last_used| now.0.signed_duration_since(last_used.0).num_seconds() > LAST_USED_UPDATE_INTERVAL_SEC) {
+        sqlx::query("UPDATE api_tokens SET last_used=? WHERE id=?")
+            .bind(now.trimmed_to_sec())
+            .bind(rec.id)
+            .execute(&db.0)
+            .await.map_err(sqlx_to_anyhow)?;
+    }
+    Ok(Some(QxApiToken {
+        event_id: rec.event_id,
+        name: rec.name,
+        scopes: rec.scopes,
+    }))
+}
+
+/// Hash tokens stored in plaintext by older server versions, called after DB migrations
+pub(crate) async fn hash_plaintext_api_tokens(pool: &SqlitePool) -> anyhow::Result<()> {
+    let tokens: Vec<(i64, String)> = sqlx::query_as("SELECT id, token FROM api_tokens WHERE token IS NOT NULL")
+        .fetch_all(pool)
+        .await.map_err(sqlx_to_anyhow)?;
+    for (id, token) in &tokens {
+        let salt = generate_salt();
+        sqlx::query("UPDATE api_tokens SET token_salt=?, token_hash=?, token=NULL WHERE id=?")
+            .bind(&salt)
+            .bind(hash_api_token(token, &salt))
+            .bind(id)
+            .execute(pool)
+            .await.map_err(sqlx_to_anyhow)?;
+    }
+    if !tokens.is_empty() {
+        info!("{} plaintext API tokens hashed", tokens.len());
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ApiTokenView {
+    #[serde(flatten)]
+    token: ApiTokenRecord,
+    is_valid: bool,
+}
+#[get("/event/<event_id>/tokens")]
+async fn get_api_tokens(event_id: EventId, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_manage_event, gdb).await?;
+    let tokens: Vec<ApiTokenRecord> = sqlx::query_as("SELECT * FROM api_tokens WHERE event_id=? ORDER BY id")
+        .bind(event_id)
+        .fetch_all(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    let now = QxDateTime::now();
+    let tokens = tokens.into_iter()
+        .map(|token| ApiTokenView { is_valid: token.is_valid(&now), token })
+        .collect::<Vec<_>>();
+    Ok(Template::render("tokens", context! {
+        user,
+        event,
+        tokens,
+        scopes: ApiScope::ALL,
+    }))
+}
+
+#[derive(Debug, FromForm)]
+struct ApiTokenFormValues<'v> {
+    #[field(validate = len(1..))]
+    name: &'v str,
+    scopes: Vec<&'v str>,
+    expires_days: Option<i64>,
+}
+/// Render page with newly created token, this is the only time the token is shown in plaintext
+pub fn render_created_api_token(event: &EventRecord, name: &str, token: &str) -> Result<Template, Custom<String>> {
+    let qrc_img_data = create_qrc(token.as_bytes()).map_err(anyhow_to_custom_error)?;
+    Ok(Template::render("token-created", context! {
+        event,
+        name,
+        token,
+        qrc_img_data,
+    }))
+}
+
+#[post("/event/<event_id>/tokens/create", data = "<form>")]
+async fn post_api_token(event_id: EventId, form: Form<ApiTokenFormValues<'_>>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_manage_event, gdb).await?;
+    let scopes = form.scopes.iter()
+        .map(|s| ApiScope::try_from_str(s).ok_or(Custom(Status::BadRequest, format!("Invalid API token scope: {s}"))))
+        .collect::<Result<Vec<_>, _>>()?;
+    if scopes.is_empty() {
+        return Err(Custom(Status::BadRequest, "At least one API token scope must be selected".to_string()));
+    }
+    let expires = form.expires_days
+        .and_then(|days| QxDateTime::now().trimmed_to_sec().0.checked_add_signed(TimeDelta::days(days)))
+        .map(QxDateTime);
+    let token = generate_api_token();
+    create_api_token(event_id, form.name, &token, &ApiScopes(scopes), expires, gdb).await.map_err(anyhow_to_custom_error)?;
+    render_created_api_token(&event, form.name, &token)
+}
+
+#[derive(Debug, FromForm)]
+struct RevokeFormValues {
+    id: i64,
+}
+#[post("/event/<event_id>/tokens/revoke", data = "<form>")]
+async fn revoke_api_token(event_id: EventId, form: Form<RevokeFormValues>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_manage_event, gdb).await?;
+    sqlx::query("UPDATE api_tokens SET revoked=1 WHERE id=? AND event_id=?")
+        .bind(form.id)
+        .bind(event_id)
+        .execute(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    Ok(Redirect::to(format!("/event/{event_id}/tokens")))
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket.mount("/", routes![
+        get_api_tokens,
+        post_api_token,
+        revoke_api_token,
+    ])
+}
+
+#[test]
+fn test_api_scopes() {
+    let scopes = ApiScopes(vec![ApiScope::OcUpload]);
+    assert!(scopes.contains(ApiScope::OcUpload));
+    assert!(!scopes.contains(ApiScope::Read));
+    let scopes = ApiScopes(vec![ApiScope::QeSync]);
+    assert!(ApiScope::ALL.into_iter().all(|scope| scopes.contains(scope)));
+    for scope in ApiScope::ALL {
+        assert_eq!(ApiScope::try_from_str(&scope.to_string()), Some(scope));
+    }
+    assert_eq!(serde_json::to_string(&ApiScopes(vec![ApiScope::Read, ApiScope::OcUpload])).unwrap(), r#"["read","oc-upload"]"#);
+}
+
+#[test]
+fn test_hash_api_token() {
+    let token = generate_api_token();
+    assert_eq!(token_prefix(&token).len(), API_TOKEN_PREFIX_LEN);
+    let salt = generate_salt();
+    let hash = hash_api_token(&token, &salt);
+    assert_eq!(hash, hash_api_token(&token, &salt));
+    assert_ne!(hash, hash_api_token(&token, &generate_salt()));
+    assert_ne!(hash, hash_api_token(&generate_api_token(), &salt));
+}
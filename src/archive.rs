@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+use anyhow::anyhow;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::{Header, Status};
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{Build, Rocket, State};
+use sqlx::{Connection, FromRow, SqlitePool};
+use crate::apitoken::{create_api_token, generate_api_token, ApiScopes};
+use crate::audit::Actor;
+use crate::auth::{generate_random_string, UserInfo};
+use crate::db::{event_id_to_schema_name, get_event_db, DbPool};
+use crate::event::{event_discard, load_event_info, save_event, user_info, EventId, EventRecord};
+use crate::members::{require_event_role, EventRole};
+use crate::qxdatetime::QxDateTime;
+use crate::util::{anyhow_to_custom_error, sqlx_to_anyhow, sqlx_to_custom_error};
+use crate::{QxSessionId, SharedQxState};
+
+// increment when archive layout changes in incompatible way
+const ARCHIVE_FORMAT_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+const EVENT_FILE: &str = "event.json";
+const API_TOKENS_FILE: &str = "api-tokens.json";
+// archive is read to memory, limit of sum of its uncompressed file sizes
+const MAX_UNCOMPRESSED_SIZE: u64 = 512 * 1024 * 1024;
+// tables copied from event DB on import, in this order
+const EVENT_DB_TABLES: [&str; 6] = ["files", "classes", "runs", "changes", "entry_classes", "entries"];
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ArchiveManifest {
+    format_version: u32,
+    server_version: String,
+    exported: QxDateTime,
+    event_id: EventId,
+    db_file: String,
+}
+
+/// API token is archived as salted hash, so that QuickEvent can keep using it on the server the event is moved to
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+struct ArchivedApiToken {
+    name: String,
+    token_prefix: String,
+    token_salt: String,
+    token_hash: String,
+    scopes: ApiScopes,
+    created: QxDateTime,
+    expires: Option<QxDateTime>,
+}
+
+/// File in temp dir removed when dropped
+struct TempFile(PathBuf);
+impl TempFile {
+    fn new(suffix: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("qx-{}{suffix}", generate_random_string(16))))
+    }
+    fn path_str(&self) -> anyhow::Result<&str> {
+        self.0.to_str().ok_or(anyhow!("Invalid temp file path: {:?}", self.0))
+    }
+}
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if self.0.exists() && let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("Remove temp file {:?} error: {e}", self.0);
+        }
+    }
+}
+
+fn write_archive(entries: &[(&str, &[u8])]) -> anyhow::Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let mtime = QxDateTime::now().0.timestamp().max(0) as u64;
+    for (name, data) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        builder.append_data(&mut header, name, *data)?;
+    }
+    Ok(builder.into_inner()?.finish()?)
+}
+
+/// Read archive files, reading stops on unexpected or duplicate file and when files are larger than `max_size` in total
+fn read_archive(data: &[u8], max_size: u64, is_expected: impl Fn(&str) -> bool) -> anyhow::Result<HashMap<String, Vec<u8>>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(data));
+    let mut entries = HashMap::new();
+    let mut total_size = 0;
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        if !is_expected(&name) || entries.contains_key(&name) {
+            return Err(anyhow!("Unexpected archive file {name}"));
+        }
+        let mut data = vec![];
+        entry.take(max_size - total_size + 1).read_to_end(&mut data)?;
+        total_size += data.len() as u64;
+        if total_size > max_size {
+            return Err(anyhow!("Archive is larger than {max_size} bytes uncompressed"));
+        }
+        entries.insert(name, data);
+    }
+    Ok(entries)
+}
+fn is_event_db_file(name: &str) -> bool {
+    name.strip_prefix("ev")
+        .and_then(|name| name.strip_suffix(".sqlite"))
+        .is_some_and(|id| id.parse::<EventId>().is_ok())
+}
+
+/// Consistent copy of event DB, it is safe to take it while the DB is in use
+async fn snapshot_event_db(edb: &SqlitePool) -> anyhow::Result<Vec<u8>> {
+    let file = TempFile::new(".sqlite");
+    sqlx::query("VACUUM INTO ?")
+        .bind(file.path_str()?)
+        .execute(edb)
+        .await.map_err(sqlx_to_anyhow)?;
+    Ok(std::fs::read(&file.0)?)
+}
+
+/// Replace content of event DB tables by tables of archived DB, only columns known to both DBs are copied
+async fn restore_event_db(db_data: &[u8], edb: &SqlitePool) -> anyhow::Result<()> {
+    let file = TempFile::new(".sqlite");
+    std::fs::write(&file.0, db_data)?;
+    // attached DB is visible to single connection only
+    let mut conn = edb.acquire().await?;
+    sqlx::query("ATTACH DATABASE ? AS archive")
+        .bind(file.path_str()?)
+        .execute(&mut *conn)
+        .await.map_err(sqlx_to_anyhow)?;
+    let res = async {
+        let mut tx = conn.begin().await?;
+        for table in EVENT_DB_TABLES {
+            let columns: Vec<(String,)> = sqlx::query_as("SELECT main_cols.name FROM pragma_table_info(?, 'main') AS main_cols
+                         JOIN pragma_table_info(?, 'archive') AS archive_cols ON archive_cols.name=main_cols.name")
+                .bind(table)
+                .bind(table)
+                .fetch_all(&mut *tx)
+                .await.map_err(sqlx_to_anyhow)?;
+            if columns.is_empty() {
+                continue;
+            }
+            let columns = columns.into_iter().map(|c| format!("\"{}\"", c.0)).collect::<Vec<_>>().join(", ");
+            sqlx::query(&format!("DELETE FROM main.{table}"))
+                .execute(&mut *tx)
+                .await.map_err(sqlx_to_anyhow)?;
+            sqlx::query(&format!("INSERT INTO main.{table} ({columns}) SELECT {columns} FROM archive.{table}"))
+                .execute(&mut *tx)
+                .await.map_err(sqlx_to_anyhow)?;
+        }
+        tx.commit().await?;
+        anyhow::Ok(())
+    }.await;
+    sqlx::query("DETACH DATABASE archive")
+        .execute(&mut *conn)
+        .await.map_err(sqlx_to_anyhow)?;
+    res
+}
+
+fn build_event_archive(event: &EventRecord, api_tokens: &[ArchivedApiToken], db_data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let manifest = ArchiveManifest {
+        format_version: ARCHIVE_FORMAT_VERSION,
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        exported: QxDateTime::now().trimmed_to_sec(),
+        event_id: event.id,
+        db_file: format!("{}.sqlite", event_id_to_schema_name(event.id)),
+    };
+    let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+    let event_json = serde_json::to_vec_pretty(event)?;
+    let api_tokens_json = serde_json::to_vec_pretty(api_tokens)?;
+    write_archive(&[
+        (MANIFEST_FILE, manifest_json.as_slice()),
+        (EVENT_FILE, event_json.as_slice()),
+        (API_TOKENS_FILE, api_tokens_json.as_slice()),
+        (manifest.db_file.as_str(), db_data),
+    ])
+}
+
+struct EventArchive {
+    event: EventRecord,
+    api_tokens: Vec<ArchivedApiToken>,
+    db_data: Vec<u8>,
+}
+fn parse_event_archive(data: &[u8]) -> anyhow::Result<EventArchive> {
+    let mut entries = read_archive(data, MAX_UNCOMPRESSED_SIZE, |name| {
+        [MANIFEST_FILE, EVENT_FILE, API_TOKENS_FILE].contains(&name) || is_event_db_file(name)
+    })?;
+    let mut take = |name: &str| entries.remove(name).ok_or(anyhow!("Archive file {name} is missing"));
+    let manifest: ArchiveManifest = serde_json::from_slice(&take(MANIFEST_FILE)?)?;
+    if manifest.format_version != ARCHIVE_FORMAT_VERSION {
+        return Err(anyhow!("Unsupported archive format version: {}", manifest.format_version));
+    }
+    Ok(EventArchive {
+        event: serde_json::from_slice(&take(EVENT_FILE)?)?,
+        api_tokens: serde_json::from_slice(&take(API_TOKENS_FILE)?)?,
+        db_data: take(&manifest.db_file)?,
+    })
+}
+
+#[derive(Responder)]
+#[response(content_type = "application/gzip")]
+struct ArchiveResponse {
+    data: Vec<u8>,
+    disposition: Header<'static>,
+}
+
+#[get("/event/<event_id>/export")]
+async fn export_event(event_id: EventId, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<ArchiveResponse, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_manage_event, gdb).await?;
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    let db_data = snapshot_event_db(&edb).await.map_err(anyhow_to_custom_error)?;
+    let api_tokens: Vec<ArchivedApiToken> = sqlx::query_as("SELECT * FROM api_tokens
+                 WHERE event_id=? AND revoked=0 AND token_salt IS NOT NULL AND token_hash IS NOT NULL")
+        .bind(event_id)
+        .fetch_all(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    let data = build_event_archive(&event, &api_tokens, &db_data).map_err(anyhow_to_custom_error)?;
+    info!("User {} exported event id: {event_id}, archive size: {}", user.email, data.len());
+    Ok(ArchiveResponse {
+        data,
+        disposition: Header::new("Content-Disposition", format!("attachment; filename=\"{}.tar.gz\"", event_id_to_schema_name(event_id))),
+    })
+}
+
+/// What to do when archived API token is already active on this server,
+/// it happens when the event is imported back to the server it was exported from
+#[derive(FromFormField, Clone, Copy, PartialEq, Eq, Default, Debug)]
+enum TokenConflict {
+    /// reject the import
+    #[default]
+    Fail,
+    /// revoke the existing token, the token then belongs to the imported event
+    Move,
+    /// generate new token for the imported event instead
+    New,
+}
+
+#[derive(Serialize, Debug)]
+struct NewApiToken {
+    name: String,
+    token: String,
+}
+#[derive(Serialize, Debug)]
+struct ImportedEvent {
+    event: EventRecord,
+    // generated tokens are shown only once
+    new_api_tokens: Vec<NewApiToken>,
+}
+
+/// Active token with the same hash as archived one
+async fn find_conflicting_token(token: &ArchivedApiToken, gdb: &State<DbPool>) -> anyhow::Result<Option<(i64, EventId)>> {
+    let conflict = sqlx::query_as("SELECT id, event_id FROM api_tokens WHERE token_prefix=? AND token_salt=? AND token_hash=? AND revoked=0")
+        .bind(&token.token_prefix)
+        .bind(&token.token_salt)
+        .bind(&token.token_hash)
+        .fetch_optional(&gdb.0)
+        .await.map_err(sqlx_to_anyhow)?;
+    Ok(conflict)
+}
+
+async fn import_api_tokens(event_id: EventId, api_tokens: Vec<ArchivedApiToken>, token_conflict: TokenConflict, gdb: &State<DbPool>) -> anyhow::Result<Vec<NewApiToken>> {
+    let mut new_api_tokens = vec![];
+    for token in api_tokens {
+        if let Some((conflicting_id, _)) = find_conflicting_token(&token, gdb).await? {
+            match token_conflict {
+                TokenConflict::Fail => return Err(anyhow!("API token {} is already in use", token.name)),
+                TokenConflict::Move => {
+                    sqlx::query("UPDATE api_tokens SET revoked=1 WHERE id=?")
+                        .bind(conflicting_id)
+                        .execute(&gdb.0)
+                        .await.map_err(sqlx_to_anyhow)?;
+                }
+                TokenConflict::New => {
+                    let new_token = generate_api_token();
+                    create_api_token(event_id, &token.name, &new_token, &token.scopes, token.expires, gdb).await?;
+                    new_api_tokens.push(NewApiToken { name: token.name, token: new_token });
+                    continue;
+                }
+            }
+        }
+        sqlx::query("INSERT INTO api_tokens (event_id, name, token_prefix, token_salt, token_hash, scopes, created, expires) VALUES (?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(event_id)
+            .bind(&token.name)
+            .bind(&token.token_prefix)
+            .bind(&token.token_salt)
+            .bind(&token.token_hash)
+            .bind(&token.scopes)
+            .bind(token.created)
+            .bind(token.expires)
+            .execute(&gdb.0)
+            .await.map_err(sqlx_to_anyhow)?;
+    }
+    Ok(new_api_tokens)
+}
+
+/// Check that token conflicts can be resolved before anything is imported
+async fn check_token_conflicts(api_tokens: &[ArchivedApiToken], token_conflict: TokenConflict, user: &UserInfo, gdb: &State<DbPool>) -> Result<(), Custom<String>> {
+    for token in api_tokens {
+        let Some((_, conflicting_event_id)) = find_conflicting_token(token, gdb).await.map_err(anyhow_to_custom_error)? else {
+            continue;
+        };
+        match token_conflict {
+            TokenConflict::Fail => {
+                return Err(Custom(Status::Conflict, format!("API token '{}' is already used by event id: {conflicting_event_id}", token.name)));
+            }
+            TokenConflict::Move => {
+                // token can be taken away from event managed by the user only
+                let event = load_event_info(conflicting_event_id, gdb).await?;
+                require_event_role(&event, user, EventRole::can_manage_event, gdb).await?;
+            }
+            TokenConflict::New => {}
+        }
+    }
+    Ok(())
+}
+
+#[post("/api/event/import?<token_conflict>", data = "<data>")]
+async fn import_event(token_conflict: Option<TokenConflict>, data: Data<'_>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Json<ImportedEvent>, Custom<String>> {
+    let user = user_info(&session_id, state).await?;
+    let token_conflict = token_conflict.unwrap_or_default();
+    let data = data.open(200.mebibytes()).into_bytes().await.map_err(|e| Custom(Status::PayloadTooLarge, e.to_string()))?.into_inner();
+    let archive = parse_event_archive(&data).map_err(|e| Custom(Status::UnprocessableEntity, format!("Invalid event archive: {e}")))?;
+    check_token_conflicts(&archive.api_tokens, token_conflict, &user, gdb).await?;
+    let actor = Actor::from(&user);
+    let source_event_id = archive.event.id;
+    // importing user becomes owner of the new event
+    let event = EventRecord {
+        id: 0,
+        owner: user.email.clone(),
+        ..archive.event
+    };
+    let event_id = save_event(&event, &actor, gdb).await.map_err(anyhow_to_custom_error)?;
+    let res = async {
+        let edb = get_event_db(event_id, state).await?;
+        restore_event_db(&archive.db_data, &edb).await?;
+        import_api_tokens(event_id, archive.api_tokens, token_conflict, gdb).await
+    }.await;
+    let new_api_tokens = match res {
+        Ok(new_api_tokens) => new_api_tokens,
+        Err(e) => {
+            // do not leave half imported event
+            if let Err(e) = event_discard(event_id, state, gdb).await {
+                error!("Discard partially imported event id: {event_id} error: {e}");
+            }
+            return Err(anyhow_to_custom_error(e));
+        }
+    };
+    info!("User {} imported event id: {source_event_id} as id: {event_id}", user.email);
+    let event = load_event_info(event_id, gdb).await?;
+    Ok(Json(ImportedEvent { event, new_api_tokens }))
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket.mount("/", routes![
+            export_event,
+            import_event,
+        ])
+}
+
+#[test]
+fn test_event_archive() {
+    let event = EventRecord::new("john@doe.com");
+    let api_tokens = vec![ArchivedApiToken {
+        name: "Default".to_string(),
+        token_prefix: "abcdefgh".to_string(),
+        token_salt: "salt".to_string(),
+        token_hash: "hash".to_string(),
+        scopes: ApiScopes(vec![]),
+        created: QxDateTime::now().trimmed_to_sec(),
+        expires: None,
+    }];
+    let data = build_event_archive(&event, &api_tokens, b"db data").unwrap();
+    let archive = parse_event_archive(&data).unwrap();
+    assert_eq!(archive.event.owner, event.owner);
+    assert_eq!(archive.api_tokens.len(), 1);
+    assert_eq!(archive.api_tokens[0].token_hash, "hash");
+    assert_eq!(archive.db_data, b"db data");
+    assert!(parse_event_archive(b"not an archive").is_err());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_archive_limits() {
+        let is_expected = |name: &str| name == MANIFEST_FILE || is_event_db_file(name);
+        let data = write_archive(&[(MANIFEST_FILE, b"{}"), ("ev0001.sqlite", &[0; 1000])]).unwrap();
+        let entries = read_archive(&data, 1002, is_expected).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries["ev0001.sqlite"].len(), 1000);
+        assert!(read_archive(&data, 1001, is_expected).is_err());
+
+        let data = write_archive(&[(MANIFEST_FILE, b"{}"), ("other.txt", b"x")]).unwrap();
+        assert!(read_archive(&data, MAX_UNCOMPRESSED_SIZE, is_expected).is_err());
+        let data = write_archive(&[(MANIFEST_FILE, b"{}"), (MANIFEST_FILE, b"{}")]).unwrap();
+        assert!(read_archive(&data, MAX_UNCOMPRESSED_SIZE, is_expected).is_err());
+    }
+}
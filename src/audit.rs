@@ -0,0 +1,158 @@
+use std::fmt::{Display, Formatter};
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{Build, Rocket, State};
+use rocket_dyn_templates::{context, Template};
+use sqlx::{FromRow, SqliteExecutor};
+use crate::auth::UserInfo;
+use crate::db::DbPool;
+use crate::event::{load_event_info, user_info, EventId};
+use crate::members::{require_event_role, EventRole};
+use crate::qxdatetime::QxDateTime;
+use crate::util::{sqlx_to_anyhow, sqlx_to_custom_error};
+use crate::{QxApiToken, QxSessionId, SharedQxState};
+
+pub const EVENT_CREATE: &str = "event-create";
+pub const EVENT_UPDATE: &str = "event-update";
+pub const EVENT_DELETE: &str = "event-delete";
+pub const EVENT_RESTORE: &str = "event-restore";
+pub const EVENT_OWNER: &str = "event-owner";
+pub const FILE_UPLOAD: &str = "file-upload";
+pub const FILE_DELETE: &str = "file-delete";
+pub const FILE_PUBLIC: &str = "file-public";
+pub const CHANGE_CREATE: &str = "change-create";
+pub const CHANGE_RESOLVE: &str = "change-resolve";
+pub const CHANGE_DELETE: &str = "change-delete";
+pub const RUN_UPDATE: &str = "run-update";
+pub const RUN_DELETE: &str = "run-delete";
+pub const ENTRIES_OPEN: &str = "entries-open";
+pub const ENTRIES_CLOSE: &str = "entries-close";
+pub const ENTRY_CREATE: &str = "entry-create";
+pub const ENTRY_CANCEL: &str = "entry-cancel";
+
+const DEFAULT_PAGE_LIMIT: i64 = 1000;
+
+/// Who performed an audited action
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Actor {
+    Anonymous,
+    User(String),
+    ApiToken(String),
+}
+impl From<&UserInfo> for Actor {
+    fn from(user: &UserInfo) -> Self {
+        Actor::User(user.email.clone())
+    }
+}
+impl From<&QxApiToken> for Actor {
+    fn from(api_token: &QxApiToken) -> Self {
+        Actor::ApiToken(api_token.name.clone())
+    }
+}
+impl Display for Actor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Actor::Anonymous => f.write_str("anonymous"),
+            Actor::User(email) => f.write_str(email),
+            Actor::ApiToken(name) => write!(f, "api-token:{name}"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct AuditRecord {
+    pub id: i64,
+    pub event_id: EventId,
+    pub created: QxDateTime,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+/// JSON summary of audited value
+pub fn summary<T: Serialize>(value: &T) -> Option<String> {
+    serde_json::to_string(value).ok()
+}
+
+/// Append record to audit log, records cannot be updated or deleted later.
+/// Changes of global DB are audited in the transaction of the change,
+/// changes of event DB are audited after they are committed, the event DB cannot share transaction with the global DB.
+pub async fn audit(
+    event_id: EventId,
+    actor: &Actor,
+    action: &str,
+    target: &str,
+    old_value: Option<String>,
+    new_value: Option<String>,
+    db: impl SqliteExecutor<'_>
+) -> anyhow::Result<()> {
+    sqlx::query("INSERT INTO audit_log (event_id, created, actor, action, target, old_value, new_value) VALUES (?, ?, ?, ?, ?, ?, ?)")
+        .bind(event_id)
+        .bind(QxDateTime::now().trimmed_to_sec())
+        .bind(actor.to_string())
+        .bind(action)
+        .bind(target)
+        .bind(old_value)
+        .bind(new_value)
+        .execute(db)
+        .await.map_err(sqlx_to_anyhow)?;
+    Ok(())
+}
+
+async fn load_audit_log(event_id: EventId, from_id: Option<i64>, limit: Option<i64>, gdb: &State<DbPool>) -> Result<Vec<AuditRecord>, Custom<String>> {
+    let records: Vec<AuditRecord> = sqlx::query_as("SELECT * FROM audit_log WHERE event_id=? AND id>=? ORDER BY id LIMIT ?")
+        .bind(event_id)
+        .bind(from_id.unwrap_or(0))
+        .bind(limit.unwrap_or(DEFAULT_PAGE_LIMIT))
+        .fetch_all(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    Ok(records)
+}
+
+#[get("/event/<event_id>/audit?<from_id>&<limit>")]
+async fn get_audit_log(event_id: EventId, from_id: Option<i64>, limit: Option<i64>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_manage_changes, gdb).await?;
+    let records = load_audit_log(event_id, from_id, limit, gdb).await?;
+    Ok(Template::render("audit", context! {
+        user,
+        event,
+        records,
+    }))
+}
+
+#[get("/api/event/<event_id>/audit?<from_id>&<limit>")]
+async fn api_audit_log(event_id: EventId, from_id: Option<i64>, limit: Option<i64>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Json<Vec<AuditRecord>>, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_manage_changes, gdb).await?;
+    let records = load_audit_log(event_id, from_id, limit, gdb).await?;
+    Ok(Json(records))
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket.mount("/", routes![
+            get_audit_log,
+            api_audit_log,
+        ])
+}
+
+#[rocket::async_test]
+async fn test_audit_log_is_append_only() {
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await.unwrap();
+    sqlx::migrate!("db/migrations").run(&pool).await.unwrap();
+    audit(1, &Actor::ApiToken("Default".to_string()), FILE_UPLOAD, "a.txt", None, summary(&42), &pool).await.unwrap();
+    assert!(sqlx::query("UPDATE audit_log SET actor='nobody'").execute(&pool).await.is_err());
+    assert!(sqlx::query("DELETE FROM audit_log").execute(&pool).await.is_err());
+    let records: Vec<AuditRecord> = sqlx::query_as("SELECT * FROM audit_log").fetch_all(&pool).await.unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].actor, "api-token:Default");
+    assert_eq!(records[0].new_value.as_deref(), Some("42"));
+}
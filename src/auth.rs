@@ -1,19 +1,30 @@
-use anyhow::{anyhow, Context};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::time::Duration;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use chrono::TimeDelta;
 use rand::Rng;
-use reqwest::header::AUTHORIZATION;
-use rocket::{get, routes, Build, Rocket, State};
-use rocket::http::{Cookie, CookieJar, SameSite};
+use rocket::form::Form;
+use rocket::{get, routes, tokio, Build, Either, Request, Rocket, State};
+use rocket::fairing::AdHoc;
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
 use rocket::log::private::info;
 use rocket::response::{Debug, Redirect};
-use rocket_oauth2::{OAuth2, TokenResponse};
-use serde_json::Value;
-use crate::{MaybeSessionId, QxSession, QxSessionId, SharedQxState};
+use rocket::response::status::Custom;
+use rocket_dyn_templates::{context, Template};
+use sqlx::{FromRow, SqlitePool};
+use crate::{MaybeSessionId, QxSession, QxSessionId, SessionLimits, SharedQxState};
+use crate::db::DbPool;
+use crate::qxdatetime::QxDateTime;
+use crate::ratelimit::RateLimiter;
+use crate::oauth::{OAuthProviderConfig, OAuthProviders};
+use crate::util::{anyhow_to_custom_error, sqlx_to_anyhow};
 
 #[derive(serde::Serialize, Clone, Debug)]
 pub struct UserInfo {
-    name: String,
+    pub(crate) name: String,
     pub(crate) email: String,
-    picture: String,
+    pub(crate) picture: String,
 }
 
 impl UserInfo {
@@ -26,25 +37,6 @@ impl UserInfo {
         }
     }
 }
-impl TryFrom<&GoogleUserInfo> for UserInfo {
-    type Error = anyhow::Error;
-
-    fn try_from(info: &GoogleUserInfo) -> Result<Self, Self::Error> {
-        fn to_string(val: &Value) -> String {
-            val.as_str().map(|s| s.to_string()).unwrap_or_default()
-        }
-        let email = to_string(&info.email);
-        if email.is_empty() {
-            return Err(anyhow!("User email must be set"));
-        };
-        Ok(Self {
-            name: to_string(&info.name),
-            email,
-            picture: to_string(&info.picture),
-        })
-    }
-}
-
 pub fn generate_random_string(len: usize) -> String {
     const WOWELS: &str = "aeiouy";
     const CONSONANTS: &str = "bcdfghjklmnpqrstvwxz";
@@ -59,88 +51,375 @@ pub fn generate_random_string(len: usize) -> String {
 }
 
 pub const QX_SESSION_ID: &str = "qx_session_id";
+const QX_OAUTH_STATE: &str = "qx_oauth_state";
+// session last_seen time is written to DB not more often than once per interval
+const SESSION_TOUCH_INTERVAL_SEC: i64 = 60;
+const SESSION_SWEEP_INTERVAL_SEC: u64 = 5 * 60;
+
+impl QxSession {
+    fn is_expired(&self, now: &QxDateTime, limits: &SessionLimits) -> bool {
+        is_session_expired(&self.created, &self.last_seen, now, limits)
+    }
+}
+fn is_session_expired(created: &QxDateTime, last_seen: &QxDateTime, now: &QxDateTime, limits: &SessionLimits) -> bool {
+    let age_min = now.msec_since(&Some(*created)).unwrap_or_default() / 60_000;
+    let idle_min = now.msec_since(&Some(*last_seen)).unwrap_or_default() / 60_000;
+    age_min >= limits.max_age || idle_min >= limits.idle_timeout
+}
+
+#[derive(FromRow)]
+struct SessionRecord {
+    user_name: String,
+    user_email: String,
+    user_picture: String,
+    created: QxDateTime,
+    last_seen: QxDateTime,
+}
+impl From<SessionRecord> for QxSession {
+    fn from(rec: SessionRecord) -> Self {
+        QxSession {
+            user_info: UserInfo {
+                name: rec.user_name,
+                email: rec.user_email,
+                picture: rec.user_picture,
+            },
+            created: rec.created,
+            last_seen: rec.last_seen,
+        }
+    }
+}
 
-/// User information to be retrieved from the Google People API.
-#[derive(serde::Deserialize)]
-#[allow(non_snake_case)]
-struct GoogleUserInfo {
-    name: Value,
-    email: Value,
-    picture: Value,
+pub(crate) async fn load_session(session_id: &QxSessionId, state: &State<SharedQxState>, db: &State<DbPool>) -> anyhow::Result<Option<QxSession>> {
+    let cached_session = state.read().await.sessions.get(session_id).cloned();
+    let mut session = match cached_session {
+        Some(session) => session,
+        None => {
+            let rec: Option<SessionRecord> = sqlx::query_as("SELECT * FROM sessions WHERE id=?")
+                .bind(&session_id.0)
+                .fetch_optional(&db.0)
+                .await.map_err(sqlx_to_anyhow)?;
+            let Some(rec) = rec else {
+                return Ok(None);
+            };
+            rec.into()
+        }
+    };
+    let now = QxDateTime::now().trimmed_to_sec();
+    let limits = state.read().await.app_config.session_limits;
+    if session.is_expired(&now, &limits) {
+        info!("Session of user: {} expired", session.user_info.email);
+        delete_session(session_id, state, db).await?;
+        return Ok(None);
+    }
+    if now.msec_since(&Some(session.last_seen)).unwrap_or_default() > SESSION_TOUCH_INTERVAL_SEC * 1000 {
+        sqlx::query("UPDATE sessions SET last_seen=? WHERE id=?")
+            .bind(now)
+            .bind(&session_id.0)
+            .execute(&db.0)
+            .await.map_err(sqlx_to_anyhow)?;
+        session.last_seen = now;
+    }
+    state.write().await.sessions.insert(session_id.clone(), session.clone());
+    Ok(Some(session))
 }
+
+pub(crate) async fn create_session(user_info: UserInfo, cookies: &CookieJar<'_>, state: &State<SharedQxState>, db: &State<DbPool>) -> anyhow::Result<QxSessionId> {
+    let session_id = QxSessionId(generate_random_string(32));
+    let now = QxDateTime::now().trimmed_to_sec();
+    info!("User log in, name: {}, email: {}, picture: {}", user_info.name, user_info.email, user_info.picture);
+    sqlx::query("INSERT INTO sessions (id, user_name, user_email, user_picture, created, last_seen) VALUES (?, ?, ?, ?, ?, ?)")
+        .bind(&session_id.0)
+        .bind(&user_info.name)
+        .bind(&user_info.email)
+        .bind(&user_info.picture)
+        .bind(now)
+        .bind(now)
+        .execute(&db.0)
+        .await.map_err(sqlx_to_anyhow)?;
+    state.write().await.sessions.insert(session_id.clone(), QxSession { user_info, created: now, last_seen: now });
+    // Set a private cookie with the session ID, and redirect to the home page.
+    cookies.add_private(
+        Cookie::build((QX_SESSION_ID, session_id.0.clone()))
+            .same_site(SameSite::Lax)
+            // .same_site(SameSite::Strict)
+            .build()
+    );
+    Ok(session_id)
+}
+
+pub(crate) async fn delete_session(session_id: &QxSessionId, state: &State<SharedQxState>, db: &State<DbPool>) -> anyhow::Result<()> {
+    state.write().await.sessions.remove(session_id);
+    sqlx::query("DELETE FROM sessions WHERE id=?")
+        .bind(&session_id.0)
+        .execute(&db.0)
+        .await.map_err(sqlx_to_anyhow)?;
+    Ok(())
+}
+
+/// Delete expired sessions from DB and from the sessions cache
+async fn sweep_sessions(state: &SharedQxState, pool: &SqlitePool) -> anyhow::Result<()> {
+    let now = QxDateTime::now().trimmed_to_sec();
+    let limits = state.read().await.app_config.session_limits;
+    state.write().await.sessions.retain(|_, session| !session.is_expired(&now, &limits));
+    let count = delete_expired_sessions(&now, &limits, pool).await?;
+    if count > 0 {
+        info!("Expired sessions deleted: {count}");
+    }
+    Ok(())
+}
+async fn delete_expired_sessions(now: &QxDateTime, limits: &SessionLimits, pool: &SqlitePool) -> anyhow::Result<u64> {
+    // timestamps can have different UTC offsets, they are compared as julian days
+    let created_cutoff = QxDateTime(now.0 - TimeDelta::minutes(limits.max_age));
+    let last_seen_cutoff = QxDateTime(now.0 - TimeDelta::minutes(limits.idle_timeout));
+    let res = sqlx::query("DELETE FROM sessions WHERE julianday(created) <= julianday(?) OR julianday(last_seen) <= julianday(?)")
+        .bind(created_cutoff)
+        .bind(last_seen_cutoff)
+        .execute(pool)
+        .await.map_err(sqlx_to_anyhow)?;
+    Ok(res.rows_affected())
+}
+
+fn session_sweeper() -> AdHoc {
+    AdHoc::on_liftoff("Expired sessions sweeper", |rocket| Box::pin(async move {
+        let state = rocket.state::<SharedQxState>().expect("QxState").clone();
+        let pool = rocket.state::<DbPool>().expect("DbPool").0.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(SESSION_SWEEP_INTERVAL_SEC));
+            loop {
+                interval.tick().await;
+                if let Err(e) = sweep_sessions(&state, &pool).await {
+                    error!("Sweep sessions error: {e}");
+                }
+            }
+        });
+    }))
+}
+
+#[catch(401)]
+fn unauthorized(request: &Request) -> Custom<Template> {
+    Custom(Status::Unauthorized, Template::render("error/401", context! {
+        uri: request.uri().to_string(),
+    }))
+}
+
 #[get("/logout")]
-async fn logout(session_id: MaybeSessionId, state: &State<SharedQxState>) -> Redirect {
+async fn logout(session_id: MaybeSessionId, cookies: &CookieJar<'_>, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<Redirect, Debug<anyhow::Error>> {
     if let Some(session_id) = session_id.0 {
-        state.write().await.sessions.remove(&session_id);
+        delete_session(&session_id, state, db).await?;
     }
-    Redirect::to("/")
+    cookies.remove_private(QX_SESSION_ID);
+    Ok(Redirect::to("/"))
 }
 
-#[get("/login")]
-async fn login(state: &State<SharedQxState>) -> Redirect {
-    // must be the same host as redirect_uri, both have to be localhost or 127.0.0.1
-    // Redirect::to("/login/google") doesn't work because of state cookie error in rocket-oauth2 check
-    let (is_local_server, server_port) = {
-        let s = state.read().await;
-        (s.app_config.is_local_server(), s.app_config.server_port)
-    };
-    if is_local_server {
-        Redirect::to(format!("http://localhost:{server_port}/login/google"))
-    } else {
-        Redirect::to(format!("https://qxqx.org:{server_port}/login/google"))
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct LocalUser {
+    name: String,
+    email: String,
+    password_hash: String,
+}
+/// Login with name and password from local users list, it is intended for venue servers,
+/// where OAuth providers are not reachable
+#[derive(serde::Deserialize, Default, Clone, Debug)]
+pub struct LocalLogin {
+    #[serde(default)]
+    enabled: bool,
+    // config file password hashes are not meant to protect public server
+    #[serde(default)]
+    allow_on_public_server: bool,
+    #[serde(default)]
+    users: Vec<LocalUser>,
+}
+impl LocalLogin {
+    /// Local login is disabled on public server address, unless it is explicitly allowed
+    fn for_server_address(mut self, server_address: &str) -> Self {
+        if self.enabled && server_address != "127.0.0.1" {
+            if self.allow_on_public_server {
+                warn!("Local login is enabled on public server address: {server_address}");
+            } else {
+                error!("Local login is disabled on public server address: {server_address}, set local_login.allow_on_public_server to enable it");
+                self.enabled = false;
+            }
+        }
+        self
+    }
+    fn verify(&self, email: &str, password: &str) -> Option<UserInfo> {
+        if !self.enabled {
+            return None;
+        }
+        let user = self.users.iter().find(|u| u.email == email)?;
+        let hash = PasswordHash::new(&user.password_hash)
+            .map_err(|e| error!("Invalid password hash of local user {email}: {e}"))
+            .ok()?;
+        Argon2::default().verify_password(password.as_bytes(), &hash).ok()?;
+        Some(UserInfo {
+            name: user.name.clone(),
+            email: user.email.clone(),
+            picture: "".to_string(),
+        })
     }
 }
 
-#[get("/login/google")]
-fn google_login(oauth2: OAuth2<GoogleUserInfo>, cookies: &CookieJar<'_>) -> Redirect {
-    oauth2.get_redirect(cookies, &["profile", "email"]).unwrap()
-}
-
-#[get("/auth/google")]
-async fn google_auth(token: TokenResponse<GoogleUserInfo>, cookies: &CookieJar<'_>, state: &State<SharedQxState>) -> Result<Redirect, Debug<anyhow::Error>> {
-    // Use the token to retrieve the user's Google account information.
-    debug!("=====> google_callback ==============");
-    let rq = reqwest::Client::builder()
-        .build()
-        .context("failed to build reqwest client")?
-        .get("https://www.googleapis.com/oauth2/v2/userinfo")
-        // .get("https://people.googleapis.com/v1/people/me?personFields=names,emailAddresses")
-        .header(AUTHORIZATION, format!("Bearer {}", token.access_token()));
-    debug!("=====> user name: {:?}", rq);
-    let response = rq.send()
-        .await
-        .context("failed to complete request")?;
-    // info!("=====> response ==============: {:?}", response.text().await);
-    let google_user_info: GoogleUserInfo = response
-        .json()
-        .await
-        .context("failed to deserialize response")?;
-    let user_info = UserInfo::try_from(&google_user_info)?;
-    fn generate_session_id() -> String {
-        generate_random_string(32)
+#[derive(serde::Serialize)]
+struct LoginProvider {
+    display_name: String,
+    login_uri: String,
+}
+#[get("/login")]
+async fn login(providers: &State<OAuthProviders>, local_login: &State<LocalLogin>) -> Result<Either<Redirect, Template>, Custom<String>> {
+    let providers = providers.0.iter()
+        .map(|p| Ok(LoginProvider { display_name: p.display_name.clone(), login_uri: p.login_uri()? }))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(anyhow_to_custom_error)?;
+    if let [provider] = &providers[..] && !local_login.enabled {
+        // Login start must be the same host as redirect_uri, both have to be localhost or 127.0.0.1,
+        // Redirect::to("/login/google") doesn't work because of OAuth state cookie check
+        let login_uri = provider.login_uri.clone();
+        return Ok(Either::Left(Redirect::to(login_uri)));
     }
-    let session_id = generate_session_id();
-    info!("User log in, name: {}, email: {}, picture: {}", user_info.name, user_info.email, user_info.picture);
+    Ok(Either::Right(Template::render("login", context! {
+        providers,
+        local_login_enabled: local_login.enabled,
+    })))
+}
 
+#[derive(Debug, FromForm)]
+struct LocalLoginFormValues<'v> {
+    email: &'v str,
+    password: &'v str,
+}
+#[post("/login/local", data = "<form>")]
+async fn local_login(
+    form: Form<LocalLoginFormValues<'_>>,
+    local_login: &State<LocalLogin>,
+    client_ip: Option<IpAddr>,
+    rate_limiter: &State<RateLimiter>,
+    cookies: &CookieJar<'_>,
+    state: &State<SharedQxState>,
+    db: &State<DbPool>
+) -> Result<Redirect, Custom<String>> {
+    let Some(user_info) = local_login.verify(form.email, form.password) else {
+        warn!("Local login failed, email: {}", form.email);
+        if let Some(ip) = client_ip {
+            rate_limiter.record_failed_lookup(ip);
+        }
+        return Err(Custom(Status::Unauthorized, "Invalid email or password".to_string()));
+    };
+    let session_id = create_session(user_info, cookies, state, db).await.map_err(anyhow_to_custom_error)?;
     info!("insert session_id: {session_id:?}");
-    
-    state.write().await.sessions.insert(QxSessionId(session_id.clone()), QxSession{ user_info });
-    // Set a private cookie with the user's name, and redirect to the home page.
+    Ok(Redirect::to("/"))
+}
+
+#[get("/login/<provider>")]
+fn oauth_login(provider: &str, providers: &State<OAuthProviders>, cookies: &CookieJar<'_>) -> Result<Redirect, Custom<String>> {
+    let provider = providers.find(provider).ok_or(Custom(Status::NotFound, format!("Unknown login provider: {provider}")))?;
+    let oauth_state = generate_random_string(32);
+    let redirect_url = provider.authorize_url(&oauth_state).map_err(anyhow_to_custom_error)?;
     cookies.add_private(
-        Cookie::build((QX_SESSION_ID, session_id))
+        Cookie::build((QX_OAUTH_STATE, format!("{}:{oauth_state}", provider.name)))
             .same_site(SameSite::Lax)
-            // .same_site(SameSite::Strict)
             .build()
     );
+    Ok(Redirect::to(redirect_url))
+}
+
+#[get("/auth/<provider>?<code>&<state>")]
+async fn oauth_auth(
+    provider: &str,
+    code: &str,
+    state: &str,
+    providers: &State<OAuthProviders>,
+    cookies: &CookieJar<'_>,
+    qx_state: &State<SharedQxState>,
+    db: &State<DbPool>
+) -> Result<Redirect, Custom<String>> {
+    let provider = providers.find(provider).ok_or(Custom(Status::NotFound, format!("Unknown login provider: {provider}")))?;
+    let expected_state = cookies.get_private(QX_OAUTH_STATE).map(|cookie| cookie.value().to_string());
+    cookies.remove_private(QX_OAUTH_STATE);
+    if expected_state.as_deref() != Some(format!("{}:{state}", provider.name).as_str()) {
+        return Err(Custom(Status::BadRequest, "OAuth state mismatch".to_string()));
+    }
+    let access_token = provider.exchange_code(code).await.map_err(|e| Custom(Status::Unauthorized, e.to_string()))?;
+    let user_info = provider.fetch_user_info(&access_token).await.map_err(|e| Custom(Status::Unauthorized, e.to_string()))?;
+    let session_id = create_session(user_info, cookies, qx_state, db).await.map_err(anyhow_to_custom_error)?;
+    info!("insert session_id: {session_id:?}");
     Ok(Redirect::to("/"))
 }
 
+fn local_login_config(rocket: &Rocket<Build>) -> LocalLogin {
+    let local_login = rocket.figment().extract_inner::<LocalLogin>("local_login").unwrap_or_default();
+    let server_address = rocket.figment().extract_inner::<String>("address").unwrap_or_default();
+    local_login.for_server_address(&server_address)
+}
+
+fn oauth_providers(rocket: &Rocket<Build>) -> OAuthProviders {
+    let config = rocket.figment().extract_inner::<BTreeMap<String, OAuthProviderConfig>>("oauth").unwrap_or_default();
+    OAuthProviders::from_config(config).expect("OAuth providers config")
+}
+
 pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    let providers = oauth_providers(&rocket);
+    let local_login_config = local_login_config(&rocket);
     rocket.mount("/", routes![
             logout,
             login,
-            google_login,
-            google_auth,
+            local_login,
+            oauth_login,
+            oauth_auth,
         ])
-        .attach(OAuth2::<GoogleUserInfo>::fairing("google"))
-}
\ No newline at end of file
+        .register("/", catchers![unauthorized])
+        .manage(providers)
+        .manage(local_login_config)
+        .attach(session_sweeper())
+}
+#[test]
+fn test_local_login_verify() {
+    use argon2::PasswordHasher;
+    use argon2::password_hash::SaltString;
+
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>()).unwrap();
+    let password_hash = Argon2::default().hash_password(b"secret", &salt).unwrap().to_string();
+    let mut local_login = LocalLogin {
+        enabled: true,
+        allow_on_public_server: false,
+        users: vec![LocalUser { name: "Event Center".to_string(), email: "center@local".to_string(), password_hash }],
+    };
+    assert_eq!(local_login.verify("center@local", "secret").unwrap().email, "center@local");
+    assert!(local_login.verify("center@local", "wrong").is_none());
+    assert!(local_login.verify("nobody@local", "secret").is_none());
+
+    assert!(local_login.clone().for_server_address("127.0.0.1").enabled);
+    assert!(!local_login.clone().for_server_address("0.0.0.0").enabled);
+    local_login.allow_on_public_server = true;
+    assert!(local_login.clone().for_server_address("0.0.0.0").enabled);
+
+    local_login.enabled = false;
+    assert!(local_login.verify("center@local", "secret").is_none());
+}
+
+#[rocket::async_test]
+async fn test_delete_expired_sessions() {
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await.unwrap();
+    sqlx::migrate!("db/migrations").run(&pool).await.unwrap();
+    let now = QxDateTime::parse_from_iso("2026-10-17T12:00:00+02:00").unwrap();
+    let limits = SessionLimits { max_age: 60, idle_timeout: 10 };
+    for (id, created, last_seen) in [
+        ("fresh", "2026-10-17T11:30:00+02:00", "2026-10-17T11:55:00+02:00"),
+        // same instants as fresh session in other UTC offset
+        ("fresh-utc", "2026-10-17T09:30:00+00:00", "2026-10-17T09:55:00+00:00"),
+        ("too-old", "2026-10-17T10:59:00+02:00", "2026-10-17T11:59:00+02:00"),
+        ("idle", "2026-10-17T11:30:00+02:00", "2026-10-17T11:50:00+02:00"),
+        ("idle-utc", "2026-10-17T09:30:00+00:00", "2026-10-17T09:49:00+00:00"),
+    ] {
+        sqlx::query("INSERT INTO sessions (id, user_name, user_email, user_picture, created, last_seen) VALUES (?, '', '', '', ?, ?)")
+            .bind(id)
+            .bind(QxDateTime::parse_from_iso(created).unwrap())
+            .bind(QxDateTime::parse_from_iso(last_seen).unwrap())
+            .execute(&pool).await.unwrap();
+    }
+    assert_eq!(delete_expired_sessions(&now, &limits, &pool).await.unwrap(), 3);
+    let ids: Vec<(String,)> = sqlx::query_as("SELECT id FROM sessions ORDER BY id").fetch_all(&pool).await.unwrap();
+    assert_eq!(ids, vec![("fresh".to_string(),), ("fresh-utc".to_string(),)]);
+}
@@ -1,6 +1,7 @@
 use std::fmt::{Display, Formatter};
 use anyhow::anyhow;
 use itertools::Itertools;
+use serde_json::json;
 use rocket::{Build, Rocket, State};
 use rocket::http::Status;
 use rocket::response::status::Custom;
@@ -8,16 +9,21 @@ use rocket::response::stream::{Event, EventStream};
 use rocket::serde::{Deserialize, Serialize};
 use rocket::serde::json::Json;
 use rocket_dyn_templates::{context, Template};
-use sqlx::{query_as, FromRow, QueryBuilder, SqlitePool};
+use sqlx::{query_as, FromRow, QueryBuilder, SqliteConnection};
 use crate::event::{load_event_info, load_event_info_for_api_token, user_info, user_info_opt, EventId};
-use crate::{impl_sqlx_json_text_type_encode_decode, impl_sqlx_text_type_encode_decode, MaybeSessionId, QxApiToken, QxSessionId, SharedQxState};
+use crate::{impl_sqlx_json_text_type_encode_decode, impl_sqlx_text_type_encode_decode, MaybeSessionId, QxApiToken, SharedQxState};
 use crate::qxdatetime::QxDateTime;
 use sqlx::{Encode, Sqlite};
 use sqlx::query::{Query};
 use sqlx::sqlite::{SqliteArgumentValue, SqliteArguments};
+use crate::access::{Changes, EventRead};
+use crate::apitoken::ApiScope;
+use crate::audit::{audit, summary, Actor, CHANGE_CREATE, CHANGE_DELETE, CHANGE_RESOLVE, RUN_DELETE, RUN_UPDATE};
 use crate::db::{get_event_db, DbPool};
+use crate::members::event_role;
 use crate::oc::OCheckListChange;
-use crate::runs::{RunChange};
+use crate::personaltoken::{ChangesScope, ReadScope, ScopedSessionId};
+use crate::runs::{RunChange, RunsRecord};
 use crate::util::{anyhow_to_custom_error, sqlx_to_anyhow, sqlx_to_custom_error};
 
 pub(crate) type DataId = i64;
@@ -152,6 +158,7 @@ pub async fn add_change(
 #[get("/event/<event_id>/changes?<from_id>&<limit>")]
 async fn get_changes(
     event_id: EventId,
+    _access: EventRead<Changes>,
     from_id: Option<i64>,
     limit: Option<i64>,
     session_id: MaybeSessionId,
@@ -175,9 +182,9 @@ async fn get_changes(
 }
 
 #[get("/event/<event_id>/my-changes")]
-async fn get_my_changes(event_id: EventId, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+async fn get_my_changes(event_id: EventId, session: ScopedSessionId<ReadScope>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
     let event = load_event_info(event_id, gdb).await?;
-    let user = user_info(&session_id, state).await?;
+    let user = user_info(&session.session_id, state).await?;
     let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
     let records: Vec<ChangesRecord> = sqlx::query_as("SELECT * FROM changes WHERE user_id=? ORDER BY created")
         .bind(&user.email)
@@ -196,32 +203,37 @@ async fn get_my_changes(event_id: EventId, session_id: QxSessionId, state: &Stat
 #[post("/api/event/<event_id>/changes/run-update-request?<data_id>", data = "<data>")]
 pub async fn add_run_update_request_change(
     event_id: EventId,
-    session_id: QxSessionId,
+    session: ScopedSessionId<ChangesScope>,
     data_id: Option<i64>,
     data: Json<RunChange>,
-    state: &State<SharedQxState>
+    state: &State<SharedQxState>,
+    gdb: &State<DbPool>
 ) -> Result<Json<i64>, Custom<String>> {
-    let user = user_info(&session_id, state).await?;
+    let user = user_info(&session.session_id, state).await?;
     let data = ChangeData::RunUpdateRequest(data.into_inner());
-    let change_id = add_change(event_id, ChangesRecord {
+    let change = ChangesRecord {
         id: 0,
         source: "www".to_string(),
         data_type: DataType::RunUpdateRequest,
         data_id,
         data,
-        user_id: Some(user.email),
+        user_id: Some(user.email.clone()),
         status: Some(ChangeStatus::Pending),
         status_message: None,
         created: QxDateTime::now(),
         lock_number: None,
-    }, state).await.map_err(anyhow_to_custom_error)?;
+    };
+    let new_value = summary(&change.data);
+    let change_id = add_change(event_id, change, state).await.map_err(anyhow_to_custom_error)?;
+    audit(event_id, &Actor::from(&user), CHANGE_CREATE, &format!("change:{change_id}"), None, new_value, &gdb.0).await
+        .map_err(anyhow_to_custom_error)?;
     //state.read().await.broadcast_runs_change((event_id, data_id, data)).await.map_err(anyhow_to_custom_error)?;
     Ok(Json(change_id))
 }
 
 #[get("/api/event/current/changes/lock-change?<change_id>&<lock_number>")]
 async fn api_changes_lock_change(change_id: i64, lock_number: i64, api_token: QxApiToken, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<Json<i64>, Custom<String>> {
-    let event = load_event_info_for_api_token(&api_token, db).await?;
+    let event = load_event_info_for_api_token(&api_token, ApiScope::QeSync, db).await?;
     let db = get_event_db(event.id, state).await.map_err(anyhow_to_custom_error)?;
     let id: (Option<i64>,) = sqlx::query_as("SELECT lock_number FROM changes WHERE id=?")
         .bind(change_id)
@@ -253,20 +265,29 @@ async fn api_changes_resolve_change(
     } else {
         ChangeStatus::Rejected
     };
-    let event = load_event_info_for_api_token(&api_token, db).await?;
+    let event = load_event_info_for_api_token(&api_token, ApiScope::QeSync, db).await?;
     let edb = get_event_db(event.id, state).await.map_err(anyhow_to_custom_error)?;
-    sqlx::query("UPDATE changes SET status=?, status_message=?  WHERE id=? AND lock_number=?")
+    let old_status: Option<(Option<String>, Option<String>)> = sqlx::query_as("SELECT status, status_message FROM changes WHERE id=?")
+        .bind(change_id)
+        .fetch_optional(&edb).await.map_err(sqlx_to_custom_error)?;
+    let res = sqlx::query("UPDATE changes SET status=?, status_message=?  WHERE id=? AND lock_number=?")
         .bind(format!("{new_status}"))
-        .bind(status_message)
+        .bind(&status_message)
         .bind(change_id)
         .bind(lock_number)
         .execute(&edb).await.map_err(sqlx_to_custom_error)?;
+    if res.rows_affected() > 0 {
+        let old_value = old_status.map(|(status, status_message)| json!({"status": status, "status_message": status_message}).to_string());
+        let new_value = json!({"status": new_status.to_string(), "status_message": status_message}).to_string();
+        audit(event.id, &Actor::from(&api_token), CHANGE_RESOLVE, &format!("change:{change_id}"), old_value, Some(new_value), &db.0).await
+            .map_err(anyhow_to_custom_error)?;
+    }
     Ok(())
 }
 
 #[post("/api/event/current/changes/run-updated?<run_id>", data = "<change>")]
 async fn add_run_updated_change(run_id: DataId, change: Json<Option<RunChange>>, api_token: QxApiToken, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<(), Custom<String>> {
-    let event = load_event_info_for_api_token(&api_token, db).await?;
+    let event = load_event_info_for_api_token(&api_token, ApiScope::QeSync, db).await?;
     let run_change = change.into_inner();
     let data = if let Some(run_change) = &run_change {
         ChangeData::RunUpdated(run_change.clone())
@@ -286,12 +307,20 @@ async fn add_run_updated_change(run_id: DataId, change: Json<Option<RunChange>>,
         lock_number: None,
     }, state).await.map_err(anyhow_to_custom_error)?;
     // add_change(event.id, "qe", data_type, run_id, &data, None, None, None, state).await.map_err(anyhow_to_custom_error)?;
-    let db = get_event_db(event.id, state).await.map_err(anyhow_to_custom_error)?;
-    apply_qe_run_change(run_id, run_change.as_ref(), &db).await.map_err(anyhow_to_custom_error)?;
+    let edb = get_event_db(event.id, state).await.map_err(anyhow_to_custom_error)?;
+    let old_run: Option<RunsRecord> = sqlx::query_as("SELECT * FROM runs WHERE run_id=?")
+        .bind(run_id)
+        .fetch_optional(&edb).await.map_err(sqlx_to_custom_error)?;
+    let mut tx = edb.begin().await.map_err(sqlx_to_custom_error)?;
+    apply_qe_run_change(run_id, run_change.as_ref(), &mut tx).await.map_err(anyhow_to_custom_error)?;
+    tx.commit().await.map_err(sqlx_to_custom_error)?;
+    let action = if run_change.is_some() { RUN_UPDATE } else { RUN_DELETE };
+    audit(event.id, &Actor::from(&api_token), action, &format!("run:{run_id}"), old_run.as_ref().and_then(summary), run_change.as_ref().and_then(summary), &db.0).await
+        .map_err(anyhow_to_custom_error)?;
     Ok(())
 }
 
-async fn apply_qe_run_change(run_id: DataId, change: Option<&RunChange>, edb: &SqlitePool) -> anyhow::Result<()> {
+async fn apply_qe_run_change(run_id: DataId, change: Option<&RunChange>, edb: &mut SqliteConnection) -> anyhow::Result<()> {
     if let Some(change) = change {
         let changed_fields = change.fields_with_value();
         if changed_fields.is_empty() {
@@ -299,7 +328,7 @@ async fn apply_qe_run_change(run_id: DataId, change: Option<&RunChange>, edb: &S
         }
         let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM runs WHERE run_id=?")
             .bind(run_id)
-            .fetch_one(edb).await.map_err(sqlx_to_anyhow)?;
+            .fetch_one(&mut *edb).await.map_err(sqlx_to_anyhow)?;
         if count.0 == 0 {
             sqlx::query("INSERT INTO runs (
                  run_id,
@@ -321,7 +350,7 @@ async fn apply_qe_run_change(run_id: DataId, change: Option<&RunChange>, edb: &S
                 .bind(change.start_time)
                 .bind(change.check_time)
                 .bind(change.finish_time)
-                .execute(edb).await.map_err(sqlx_to_anyhow)?;
+                .execute(&mut *edb).await.map_err(sqlx_to_anyhow)?;
         } else {
             let placeholders = changed_fields.iter().map(|&fld_name| format!("{fld_name}=?") ).join(",");
             let qs = format!("UPDATE runs SET {placeholders} WHERE run_id=?");
@@ -344,19 +373,19 @@ async fn apply_qe_run_change(run_id: DataId, change: Option<&RunChange>, edb: &S
                 q = bind_field(q, field_name, change)?;
             }
             let q = q.bind(run_id);
-            q.execute(edb).await.map_err(sqlx_to_anyhow)?;
+            q.execute(&mut *edb).await.map_err(sqlx_to_anyhow)?;
         }
         Ok(())
     } else {
         sqlx::query("DELETE FROM runs WHERE run_id=?")
             .bind(run_id)
-            .execute(edb).await.map_err(sqlx_to_anyhow)?;
+            .execute(&mut *edb).await.map_err(sqlx_to_anyhow)?;
         Ok(())
     }
 }
 
 #[get("/api/event/<event_id>/changes/sse")]
-async fn changes_sse(event_id: EventId, state: &State<SharedQxState>) -> EventStream![] {
+async fn changes_sse(event_id: EventId, _access: EventRead<Changes>, state: &State<SharedQxState>) -> EventStream![] {
     let mut chng_receiver = state.read().await.changes_receiver.clone();
     EventStream! {
         loop {
@@ -385,6 +414,7 @@ async fn changes_sse(event_id: EventId, state: &State<SharedQxState>) -> EventSt
 #[get("/api/event/<event_id>/changes?<from_id>&<limit>&<data_type>&<status>")]
 async fn api_changes_get(
     event_id: EventId,
+    _access: EventRead<Changes>,
     from_id: Option<i64>,
     limit: Option<i64>,
     data_type: Option<&str>,
@@ -418,26 +448,29 @@ async fn api_changes_get(
 async fn api_changes_delete(
     event_id: EventId,
     change_id: i64,
-    session_id: QxSessionId,
+    session: ScopedSessionId<ChangesScope>,
     state: &State<SharedQxState>,
+    gdb: &State<DbPool>,
 ) -> Result<(), Custom<String>> {
-    let user = user_info(&session_id, state).await?;
+    let user = user_info(&session.session_id, state).await?;
+    let event = load_event_info(event_id, gdb).await?;
+    let can_manage_changes = event_role(&event, Some(&user), gdb).await?.is_some_and(|r| r.can_manage_changes());
     let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
     let change: ChangesRecord = sqlx::query_as("SELECT * FROM changes WHERE id=?")
         .bind(change_id)
         .fetch_one(&edb)
         .await
         .map_err(sqlx_to_custom_error)?;
-    if let Some(user_id) = change.user_id {
-        if user_id == user.email {
-            sqlx::query("DELETE FROM changes WHERE id=?")
-                .bind(change_id)
-                .execute(&edb).await
-                .map_err(sqlx_to_custom_error)?;
-            return Ok(())
-        }
+    if change.user_id.as_ref() == Some(&user.email) || can_manage_changes {
+        sqlx::query("DELETE FROM changes WHERE id=?")
+            .bind(change_id)
+            .execute(&edb).await
+            .map_err(sqlx_to_custom_error)?;
+        audit(event_id, &Actor::from(&user), CHANGE_DELETE, &format!("change:{change_id}"), summary(&change), None, &gdb.0).await
+            .map_err(anyhow_to_custom_error)?;
+        return Ok(())
     }
-    Err(Custom(Status::Unauthorized, "Only change owner can delete.".into()))
+    Err(Custom(Status::Unauthorized, "Only change owner or event judge can delete.".into()))
 }
 
 pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
@@ -0,0 +1,477 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{Build, Rocket, State};
+use rocket_dyn_templates::{context, Template};
+use sqlx::{Encode, FromRow, Sqlite};
+use sqlx::sqlite::SqliteArgumentValue;
+use crate::auth::UserInfo;
+use crate::db::{get_event_db, DbPool};
+use crate::event::{load_event_info, user_info, user_info_opt, EventId, EventRecord};
+use crate::members::{event_role, require_event_role, EventRole};
+use crate::qxdatetime::QxDateTime;
+use crate::runs::RunsRecord;
+use crate::util::{anyhow_to_custom_error, sqlx_to_anyhow, sqlx_to_custom_error};
+use crate::{impl_sqlx_text_type_encode_decode, MaybeSessionId, QxSessionId, SharedQxState};
+
+pub type CompetitionId = i64;
+
+// stage winner gets max points, others proportionally less according to their time
+const STAGE_POINTS_MAX: i64 = 100;
+
+const TIME_SUM: &str = "TimeSum";
+const POINTS: &str = "Points";
+const BEST_OF: &str = "BestOf";
+
+/// How overall results are computed from stage results
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Aggregation {
+    /// sum of stage times, only competitors finishing all stages are classified
+    TimeSum,
+    /// sum of stage points
+    Points,
+    /// sum of points from the best `best_count` stages
+    BestOf,
+}
+impl_sqlx_text_type_encode_decode!(Aggregation);
+
+impl Aggregation {
+    pub fn from_str(s: &str) -> Self {
+        match s {
+            TIME_SUM => Self::TimeSum,
+            POINTS => Self::Points,
+            BEST_OF => Self::BestOf,
+            _ => panic!("Unknown aggregation: {s}"),
+        }
+    }
+    fn try_from_str(s: &str) -> Option<Self> {
+        [Self::TimeSum, Self::Points, Self::BestOf].into_iter()
+            .find(|aggregation| aggregation.to_string() == s)
+    }
+}
+
+impl Display for Aggregation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Aggregation::TimeSum => f.write_str(TIME_SUM),
+            Aggregation::Points => f.write_str(POINTS),
+            Aggregation::BestOf => f.write_str(BEST_OF),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct CompetitionRecord {
+    pub id: CompetitionId,
+    pub name: String,
+    pub owner: String,
+    pub aggregation: Aggregation,
+    pub best_count: Option<i64>,
+    pub created: QxDateTime,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct StageResult {
+    pub time_msec: Option<i64>,
+    pub points: Option<i64>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct OverallResult {
+    pub position: Option<usize>,
+    pub registration: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub stages: Vec<StageResult>,
+    /// time sum in msec or points, according to aggregation
+    pub total: Option<i64>,
+}
+
+/// Points of stage run, winner of the class gets `STAGE_POINTS_MAX`
+fn stage_points(time_msec: i64, winner_msec: i64) -> i64 {
+    if time_msec <= 0 {
+        return 0;
+    }
+    (STAGE_POINTS_MAX * winner_msec + time_msec / 2) / time_msec
+}
+
+/// Aggregate results of one class, `stages` contains runs of the class in each stage ordered by stage number.
+/// Runs are matched across stages by registration, runs without registration are not classified.
+pub fn aggregate_results(stages: &[Vec<RunsRecord>], aggregation: Aggregation, best_count: Option<i64>) -> Vec<OverallResult> {
+    let mut results: Vec<OverallResult> = vec![];
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (stage_no, runs) in stages.iter().enumerate() {
+        let winner_msec = runs.iter()
+            .filter_map(|run| QxDateTime::msec_since_until(&run.start_time, &run.finish_time))
+            .filter(|msec| *msec > 0)
+            .min();
+        for run in runs {
+            let Some(registration) = run.registration.as_ref().filter(|r| !r.is_empty()) else {
+                continue;
+            };
+            let idx = *index.entry(registration.clone()).or_insert_with(|| {
+                results.push(OverallResult {
+                    position: None,
+                    registration: registration.clone(),
+                    first_name: run.first_name.clone(),
+                    last_name: run.last_name.clone(),
+                    stages: vec![StageResult { time_msec: None, points: None }; stages.len()],
+                    total: None,
+                });
+                results.len() - 1
+            });
+            let time_msec = QxDateTime::msec_since_until(&run.start_time, &run.finish_time).filter(|msec| *msec > 0);
+            let points = time_msec.zip(winner_msec).map(|(msec, winner_msec)| stage_points(msec, winner_msec));
+            results[idx].stages[stage_no] = StageResult { time_msec, points };
+        }
+    }
+    for result in &mut results {
+        result.total = match aggregation {
+            Aggregation::TimeSum => result.stages.iter()
+                .map(|stage| stage.time_msec)
+                .sum::<Option<i64>>(),
+            Aggregation::Points | Aggregation::BestOf => {
+                let mut points = result.stages.iter().filter_map(|stage| stage.points).collect::<Vec<_>>();
+                if points.is_empty() {
+                    None
+                } else {
+                    points.sort_unstable_by(|a, b| b.cmp(a));
+                    if aggregation == Aggregation::BestOf {
+                        points.truncate(best_count.unwrap_or(i64::MAX).max(0) as usize);
+                    }
+                    Some(points.iter().sum())
+                }
+            }
+        };
+    }
+    let finished_stages = |result: &OverallResult| result.stages.iter().filter(|stage| stage.time_msec.is_some()).count();
+    results.sort_by(|a, b| {
+        let by_total = match (a.total, b.total) {
+            (Some(a), Some(b)) if aggregation == Aggregation::TimeSum => a.cmp(&b),
+            (Some(a), Some(b)) => b.cmp(&a),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => finished_stages(b).cmp(&finished_stages(a)),
+        };
+        by_total
+            .then_with(|| a.last_name.cmp(&b.last_name))
+            .then_with(|| a.first_name.cmp(&b.first_name))
+    });
+    // competitors with equal total share the position
+    let mut prev: Option<(i64, usize)> = None;
+    for (n, result) in results.iter_mut().enumerate() {
+        let Some(total) = result.total else {
+            break;
+        };
+        let position = match prev {
+            Some((prev_total, prev_position)) if prev_total == total => prev_position,
+            _ => n + 1,
+        };
+        result.position = Some(position);
+        prev = Some((total, position));
+    }
+    results
+}
+
+async fn load_competition(competition_id: CompetitionId, gdb: &State<DbPool>) -> Result<CompetitionRecord, Custom<String>> {
+    sqlx::query_as("SELECT * FROM competitions WHERE id=?")
+        .bind(competition_id)
+        .fetch_optional(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?
+        .ok_or(Custom(Status::NotFound, format!("Competition id: {competition_id} not found")))
+}
+
+/// Stage events of competition ordered by stage number
+async fn load_stages(competition_id: CompetitionId, gdb: &State<DbPool>) -> anyhow::Result<Vec<EventRecord>> {
+    let stages = sqlx::query_as("SELECT * FROM events WHERE competition_id=? ORDER BY stage, id")
+        .bind(competition_id)
+        .fetch_all(&gdb.0)
+        .await.map_err(sqlx_to_anyhow)?;
+    Ok(stages)
+}
+
+/// Competition the event is stage of, together with all its stages
+pub async fn load_event_competition(event_id: EventId, gdb: &State<DbPool>) -> anyhow::Result<Option<(CompetitionRecord, Vec<EventRecord>)>> {
+    let competition: Option<CompetitionRecord> = sqlx::query_as("SELECT competitions.* FROM competitions, events
+                 WHERE events.id=? AND competitions.id=events.competition_id")
+        .bind(event_id)
+        .fetch_optional(&gdb.0)
+        .await.map_err(sqlx_to_anyhow)?;
+    let Some(competition) = competition else {
+        return Ok(None);
+    };
+    let stages = load_stages(competition.id, gdb).await?;
+    Ok(Some((competition, stages)))
+}
+
+fn require_competition_owner(competition: &CompetitionRecord, user: &UserInfo) -> Result<(), Custom<String>> {
+    if competition.owner != user.email {
+        return Err(Custom(Status::Forbidden, "Only competition owner can change the competition".to_string()));
+    }
+    Ok(())
+}
+
+/// Overall results can be read by anyone who can read runs of all stages
+async fn require_stages_readable(stages: &[EventRecord], user: Option<&UserInfo>, gdb: &State<DbPool>) -> Result<(), Custom<String>> {
+    for stage in stages {
+        if !stage.runs_public && event_role(stage, user, gdb).await?.is_none() {
+            return Err(Custom(Status::Forbidden, format!("Runs of stage E{} are not public", stage.stage)));
+        }
+    }
+    Ok(())
+}
+
+/// Class names from all stages, class does not need to be present in every stage
+async fn load_class_names(stages: &[EventRecord], state: &State<SharedQxState>) -> anyhow::Result<Vec<String>> {
+    let mut class_names = BTreeSet::new();
+    for stage in stages {
+        let edb = get_event_db(stage.id, state).await?;
+        let names: Vec<(String,)> = sqlx::query_as("SELECT name FROM classes")
+            .fetch_all(&edb)
+            .await.map_err(sqlx_to_anyhow)?;
+        class_names.extend(names.into_iter().map(|n| n.0));
+    }
+    Ok(class_names.into_iter().collect())
+}
+
+async fn load_overall_results(competition: &CompetitionRecord, stages: &[EventRecord], class_name: &str, state: &State<SharedQxState>) -> anyhow::Result<Vec<OverallResult>> {
+    let mut stage_runs = vec![];
+    for stage in stages {
+        let edb = get_event_db(stage.id, state).await?;
+        let runs: Vec<RunsRecord> = sqlx::query_as("SELECT * FROM runs WHERE class_name=?")
+            .bind(class_name)
+            .fetch_all(&edb)
+            .await.map_err(sqlx_to_anyhow)?;
+        stage_runs.push(runs);
+    }
+    Ok(aggregate_results(&stage_runs, competition.aggregation, competition.best_count))
+}
+
+#[get("/competition/<competition_id>?<class_name>")]
+async fn get_competition(competition_id: CompetitionId, class_name: Option<&str>, session_id: MaybeSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    let competition = load_competition(competition_id, gdb).await?;
+    let stages = load_stages(competition_id, gdb).await.map_err(anyhow_to_custom_error)?;
+    let user = user_info_opt(session_id.0.as_ref(), state).await.map_err(anyhow_to_custom_error)?;
+    require_stages_readable(&stages, user.as_ref(), gdb).await?;
+    let classes = load_class_names(&stages, state).await.map_err(anyhow_to_custom_error)?;
+    let class_name = class_name.map(|s| s.to_string())
+        .or_else(|| classes.first().cloned())
+        .unwrap_or_default();
+    let results = load_overall_results(&competition, &stages, &class_name, state).await.map_err(anyhow_to_custom_error)?;
+    let is_owner = user.as_ref().is_some_and(|user| user.email == competition.owner);
+    Ok(Template::render("competition", context! {
+        user,
+        competition,
+        stages,
+        classes,
+        class_name,
+        results,
+        is_owner,
+        aggregations: [Aggregation::TimeSum, Aggregation::Points, Aggregation::BestOf],
+    }))
+}
+
+#[get("/api/competition/<competition_id>/results?<class_name>")]
+async fn get_api_competition_results(competition_id: CompetitionId, class_name: &str, session_id: MaybeSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Json<Vec<OverallResult>>, Custom<String>> {
+    let competition = load_competition(competition_id, gdb).await?;
+    let stages = load_stages(competition_id, gdb).await.map_err(anyhow_to_custom_error)?;
+    let user = user_info_opt(session_id.0.as_ref(), state).await.map_err(anyhow_to_custom_error)?;
+    require_stages_readable(&stages, user.as_ref(), gdb).await?;
+    let results = load_overall_results(&competition, &stages, class_name, state).await.map_err(anyhow_to_custom_error)?;
+    Ok(Json(results))
+}
+
+fn parse_aggregation(aggregation: &str, best_count: Option<i64>) -> Result<(Aggregation, Option<i64>), Custom<String>> {
+    let aggregation = Aggregation::try_from_str(aggregation)
+        .ok_or(Custom(Status::BadRequest, format!("Invalid aggregation: {aggregation}")))?;
+    if aggregation != Aggregation::BestOf {
+        return Ok((aggregation, None));
+    }
+    match best_count {
+        Some(n) if n > 0 => Ok((aggregation, Some(n))),
+        _ => Err(Custom(Status::BadRequest, "Number of best stages must be positive".to_string())),
+    }
+}
+
+#[derive(Debug, FromForm)]
+struct CreateCompetitionFormValues<'v> {
+    // first stage of the competition
+    event_id: EventId,
+    #[field(validate = len(1..))]
+    name: &'v str,
+    aggregation: &'v str,
+    best_count: Option<i64>,
+}
+#[post("/competition", data = "<form>")]
+async fn post_competition(form: Form<CreateCompetitionFormValues<'_>>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let event = load_event_info(form.event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_manage_event, gdb).await?;
+    let (aggregation, best_count) = parse_aggregation(form.aggregation, form.best_count)?;
+    let mut tx = gdb.0.begin().await.map_err(sqlx_to_custom_error)?;
+    let competition_id: (CompetitionId,) = sqlx::query_as("INSERT INTO competitions (name, owner, aggregation, best_count, created) VALUES (?, ?, ?, ?, ?) RETURNING id")
+        .bind(form.name.trim())
+        .bind(&user.email)
+        .bind(aggregation)
+        .bind(best_count)
+        .bind(QxDateTime::now().trimmed_to_sec())
+        .fetch_one(&mut *tx)
+        .await.map_err(sqlx_to_custom_error)?;
+    sqlx::query("UPDATE events SET competition_id=? WHERE id=?")
+        .bind(competition_id.0)
+        .bind(event.id)
+        .execute(&mut *tx)
+        .await.map_err(sqlx_to_custom_error)?;
+    tx.commit().await.map_err(sqlx_to_custom_error)?;
+    info!("User {} created competition id: {} with event id: {}", user.email, competition_id.0, event.id);
+    Ok(Redirect::to(format!("/competition/{}", competition_id.0)))
+}
+
+#[derive(Debug, FromForm)]
+struct CompetitionFormValues<'v> {
+    #[field(validate = len(1..))]
+    name: &'v str,
+    aggregation: &'v str,
+    best_count: Option<i64>,
+}
+#[post("/competition/<competition_id>", data = "<form>")]
+async fn update_competition(competition_id: CompetitionId, form: Form<CompetitionFormValues<'_>>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let competition = load_competition(competition_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_competition_owner(&competition, &user)?;
+    let (aggregation, best_count) = parse_aggregation(form.aggregation, form.best_count)?;
+    sqlx::query("UPDATE competitions SET name=?, aggregation=?, best_count=? WHERE id=?")
+        .bind(form.name.trim())
+        .bind(aggregation)
+        .bind(best_count)
+        .bind(competition_id)
+        .execute(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    Ok(Redirect::to(format!("/competition/{competition_id}")))
+}
+
+#[derive(Debug, FromForm)]
+struct StageFormValues {
+    event_id: EventId,
+}
+#[post("/competition/<competition_id>/stages/add", data = "<form>")]
+async fn add_stage(competition_id: CompetitionId, form: Form<StageFormValues>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let competition = load_competition(competition_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_competition_owner(&competition, &user)?;
+    let event = load_event_info(form.event_id, gdb).await?;
+    require_event_role(&event, &user, EventRole::can_manage_event, gdb).await?;
+    let res = sqlx::query("UPDATE events SET competition_id=? WHERE id=? AND competition_id IS NULL")
+        .bind(competition_id)
+        .bind(event.id)
+        .execute(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    if res.rows_affected() == 0 {
+        return Err(Custom(Status::Conflict, format!("Event id: {} is already stage of a competition", event.id)));
+    }
+    Ok(Redirect::to(format!("/competition/{competition_id}")))
+}
+
+#[post("/competition/<competition_id>/stages/remove", data = "<form>")]
+async fn remove_stage(competition_id: CompetitionId, form: Form<StageFormValues>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let competition = load_competition(competition_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    let event = load_event_info(form.event_id, gdb).await?;
+    // event owner can always take the event out of competition
+    if competition.owner != user.email {
+        require_event_role(&event, &user, EventRole::can_manage_event, gdb).await?;
+    }
+    sqlx::query("UPDATE events SET competition_id=NULL WHERE id=? AND competition_id=?")
+        .bind(event.id)
+        .bind(competition_id)
+        .execute(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    Ok(Redirect::to(format!("/competition/{competition_id}")))
+}
+
+#[post("/competition/<competition_id>/delete")]
+async fn delete_competition(competition_id: CompetitionId, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let competition = load_competition(competition_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_competition_owner(&competition, &user)?;
+    let mut tx = gdb.0.begin().await.map_err(sqlx_to_custom_error)?;
+    sqlx::query("UPDATE events SET competition_id=NULL WHERE competition_id=?")
+        .bind(competition_id)
+        .execute(&mut *tx)
+        .await.map_err(sqlx_to_custom_error)?;
+    sqlx::query("DELETE FROM competitions WHERE id=?")
+        .bind(competition_id)
+        .execute(&mut *tx)
+        .await.map_err(sqlx_to_custom_error)?;
+    tx.commit().await.map_err(sqlx_to_custom_error)?;
+    info!("User {} deleted competition id: {competition_id}, name: {}", user.email, competition.name);
+    Ok(Redirect::to("/"))
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket.mount("/", routes![
+            get_competition,
+            get_api_competition_results,
+            post_competition,
+            update_competition,
+            add_stage,
+            remove_stage,
+            delete_competition,
+        ])
+}
+
+#[cfg(test)]
+fn test_run(registration: &str, time_sec: Option<i64>) -> RunsRecord {
+    let start_time = QxDateTime::parse_from_iso("2025-06-01T10:00:00+02:00").unwrap();
+    let finish_time = time_sec.and_then(|sec| start_time.0.checked_add_signed(chrono::TimeDelta::seconds(sec))).map(QxDateTime);
+    RunsRecord {
+        registration: Some(registration.to_string()),
+        last_name: Some(registration.to_string()),
+        start_time: Some(start_time),
+        finish_time,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_aggregate_time_sum() {
+    let stages = vec![
+        vec![test_run("A", Some(100)), test_run("B", Some(120)), test_run("C", Some(90))],
+        vec![test_run("A", Some(100)), test_run("B", Some(80)), test_run("C", None)],
+    ];
+    let results = aggregate_results(&stages, Aggregation::TimeSum, None);
+    let order = results.iter().map(|r| (r.registration.as_str(), r.position, r.total)).collect::<Vec<_>>();
+    assert_eq!(order, vec![
+        ("A", Some(1), Some(200_000)),
+        ("B", Some(1), Some(200_000)),
+        ("C", None, None),
+    ]);
+}
+
+#[test]
+fn test_aggregate_points() {
+    let stages = vec![
+        vec![test_run("A", Some(100)), test_run("B", Some(200))],
+        vec![test_run("A", None), test_run("B", Some(50)), test_run("", Some(10))],
+        vec![test_run("A", Some(100)), test_run("B", Some(400))],
+    ];
+    let results = aggregate_results(&stages, Aggregation::Points, None);
+    let order = results.iter().map(|r| (r.registration.as_str(), r.position, r.total)).collect::<Vec<_>>();
+    // run without registration sets stage winner time but is not classified
+    assert_eq!(order, vec![
+        ("A", Some(1), Some(200)),
+        ("B", Some(2), Some(50 + 20 + 25)),
+    ]);
+
+    let results = aggregate_results(&stages, Aggregation::BestOf, Some(1));
+    let order = results.iter().map(|r| (r.registration.as_str(), r.position, r.total)).collect::<Vec<_>>();
+    assert_eq!(order, vec![
+        ("A", Some(1), Some(100)),
+        ("B", Some(2), Some(50)),
+    ]);
+}
@@ -0,0 +1,121 @@
+use std::io::Cursor;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::form::Form;
+use rocket::http::uri::Origin;
+use rocket::http::{Cookie, Method, SameSite, Status};
+use rocket::response::status::Custom;
+use rocket::{Build, Data, Request, Response, Rocket};
+use crate::auth::{generate_random_string, QX_SESSION_ID};
+
+pub const CSRF_COOKIE: &str = "qx_csrf";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+// hidden form field, it is inserted as the first form field by static/js/csrf.js
+pub const CSRF_FORM_FIELD: &str = "csrf_token";
+const CSRF_META: &str = "csrf-token";
+const CSRF_ERROR_PATH: &str = "/csrf-error";
+// form data bytes searched for the CSRF form field
+const FORM_PEEK_LEN: usize = 512;
+
+#[derive(Default, Clone, Debug)]
+struct CsrfToken(String);
+
+/// CSRF protection using the synchronizer token pattern.
+/// Token is stored in a cookie and injected as a `<meta>` tag into every rendered HTML page.
+/// State changing requests authorized by the session cookie must send the token back
+/// in the `X-CSRF-Token` header or in the `csrf_token` form field, otherwise they are rejected with 403.
+/// Requests authorized by API token are not affected, since browsers do not send them automatically.
+pub struct CsrfFairing;
+
+fn csrf_cookie_value(request: &Request<'_>) -> Option<String> {
+    let cookies = request.cookies();
+    let cookie = if cfg!(test) {
+        cookies.get(CSRF_COOKIE).cloned()
+    } else {
+        cookies.get_private(CSRF_COOKIE)
+    };
+    cookie.map(|cookie| cookie.value().to_string())
+}
+
+async fn submitted_token(request: &Request<'_>, data: &mut Data<'_>) -> Option<String> {
+    if let Some(token) = request.headers().get_one(CSRF_HEADER) {
+        return Some(token.to_string());
+    }
+    if request.content_type().is_some_and(|ct| ct.is_form()) {
+        let peek = data.peek(FORM_PEEK_LEN).await;
+        let form_data = std::str::from_utf8(peek).ok()?;
+        return Form::values(form_data)
+            .find(|field| field.name == CSRF_FORM_FIELD)
+            .map(|field| field.value.to_string());
+    }
+    None
+}
+
+fn is_safe_method(method: Method) -> bool {
+    matches!(method, Method::Get | Method::Head | Method::Options)
+}
+
+#[rocket::async_trait]
+impl Fairing for CsrfFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "CSRF protection",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        let token = match csrf_cookie_value(request) {
+            Some(token) => token,
+            None => {
+                let token = generate_random_string(32);
+                let cookie = Cookie::build((CSRF_COOKIE, token.clone()))
+                    .same_site(SameSite::Strict)
+                    .http_only(true);
+                if cfg!(test) {
+                    request.cookies().add(cookie);
+                } else {
+                    request.cookies().add_private(cookie);
+                }
+                token
+            }
+        };
+        request.local_cache(|| CsrfToken(token.clone()));
+
+        if is_safe_method(request.method())
+            || request.cookies().get(QX_SESSION_ID).is_none()
+            || request.headers().contains("qx-api-token") {
+            return;
+        }
+        if submitted_token(request, data).await.as_ref() != Some(&token) {
+            warn!("CSRF token mismatch, {} {}", request.method(), request.uri());
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(CSRF_ERROR_PATH).expect("valid URI"));
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !response.content_type().is_some_and(|ct| ct.is_html()) {
+            return;
+        }
+        let token = &request.local_cache(CsrfToken::default).0;
+        if token.is_empty() {
+            return;
+        }
+        let Ok(body) = response.body_mut().to_string().await else {
+            return;
+        };
+        let body = body.replacen("</head>", &format!("<meta name=\"{CSRF_META}\" content=\"{token}\">\n</head>"), 1);
+        response.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+#[get("/csrf-error")]
+fn csrf_error() -> Custom<&'static str> {
+    Custom(Status::Forbidden, "Invalid or missing CSRF token")
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket
+        .attach(CsrfFairing)
+        .mount("/", routes![csrf_error])
+}
@@ -6,6 +6,8 @@ use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
 use anyhow::{anyhow};
+use crate::apitoken::hash_plaintext_api_tokens;
+use crate::trash::{purge_trash, trash_orphan_event_dbs, DEFAULT_RETENTION_DAYS};
 use crate::event::EventId;
 use crate::{OpenEvent, SharedQxState};
 
@@ -125,6 +127,17 @@ impl Fairing for DbPoolFairing {
                 return Err(rocket);
             }
         };
+        if let Err(err) = hash_plaintext_api_tokens(&pool).await {
+            error!("Hash plaintext API tokens error: {:?}", err);
+            return Err(rocket);
+        }
+        let retention_days = figment.extract_inner::<i64>("trash_retention_days").unwrap_or(DEFAULT_RETENTION_DAYS);
+        if let Err(err) = trash_orphan_event_dbs(&db_path, &pool).await {
+            error!("Move orphan event DB files to trash error: {:?}", err);
+        }
+        if let Err(err) = purge_trash(&db_path, retention_days, &pool).await {
+            error!("Purge trash error: {:?}", err);
+        }
 
         Ok(rocket.manage(DbPool(pool)))
     }
@@ -152,9 +165,7 @@ pub async fn get_event_db(event_id: EventId, state: &State<SharedQxState>) -> an
 }
 
 async fn open_db(db_path: &str, schema_name: &str) -> anyhow::Result<SqlitePool> {
-    let database_url = if cfg!(test) {
-        "sqlite::memory:".to_string()
-    } else {
+    let database_url = {
         let db_path = format!("{db_path}/{schema_name}.sqlite");
         if !Path::new(&db_path).exists() {
             // info!("creating database: {database_url}");
@@ -174,7 +185,7 @@ async fn open_db(db_path: &str, schema_name: &str) -> anyhow::Result<SqlitePool>
     Ok(pool)
 }
 
-fn event_id_to_schema_name(event_id: EventId) -> String {
+pub(crate) fn event_id_to_schema_name(event_id: EventId) -> String {
     format!("ev{event_id:0>4}")
 }
 
@@ -0,0 +1,330 @@
+use chrono::NaiveDateTime;
+use rocket::form::Form;
+use rocket::http::{ContentType, Status};
+use rocket::response::Redirect;
+use rocket::response::status::Custom;
+use rocket::serde::Serialize;
+use rocket::{Build, Rocket, State};
+use rocket_dyn_templates::{context, Template};
+use serde_json::json;
+use sqlx::{FromRow, SqlitePool};
+use crate::access::{EventRead, Runs};
+use crate::audit::{audit, summary, Actor, ENTRIES_CLOSE, ENTRIES_OPEN, ENTRY_CANCEL, ENTRY_CREATE};
+use crate::db::{get_event_db, DbPool};
+use crate::event::{load_event_info, user_info, user_info_opt, EventId, EventRecord};
+use crate::iofxml3::entrylist::{EntryClass, EntryControlCard, EntryList, EntryOrganisation, EntryPerson, EntryPersonId, EntryPersonName, PersonEntry};
+use crate::members::{event_role, require_event_role, EventRole};
+use crate::qxdatetime::QxDateTime;
+use crate::runs::ClassesRecord;
+use crate::util::{anyhow_to_custom_error, empty_string_to_none, sqlx_to_custom_error};
+use crate::{MaybeSessionId, QxSessionId, SharedQxState};
+
+/// Status of run created by online entry, until QuickEvent imports the entry list
+pub const ENTRY_PENDING: &str = "EntryPending";
+
+#[derive(Serialize, FromRow, Clone, Debug)]
+struct EntryClassRecord {
+    class_name: String,
+    deadline: QxDateTime,
+    max_entries: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+struct EntryClassView {
+    class_name: String,
+    deadline: QxDateTime,
+    max_entries: Option<i64>,
+    // runs in class, including the ones not entered online
+    entry_count: i64,
+    is_open: bool,
+}
+
+#[derive(Serialize, FromRow, Clone, Debug)]
+struct EntryRecord {
+    id: i64,
+    run_id: i64,
+    class_name: Option<String>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    registration: Option<String>,
+    si_id: Option<i64>,
+    club: Option<String>,
+    entered_by: String,
+    created: QxDateTime,
+}
+
+#[derive(Serialize, Debug)]
+struct EntryView {
+    #[serde(flatten)]
+    entry: EntryRecord,
+    can_cancel: bool,
+}
+
+async fn load_entry_classes(edb: &SqlitePool) -> Result<Vec<EntryClassView>, Custom<String>> {
+    let entry_classes: Vec<EntryClassRecord> = sqlx::query_as("SELECT * FROM entry_classes ORDER BY class_name")
+        .fetch_all(edb).await.map_err(sqlx_to_custom_error)?;
+    let now = QxDateTime::now();
+    let mut views = Vec::with_capacity(entry_classes.len());
+    for ec in entry_classes {
+        let (entry_count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM runs WHERE class_name=?")
+            .bind(&ec.class_name)
+            .fetch_one(edb).await.map_err(sqlx_to_custom_error)?;
+        let is_open = ec.deadline.0 > now.0 && ec.max_entries.is_none_or(|max| entry_count < max);
+        views.push(EntryClassView {
+            class_name: ec.class_name,
+            deadline: ec.deadline,
+            max_entries: ec.max_entries,
+            entry_count,
+            is_open,
+        });
+    }
+    Ok(views)
+}
+
+async fn load_entries(edb: &SqlitePool) -> Result<Vec<EntryRecord>, Custom<String>> {
+    sqlx::query_as("SELECT entries.id, entries.run_id, runs.class_name, runs.first_name, runs.last_name, runs.registration, runs.si_id,
+                        entries.club, entries.entered_by, entries.created
+                 FROM entries JOIN runs ON runs.run_id=entries.run_id
+                 ORDER BY runs.class_name, runs.last_name, runs.first_name")
+        .fetch_all(edb).await.map_err(sqlx_to_custom_error)
+}
+
+/// Deadline from `datetime-local` input, seconds are optional, time without UTC offset is in event time zone
+fn parse_deadline(deadline: &str, event: &EventRecord) -> Result<QxDateTime, Custom<String>> {
+    let tz = event.tz();
+    QxDateTime::parse_from_string(deadline, Some(&tz)).ok()
+        .or_else(|| NaiveDateTime::parse_from_str(deadline, "%Y-%m-%dT%H:%M").ok()
+            .and_then(|dt| QxDateTime::from_local_timezone(dt, &tz)))
+        .ok_or_else(|| Custom(Status::BadRequest, format!("Invalid entry deadline: {deadline}")))
+}
+
+#[get("/event/<event_id>/entries")]
+async fn get_entries(event_id: EventId, session_id: MaybeSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info_opt(session_id.0.as_ref(), state).await.map_err(anyhow_to_custom_error)?;
+    let role = event_role(&event, user.as_ref(), gdb).await?;
+    let can_edit_event = role.is_some_and(|r| r.can_edit_event());
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    let classes = sqlx::query_as::<_, ClassesRecord>("SELECT * FROM classes ORDER BY name")
+        .fetch_all(&edb).await.map_err(sqlx_to_custom_error)?;
+    let entry_classes = load_entry_classes(&edb).await?;
+    // entered runners are visible as the start list is
+    let entries = if event.runs_public || role.is_some() {
+        load_entries(&edb).await?
+    } else if let Some(user) = &user {
+        load_entries(&edb).await?.into_iter().filter(|e| e.entered_by == user.email).collect()
+    } else {
+        vec![]
+    };
+    let entries = entries.into_iter()
+        .map(|entry| {
+            let is_open = entry_classes.iter().any(|ec| Some(&ec.class_name) == entry.class_name.as_ref() && ec.deadline.0 > QxDateTime::now().0);
+            let is_own = user.as_ref().is_some_and(|user| user.email == entry.entered_by);
+            EntryView { can_cancel: can_edit_event || (is_own && is_open), entry }
+        })
+        .collect::<Vec<_>>();
+    let deadline_default = QxDateTime::now_in_time_zone(&event.tz()).0.format("%Y-%m-%dT%H:%M").to_string();
+    Ok(Template::render("entries", context! {
+        user,
+        event,
+        can_edit_event,
+        classes,
+        entry_classes,
+        entries,
+        deadline_default,
+    }))
+}
+
+#[derive(Debug, FromForm)]
+struct EntryClassFormValues<'r> {
+    class_name: &'r str,
+    deadline: &'r str,
+    max_entries: Option<i64>,
+}
+#[post("/event/<event_id>/entries/classes", data = "<form>")]
+async fn post_entry_class(event_id: EventId, form: Form<EntryClassFormValues<'_>>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_edit_event, gdb).await?;
+    let deadline = parse_deadline(form.deadline, &event)?;
+    if form.max_entries.is_some_and(|max| max < 0) {
+        return Err(Custom(Status::BadRequest, "Entry limit must not be negative".to_string()));
+    }
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    let (class_count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM classes WHERE name=?")
+        .bind(form.class_name)
+        .fetch_one(&edb).await.map_err(sqlx_to_custom_error)?;
+    if class_count == 0 {
+        return Err(Custom(Status::BadRequest, format!("Class {} not found", form.class_name)));
+    }
+    sqlx::query("INSERT OR REPLACE INTO entry_classes (class_name, deadline, max_entries) VALUES (?, ?, ?)")
+        .bind(form.class_name)
+        .bind(deadline)
+        .bind(form.max_entries)
+        .execute(&edb).await.map_err(sqlx_to_custom_error)?;
+    audit(event_id, &Actor::from(&user), ENTRIES_OPEN, form.class_name, None,
+          Some(json!({"deadline": deadline, "max_entries": form.max_entries}).to_string()), &gdb.0).await
+        .map_err(anyhow_to_custom_error)?;
+    Ok(Redirect::to(format!("/event/{event_id}/entries")))
+}
+
+#[derive(Debug, FromForm)]
+struct CloseEntryClassFormValues<'r> {
+    class_name: &'r str,
+}
+#[post("/event/<event_id>/entries/classes/close", data = "<form>")]
+async fn post_close_entry_class(event_id: EventId, form: Form<CloseEntryClassFormValues<'_>>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_edit_event, gdb).await?;
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    sqlx::query("DELETE FROM entry_classes WHERE class_name=?")
+        .bind(form.class_name)
+        .execute(&edb).await.map_err(sqlx_to_custom_error)?;
+    audit(event_id, &Actor::from(&user), ENTRIES_CLOSE, form.class_name, None, None, &gdb.0).await
+        .map_err(anyhow_to_custom_error)?;
+    Ok(Redirect::to(format!("/event/{event_id}/entries")))
+}
+
+#[derive(Debug, FromForm)]
+struct EntryFormValues<'r> {
+    class_name: &'r str,
+    first_name: &'r str,
+    last_name: &'r str,
+    registration: &'r str,
+    si_id: Option<i64>,
+    club: &'r str,
+}
+#[post("/event/<event_id>/entries", data = "<form>")]
+async fn post_entry(event_id: EventId, form: Form<EntryFormValues<'_>>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    let first_name = form.first_name.trim();
+    let last_name = form.last_name.trim();
+    if first_name.is_empty() || last_name.is_empty() {
+        return Err(Custom(Status::BadRequest, "First and last name must be entered".to_string()));
+    }
+    let registration = empty_string_to_none(form.registration.trim());
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    // write lock is taken at once, so that concurrent entries cannot pass the class size and duplicate checks together
+    let mut tx = edb.begin_with("BEGIN IMMEDIATE").await.map_err(sqlx_to_custom_error)?;
+    let entry_class: EntryClassRecord = sqlx::query_as("SELECT * FROM entry_classes WHERE class_name=?")
+        .bind(form.class_name)
+        .fetch_optional(&mut *tx).await.map_err(sqlx_to_custom_error)?
+        .ok_or_else(|| Custom(Status::BadRequest, format!("Entries to class {} are not open", form.class_name)))?;
+    if entry_class.deadline.0 <= QxDateTime::now().0 {
+        return Err(Custom(Status::Forbidden, format!("Entry deadline of class {} is over", form.class_name)));
+    }
+    let (entry_count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM runs WHERE class_name=?")
+        .bind(form.class_name)
+        .fetch_one(&mut *tx).await.map_err(sqlx_to_custom_error)?;
+    if entry_class.max_entries.is_some_and(|max| entry_count >= max) {
+        return Err(Custom(Status::Conflict, format!("Class {} is full", form.class_name)));
+    }
+    if let Some(registration) = &registration {
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM runs WHERE registration=?")
+            .bind(registration)
+            .fetch_one(&mut *tx).await.map_err(sqlx_to_custom_error)?;
+        if count > 0 {
+            return Err(Custom(Status::Conflict, format!("Runner {registration} is already entered")));
+        }
+    }
+    // entries get negative run IDs, positive ones are assigned by QuickEvent
+    let (run_id,): (i64,) = sqlx::query_as("SELECT MIN(IFNULL(MIN(run_id), 0), 0) - 1 FROM runs")
+        .fetch_one(&mut *tx).await.map_err(sqlx_to_custom_error)?;
+    sqlx::query("INSERT INTO runs (run_id, class_name, first_name, last_name, registration, si_id, status) VALUES (?, ?, ?, ?, ?, ?, ?)")
+        .bind(run_id)
+        .bind(form.class_name)
+        .bind(first_name)
+        .bind(last_name)
+        .bind(&registration)
+        .bind(form.si_id)
+        .bind(ENTRY_PENDING)
+        .execute(&mut *tx).await.map_err(sqlx_to_custom_error)?;
+    let club = empty_string_to_none(form.club.trim());
+    let (entry_id,): (i64,) = sqlx::query_as("INSERT INTO entries (run_id, club, entered_by, created) VALUES (?, ?, ?, ?) RETURNING id")
+        .bind(run_id)
+        .bind(&club)
+        .bind(&user.email)
+        .bind(QxDateTime::now().trimmed_to_sec())
+        .fetch_one(&mut *tx).await.map_err(sqlx_to_custom_error)?;
+    tx.commit().await.map_err(sqlx_to_custom_error)?;
+    audit(event_id, &Actor::from(&user), ENTRY_CREATE, &format!("entry:{entry_id}"), None,
+          Some(json!({"run_id": run_id, "class_name": form.class_name, "first_name": first_name, "last_name": last_name,
+              "registration": registration, "si_id": form.si_id, "club": club}).to_string()), &gdb.0).await
+        .map_err(anyhow_to_custom_error)?;
+    info!("User {} entered {last_name} {first_name} to class {}, event id: {event_id}", user.email, form.class_name);
+    Ok(Redirect::to(format!("/event/{event_id}/entries")))
+}
+
+#[post("/event/<event_id>/entries/<entry_id>/cancel")]
+async fn post_cancel_entry(event_id: EventId, entry_id: i64, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    let can_edit_event = event_role(&event, Some(&user), gdb).await?.is_some_and(|r| r.can_edit_event());
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    let entry = load_entries(&edb).await?.into_iter()
+        .find(|e| e.id == entry_id)
+        .ok_or_else(|| Custom(Status::NotFound, format!("Entry id: {entry_id} not found")))?;
+    if !can_edit_event {
+        if entry.entered_by != user.email {
+            return Err(Custom(Status::Forbidden, "Only the user who entered the runner can cancel the entry".to_string()));
+        }
+        let deadline: Option<(QxDateTime,)> = sqlx::query_as("SELECT deadline FROM entry_classes WHERE class_name=?")
+            .bind(&entry.class_name)
+            .fetch_optional(&edb).await.map_err(sqlx_to_custom_error)?;
+        if deadline.is_none_or(|(deadline,)| deadline.0 <= QxDateTime::now().0) {
+            return Err(Custom(Status::Forbidden, "Entries are closed, ask the organizer to cancel the entry".to_string()));
+        }
+    }
+    let mut tx = edb.begin().await.map_err(sqlx_to_custom_error)?;
+    sqlx::query("DELETE FROM entries WHERE id=?")
+        .bind(entry.id)
+        .execute(&mut *tx).await.map_err(sqlx_to_custom_error)?;
+    // run is kept once QuickEvent took it over
+    sqlx::query("DELETE FROM runs WHERE run_id=? AND status=?")
+        .bind(entry.run_id)
+        .bind(ENTRY_PENDING)
+        .execute(&mut *tx).await.map_err(sqlx_to_custom_error)?;
+    tx.commit().await.map_err(sqlx_to_custom_error)?;
+    audit(event_id, &Actor::from(&user), ENTRY_CANCEL, &format!("entry:{entry_id}"), summary(&entry), None, &gdb.0).await
+        .map_err(anyhow_to_custom_error)?;
+    info!("User {} canceled entry id: {entry_id}, event id: {event_id}", user.email);
+    Ok(Redirect::to(format!("/event/{event_id}/entries")))
+}
+
+/// Entry list in IOF XML 3.0 format to be imported by QuickEvent
+#[get("/api/event/<event_id>/entries/iofxml3")]
+async fn get_entry_list_iofxml3(event_id: EventId, _access: EventRead<Runs>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<(ContentType, String), Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    let person_entry = load_entries(&edb).await?.into_iter()
+        .map(|entry| PersonEntry {
+            id: entry.id,
+            person: EntryPerson {
+                id: entry.registration.map(|registration| EntryPersonId { id_type: "CZE".to_string(), text: registration }),
+                name: EntryPersonName {
+                    family: entry.last_name.unwrap_or_default(),
+                    given: entry.first_name.unwrap_or_default(),
+                },
+            },
+            organisation: entry.club.map(|name| EntryOrganisation { name }),
+            control_card: entry.si_id.map(|si_id| EntryControlCard { punching_system: "SI".to_string(), text: si_id.to_string() }),
+            class: EntryClass { name: entry.class_name.unwrap_or_default() },
+            entry_time: entry.created.to_iso_string(),
+        })
+        .collect();
+    let xml = EntryList::new(&event.name, &event.start_time, person_entry).to_xml().map_err(anyhow_to_custom_error)?;
+    Ok((ContentType::XML, xml))
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket.mount("/", routes![
+            get_entries,
+            post_entry_class,
+            post_close_entry_class,
+            post_entry,
+            post_cancel_entry,
+            get_entry_list_iofxml3,
+        ])
+}
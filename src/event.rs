@@ -4,25 +4,33 @@ use rocket::serde::json::Json;
 use std::io::{Read};
 use anyhow::anyhow;
 use rocket::form::{Contextual, Form};
-use rocket::http::{Status};
+use rocket::http::{RawStr, Status};
 use rocket::response::{Redirect};
 use rocket::response::status::Custom;
-use rocket::{Build, Rocket, State};
+use rocket::{Build, Either, Rocket, State};
 use rocket_dyn_templates::{context, Template};
-use sqlx::{query, query_as, FromRow, SqlitePool};
+use sqlx::{query, query_as, Encode, FromRow, Sqlite, SqlitePool};
+use sqlx::sqlite::SqliteArgumentValue;
 use crate::db::{get_event_db, DbPool};
-use crate::{files, MaybeSessionId, QxApiToken, QxSessionId, SharedQxState};
-use crate::auth::{generate_random_string, UserInfo};
-use chrono::{DateTime, FixedOffset};
+use crate::{files, impl_sqlx_json_text_type_encode_decode, MaybeSessionId, QxApiToken, QxSessionId, SharedQxState};
+use crate::access::{EventRead, Runs};
+use crate::apitoken::{create_api_token, generate_api_token, render_created_api_token, resolve_api_token, ApiScope, ApiScopes};
+use crate::audit::{audit, summary, Actor, EVENT_CREATE, EVENT_DELETE, EVENT_UPDATE};
+use crate::auth::UserInfo;
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use chrono_tz::Tz;
 use rocket::serde::{Deserialize, Serialize};
 use log::info;
 use serde_json::Value;
 use crate::changes::{ChangesRecord, PENDING, RUN_UPDATE_REQUEST};
-use crate::files::{load_file_from_db, save_file_to_db};
+use crate::competition::load_event_competition;
+use crate::files::{load_file_from_db, save_file_to_db, FileInfo};
 use crate::iofxml3::parser::parse_startlist_xml_data;
-use crate::qxdatetime::QxDateTime;
+use crate::members::{event_role, require_event_role, EventRole};
+use crate::qxdatetime::{parse_time_zone, QxDateTime};
 use crate::runs::{ClassesRecord, RunsRecord};
-use crate::util::{anyhow_to_custom_error, create_qrc, from_csv_json, sqlx_to_anyhow, sqlx_to_custom_error, string_to_custom_error};
+use crate::trash::{insert_deleted_event, remove_event_db, trash_event_db};
+use crate::util::{anyhow_to_custom_error, create_qrc, empty_string_to_none, from_csv_json, markdown_to_html, sqlx_to_anyhow, sqlx_to_custom_error};
 
 pub const START_LIST_IOFXML3_FILE: &str = "startlist-iof3.xml";
 pub const RUNS_CSV_JSON_FILE: &str = "runs.csv.json";
@@ -31,6 +39,70 @@ pub const RUNS_CSV_JSON_FILE: &str = "runs.csv.json";
 pub type SiId = i64;
 pub type EventId = i64;
 
+pub const DEFAULT_TIME_ZONE: &str = "Europe/Prague";
+fn default_time_zone() -> String {
+    DEFAULT_TIME_ZONE.to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct EventLink {
+    pub title: String,
+    pub url: String,
+}
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct EventLinks(pub Vec<EventLink>);
+impl_sqlx_json_text_type_encode_decode!(EventLinks);
+impl EventLinks {
+    /// Parse lines `title | url`, title is optional
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut links = vec![];
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (title, url) = match line.rsplit_once('|') {
+                Some((title, url)) => (title.trim(), url.trim()),
+                None => (line, line),
+            };
+            if !(url.starts_with("https://") || url.starts_with("http://")) {
+                return Err(format!("Invalid link URL: {url}, it must start with https:// or http://"));
+            }
+            links.push(EventLink { title: title.to_string(), url: url.to_string() });
+        }
+        Ok(Self(links))
+    }
+    fn to_text(&self) -> String {
+        self.0.iter().map(|link| format!("{} | {}", link.title, link.url)).collect::<Vec<_>>().join("\n")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct EventContact {
+    pub name: String,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+}
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct EventContacts(pub Vec<EventContact>);
+impl_sqlx_json_text_type_encode_decode!(EventContacts);
+impl EventContacts {
+    /// Parse lines `name | email | phone`, email and phone are optional
+    fn parse(text: &str) -> Self {
+        let contacts = text.lines().map(str::trim).filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut parts = line.split('|').map(str::trim);
+                let name = parts.next().unwrap_or_default().to_string();
+                let email = parts.next().and_then(empty_string_to_none);
+                let phone = parts.next().and_then(empty_string_to_none);
+                EventContact { name, email, phone }
+            })
+            .collect();
+        Self(contacts)
+    }
+    fn to_text(&self) -> String {
+        self.0.iter()
+            .map(|c| format!("{} | {} | {}", c.name, c.email.as_deref().unwrap_or_default(), c.phone.as_deref().unwrap_or_default()))
+            .collect::<Vec<_>>().join("\n")
+    }
+}
+
 #[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
 pub struct EventRecord {
     pub id: EventId,
@@ -39,12 +111,34 @@ pub struct EventRecord {
     pub stage_count: i64,
     pub place: String,
     pub start_time: QxDateTime,
+    // IANA time zone name, times without UTC offset are considered to be in this zone
+    #[serde(default = "default_time_zone")]
+    pub time_zone: String,
     pub owner: String,
-    api_token: QxApiToken,
+    // event resources readable without authorization
+    pub files_public: bool,
+    pub runs_public: bool,
+    pub changes_public: bool,
+    // organizing club
+    #[serde(default)]
+    pub organizer: String,
+    // Markdown, rendered sanitized on event page
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub links: EventLinks,
+    // GPS coordinates of the event centre
+    #[serde(default)]
+    pub latitude: Option<f64>,
+    #[serde(default)]
+    pub longitude: Option<f64>,
+    #[serde(default)]
+    pub contacts: EventContacts,
 }
 impl EventRecord {
     pub fn new(owner: &str) -> Self {
-        let start_time = QxDateTime::now().trimmed_to_sec();
+        let tz = parse_time_zone(DEFAULT_TIME_ZONE).expect("valid default time zone");
+        let start_time = QxDateTime::now_in_time_zone(&tz).trimmed_to_sec();
         Self {
             id: 0,
             name: "".to_string(),
@@ -52,11 +146,23 @@ impl EventRecord {
             stage_count: 1,
             place: "".to_string(),
             start_time,
-            // time_zone: "Europe/Prague".to_string(),
+            time_zone: DEFAULT_TIME_ZONE.to_string(),
             owner: owner.to_string(),
-            api_token: QxApiToken(generate_random_string(10)),
+            files_public: true,
+            runs_public: true,
+            changes_public: true,
+            organizer: "".to_string(),
+            description: "".to_string(),
+            links: EventLinks::default(),
+            latitude: None,
+            longitude: None,
+            contacts: EventContacts::default(),
         }
     }
+    /// Event time zone, UTC if the stored name is not valid
+    pub fn tz(&self) -> Tz {
+        parse_time_zone(&self.time_zone).unwrap_or(Tz::UTC)
+    }
 }
 
 pub async fn load_event(event_id: EventId, db: &State<DbPool>) -> anyhow::Result<EventRecord> {
@@ -69,51 +175,74 @@ pub async fn load_event(event_id: EventId, db: &State<DbPool>) -> anyhow::Result
     Ok(event)
 }
 pub async fn load_event_info(event_id: EventId, db: &State<DbPool>) -> Result<EventRecord, Custom<String>> {
-    let event = load_event(event_id, db).await.map_err(anyhow_to_custom_error)?;
-    Ok(event)
-}
-pub async fn load_event_info_for_api_token(qx_api_token: &QxApiToken, db: &State<DbPool>) -> Result<EventRecord, Custom<String>> {
-    let pool = &db.0;
-    let event: EventRecord = sqlx::query_as("SELECT * FROM events WHERE api_token=?")
-        .bind(&qx_api_token.0)
-        .fetch_one(pool)
+    let event: Option<EventRecord> = sqlx::query_as("SELECT * FROM events WHERE id=?")
+        .bind(event_id)
+        .fetch_optional(&db.0)
         .await
-        .map_err(|e| {
-            warn!("Unauthorized request for api token: {}, error: {}", qx_api_token.0, e);
-            Custom(Status::Unauthorized, e.to_string())
-        })?;
-    Ok(event)
+        .map_err(sqlx_to_custom_error)?;
+    event.ok_or_else(|| Custom(Status::NotFound, format!("Event id: {event_id} not found")))
 }
-pub(crate) async fn save_event(event: &EventRecord, db: &State<DbPool>) -> anyhow::Result<EventId> {
+pub async fn load_event_info_for_api_token(qx_api_token: &QxApiToken, scope: ApiScope, db: &State<DbPool>) -> Result<EventRecord, Custom<String>> {
+    if !qx_api_token.scopes.contains(scope) {
+        return Err(Custom(Status::Forbidden, format!("API token '{}' is missing scope: {scope}", qx_api_token.name)));
+    }
+    load_event_info(qx_api_token.event_id, db).await
+}
+pub(crate) async fn save_event(event: &EventRecord, actor: &Actor, db: &State<DbPool>) -> anyhow::Result<EventId> {
+    let mut tx = db.0.begin().await.map_err(sqlx_to_anyhow)?;
     let id = if event.id > 0 {
-        query("UPDATE events SET name=?, place=?, stage=?, stage_count=?, start_time=? WHERE id=?")
+        let old_event = load_event(event.id, db).await?;
+        query("UPDATE events SET name=?, place=?, stage=?, stage_count=?, start_time=?, time_zone=?, files_public=?, runs_public=?, changes_public=?,
+                      organizer=?, description=?, links=?, latitude=?, longitude=?, contacts=?, ical_sequence=ical_sequence+1 WHERE id=?")
             .bind(&event.name)
             .bind(&event.place)
             .bind(event.stage)
             .bind(event.stage_count)
             .bind(event.start_time.0)
-            // .bind(&event.time_zone)
+            .bind(&event.time_zone)
+            .bind(event.files_public)
+            .bind(event.runs_public)
+            .bind(event.changes_public)
+            .bind(&event.organizer)
+            .bind(&event.description)
+            .bind(&event.links)
+            .bind(event.latitude)
+            .bind(event.longitude)
+            .bind(&event.contacts)
             .bind(event.id)
-            .execute(&db.0)
+            .execute(&mut *tx)
             .await.map_err(|e| anyhow!("{e}"))?;
+        audit(event.id, actor, EVENT_UPDATE, &event.name, summary(&old_event), summary(event), &mut *tx).await?;
         event.id
     } else {
         let id: (i64, ) = query_as(
-            "INSERT INTO events(name, place, stage, stage_count, start_time, api_token, owner)
-                 VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING id"
+            "INSERT INTO events(name, place, stage, stage_count, start_time, time_zone, owner, files_public, runs_public, changes_public,
+                                organizer, description, links, latitude, longitude, contacts)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id"
         )
             .bind(&event.name)
             .bind(&event.place)
             .bind(event.stage)
             .bind(event.stage_count)
             .bind(event.start_time.0)
-            .bind(&event.api_token.0)
+            .bind(&event.time_zone)
             .bind(&event.owner)
-            .fetch_one(&db.0)
+            .bind(event.files_public)
+            .bind(event.runs_public)
+            .bind(event.changes_public)
+            .bind(&event.organizer)
+            .bind(&event.description)
+            .bind(&event.links)
+            .bind(event.latitude)
+            .bind(event.longitude)
+            .bind(&event.contacts)
+            .fetch_one(&mut *tx)
             .await.map_err(|e| anyhow!("{e}"))?;
         info!("Event created, id: {}", id.0);
+        audit(id.0, actor, EVENT_CREATE, &event.name, None, summary(&EventRecord { id: id.0, ..event.clone() }), &mut *tx).await?;
         id.0
     };
+    tx.commit().await.map_err(sqlx_to_anyhow)?;
     Ok(id)
 }
 #[derive(Debug, FromForm)]
@@ -124,21 +253,40 @@ struct EventFormValues<'v> {
     stage: i64,
     stage_count: i64,
     start_time: &'v str,
+    time_zone: &'v str,
+    files_public: bool,
+    runs_public: bool,
+    changes_public: bool,
+    organizer: Option<&'v str>,
+    description: Option<&'v str>,
+    links: Option<&'v str>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    contacts: Option<&'v str>,
     // #[field(validate = len(1..))]
     // owner: &'v str,
-    #[field(validate = len(10..))]
-    api_token: &'v str,
 }
 // NOTE: We use `Contextual` here because we want to collect all submitted form
 // fields to re-render forms with submitted values on error. If you have no such
 // need, do not use `Contextual`. Use the equivalent of `Form<Submit<'_>>`.
 #[post("/event", data = "<form>")]
-async fn post_event<'r>(form: Form<Contextual<'r, EventFormValues<'r>>>, session_id: QxSessionId, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+async fn post_event<'r>(form: Form<Contextual<'r, EventFormValues<'r>>>, session_id: QxSessionId, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<Either<Redirect, Template>, Custom<String>> {
     let user = user_info(&session_id, state).await?;
     let vals = form.value.as_ref().ok_or(Custom(Status::BadRequest, "Form data invalid".to_string()))?;
-    let start_time = QxDateTime::parse_from_iso(vals.start_time)
+    let tz = parse_time_zone(vals.time_zone).map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+    // start time without UTC offset is local time of the event time zone
+    let start_time = QxDateTime::parse_from_string(vals.start_time, Some(&tz))
         .map_err(|e| Custom(Status::BadRequest, format!("Unrecognized date-time string: {}, error: {e}", vals.start_time)))?;
+    let time_zone = tz.name().to_string();
+    let links = EventLinks::parse(vals.links.unwrap_or_default()).map_err(|e| Custom(Status::BadRequest, e))?;
+    let contacts = EventContacts::parse(vals.contacts.unwrap_or_default());
+    if vals.latitude.is_some_and(|lat| !(-90.0..=90.0).contains(&lat)) || vals.longitude.is_some_and(|lon| !(-180.0..=180.0).contains(&lon)) {
+        return Err(Custom(Status::BadRequest, "GPS coordinates out of range".to_string()));
+    }
+    let organizer = vals.organizer.unwrap_or_default().trim().to_string();
+    let description = vals.description.unwrap_or_default().to_string();
     let event = if vals.id == 0 {
+        // creator becomes the event owner
         EventRecord {
             id: 0,
             name: vals.name.to_string(),
@@ -146,25 +294,50 @@ async fn post_event<'r>(form: Form<Contextual<'r, EventFormValues<'r>>>, session
             stage_count: vals.stage_count,
             place: vals.place.to_string(),
             start_time,
+            time_zone,
             owner: user.email.clone(),
-            api_token: QxApiToken(vals.api_token.to_string()),
+            files_public: vals.files_public,
+            runs_public: vals.runs_public,
+            changes_public: vals.changes_public,
+            organizer,
+            description,
+            links,
+            latitude: vals.latitude,
+            longitude: vals.longitude,
+            contacts,
         }
     } else {
         let event = load_event_info(vals.id, db).await?;
+        require_event_role(&event, &user, EventRole::can_edit_event, db).await?;
         EventRecord {
             name: vals.name.to_string(),
             place: vals.place.to_string(),
             stage: vals.stage,
             stage_count: vals.stage_count,
             start_time,
+            time_zone,
+            files_public: vals.files_public,
+            runs_public: vals.runs_public,
+            changes_public: vals.changes_public,
+            organizer,
+            description,
+            links,
+            latitude: vals.latitude,
+            longitude: vals.longitude,
+            contacts,
             ..event
         }
     };
-    if event.owner != user.email {
-        return Err(Custom(Status::BadRequest, "Different event owner".to_string()))
+    let event_id = save_event(&event, &Actor::from(&user), db).await.map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+    if vals.id == 0 {
+        let token = generate_api_token();
+        let token_name = "Default";
+        create_api_token(event_id, token_name, &token, &ApiScopes(vec![ApiScope::QeSync]), None, db).await
+            .map_err(anyhow_to_custom_error)?;
+        let event = load_event_info(event_id, db).await?;
+        return Ok(Either::Right(render_created_api_token(&event, token_name, &token)?));
     }
-    let event_id = save_event(&event, db).await.map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
-    Ok(Redirect::to(format!("/event/{event_id}")))
+    Ok(Either::Left(Redirect::to(format!("/event/{event_id}"))))
 }
 pub async fn user_info(session_id: &QxSessionId, state: &State<SharedQxState>) -> Result<UserInfo, Custom<String>> {
     state.read().await
@@ -185,43 +358,176 @@ pub async fn get_user_info(session_id: &QxSessionId, state: &State<SharedQxState
     Ok(user_info)
 }
 
-pub fn is_event_owner(event: &EventRecord, user: Option<&UserInfo>) -> bool {
-    if let Some(user) = user {
-        user.email == event.owner
-    } else {
-        false
-    }
-}
-
 async fn event_edit_insert(event_id: Option<EventId>, session_id: &QxSessionId, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<Template, Custom<String>> {
     let user = get_user_info(session_id, state).await
         .and_then(|u| if let Some(u) = u {Ok(u)} else {Err(anyhow!("Invalid session ID"))})
         .map_err(anyhow_to_custom_error)?;
     let event = if let Some(event_id) = event_id {
         let event = load_event_info(event_id, db).await?;
-        if is_event_owner(&event, Some(&user)) {
-            event
-        } else {
-            return Err(Custom(Status::Unauthorized, "Event owner mismatch".to_string()))
-        }
+        require_event_role(&event, &user, EventRole::can_edit_event, db).await?;
+        // start time is edited as local time of event
+        EventRecord { start_time: event.start_time.in_time_zone(&event.tz()), ..event }
     } else {
         EventRecord::new(&user.email)
     };
-    let api_token_qrc_img_data = create_qrc(event.api_token.0.as_bytes()).map_err(anyhow_to_custom_error)?;
+    let links_text = event.links.to_text();
+    let contacts_text = event.contacts.to_text();
     Ok(Template::render("event-edit", context! {
         event_id,
         user,
         event,
-        api_token_qrc_img_data,
+        links_text,
+        contacts_text,
+        time_zones: chrono_tz::TZ_VARIANTS.iter().map(|tz| tz.name()).collect::<Vec<_>>(),
         back_link: if let Some(event_id) = event_id {format!("/event/{event_id}")} else {"/".to_string()},
     }))
 }
-async fn event_drop(event_id: EventId, db: &State<DbPool>) -> Result<(), anyhow::Error> {
+pub(crate) async fn event_drop(event_id: EventId, actor: &Actor, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<(), anyhow::Error> {
+    let event = load_event(event_id, db).await?;
+    let mut tx = db.0.begin().await?;
+    // tokens are kept revoked, so that they can be enabled again when the event is restored
+    let api_token_ids: Vec<(i64,)> = sqlx::query_as("UPDATE api_tokens SET revoked=1 WHERE event_id=? AND revoked=0 RETURNING id")
+        .bind(event_id)
+        .fetch_all(&mut *tx).await?;
+    let api_token_ids = api_token_ids.into_iter().map(|id| id.0).collect::<Vec<_>>();
+    let deleted_event_id = insert_deleted_event(&event, &api_token_ids, actor, &mut tx).await?;
     sqlx::query("DELETE FROM events WHERE id=?")
         .bind(event_id)
-        .execute(&db.0).await?;
-    //TODO: delete also DB file
-    Ok(())
+        .execute(&mut *tx).await?;
+    audit(event_id, actor, EVENT_DELETE, &event.name, summary(&event), None, &mut *tx).await?;
+    trash_event_db(event_id, deleted_event_id, tx, state, db).await
+}
+/// Roll back creation of partially cloned or imported event, it is deleted without trash and audit records
+pub(crate) async fn event_discard(event_id: EventId, state: &State<SharedQxState>, db: &State<DbPool>) -> anyhow::Result<()> {
+    let mut tx = db.0.begin().await?;
+    sqlx::query("DELETE FROM api_tokens WHERE event_id=?")
+        .bind(event_id)
+        .execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM events WHERE id=?")
+        .bind(event_id)
+        .execute(&mut *tx).await?;
+    tx.commit().await?;
+    remove_event_db(event_id, state).await
+}
+#[derive(Deserialize, FromForm, Default, Debug)]
+pub struct CloneEventOptions {
+    /// name of the new event, source event name is used if empty
+    #[serde(default)]
+    pub name: Option<String>,
+    /// copy runs without start, check and finish times
+    #[serde(default)]
+    pub copy_runs: bool,
+    /// names of files copied to the new event
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+#[derive(Serialize, Debug)]
+pub struct ClonedEvent {
+    pub event: EventRecord,
+    // token is shown only once, as when the event is created
+    pub api_token: String,
+}
+// name, length, climb, control_count, start_time, interval, start_slot_count
+type ClonedClassColumns = (String, Option<i64>, Option<i64>, Option<i64>, Option<i64>, Option<i64>, Option<i64>);
+/// Create next stage of event, classes, optionally runs and selected files are copied from source event DB
+async fn clone_event(source: &EventRecord, options: &CloneEventOptions, user: &UserInfo, state: &State<SharedQxState>, gdb: &State<DbPool>) -> anyhow::Result<ClonedEvent> {
+    let stage = source.stage + 1;
+    let event = EventRecord {
+        id: 0,
+        name: options.name.as_deref().map(str::trim).filter(|s| !s.is_empty()).unwrap_or(&source.name).to_string(),
+        stage,
+        stage_count: source.stage_count.max(stage),
+        start_time: source.start_time.0.checked_add_signed(TimeDelta::days(1)).map(QxDateTime).unwrap_or(source.start_time),
+        owner: user.email.clone(),
+        ..source.clone()
+    };
+    let actor = Actor::from(user);
+    let event_id = save_event(&event, &actor, gdb).await?;
+    let res = async {
+        let api_token = generate_api_token();
+        create_api_token(event_id, "Default", &api_token, &ApiScopes(vec![ApiScope::QeSync]), None, gdb).await?;
+
+        let src_edb = get_event_db(source.id, state).await?;
+        let edb = get_event_db(event_id, state).await?;
+        let classes: Vec<ClonedClassColumns> = sqlx::query_as(
+            "SELECT name, length, climb, control_count, start_time, interval, start_slot_count FROM classes")
+            .fetch_all(&src_edb)
+            .await.map_err(sqlx_to_anyhow)?;
+        let runs: Vec<RunsRecord> = if options.copy_runs {
+            sqlx::query_as("SELECT * FROM runs")
+                .fetch_all(&src_edb)
+                .await.map_err(sqlx_to_anyhow)?
+        } else {
+            vec![]
+        };
+        let mut tx = edb.begin().await?;
+        for (name, length, climb, control_count, start_time, interval, start_slot_count) in classes {
+            sqlx::query("INSERT INTO classes (name, length, climb, control_count, start_time, interval, start_slot_count) VALUES (?, ?, ?, ?, ?, ?, ?)")
+                .bind(name)
+                .bind(length)
+                .bind(climb)
+                .bind(control_count)
+                .bind(start_time)
+                .bind(interval)
+                .bind(start_slot_count)
+                .execute(&mut *tx).await.map_err(sqlx_to_anyhow)?;
+        }
+        for run in runs {
+            sqlx::query("INSERT INTO runs (run_id, si_id, last_name, first_name, registration, class_name) VALUES (?, ?, ?, ?, ?, ?)")
+                .bind(run.run_id)
+                .bind(run.si_id)
+                .bind(run.last_name)
+                .bind(run.first_name)
+                .bind(run.registration)
+                .bind(run.class_name)
+                .execute(&mut *tx).await.map_err(sqlx_to_anyhow)?;
+        }
+        tx.commit().await?;
+        for name in &options.files {
+            let data = load_file_from_db(name, &src_edb).await.map_err(|e| anyhow!("Copy file {name} error: {e}"))?;
+            save_file_to_db(name, &data, &edb).await?;
+        }
+
+        // next stage joins the competition, if the user may add stages to it
+        if let Some((competition, _)) = load_event_competition(source.id, gdb).await?
+            && competition.owner == user.email {
+            sqlx::query("UPDATE events SET competition_id=? WHERE id=?")
+                .bind(competition.id)
+                .bind(event_id)
+                .execute(&gdb.0)
+                .await.map_err(sqlx_to_anyhow)?;
+        }
+        anyhow::Ok(api_token)
+    }.await;
+    let api_token = match res {
+        Ok(api_token) => api_token,
+        Err(e) => {
+            // do not leave half cloned event
+            if let Err(e) = event_discard(event_id, state, gdb).await {
+                error!("Discard partially cloned event id: {event_id} error: {e}");
+            }
+            return Err(e);
+        }
+    };
+    info!("Event id: {} cloned to id: {event_id} by: {}", source.id, user.email);
+    let event = load_event(event_id, gdb).await?;
+    Ok(ClonedEvent { event, api_token })
+}
+#[post("/event/<event_id>/clone", data = "<form>")]
+async fn post_event_clone(event_id: EventId, form: Form<CloneEventOptions>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_edit_event, gdb).await?;
+    let cloned = clone_event(&event, &form, &user, state, gdb).await.map_err(anyhow_to_custom_error)?;
+    render_created_api_token(&cloned.event, "Default", &cloned.api_token)
+}
+#[post("/api/event/<event_id>/clone", data = "<options>")]
+async fn post_api_event_clone(event_id: EventId, options: Json<CloneEventOptions>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Json<ClonedEvent>, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_edit_event, gdb).await?;
+    let cloned = clone_event(&event, &options, &user, state, gdb).await.map_err(anyhow_to_custom_error)?;
+    Ok(Json(cloned))
 }
 #[get("/event/create")]
 async fn event_create(session_id: QxSessionId, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<Template, Custom<String>> {
@@ -231,44 +537,54 @@ async fn event_create(session_id: QxSessionId, state: &State<SharedQxState>, db:
 async fn event_edit(event_id: EventId, session_id: QxSessionId, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<Template, Custom<String>> {
     event_edit_insert(Some(event_id), &session_id, state, db).await
 }
-#[get("/event/<event_id>/delete")]
+#[post("/event/<event_id>/delete")]
 async fn event_delete(event_id: EventId, session_id: QxSessionId, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<Redirect, Custom<String>> {
     let user = user_info(&session_id, state).await?;
     let event = load_event_info(event_id, db).await?;
-    if event.owner == user.email {
-        event_drop(event_id, db).await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
-        Ok(Redirect::to("/"))
-    } else {
-        Err(Custom(Status::Unauthorized, String::from("Event owner email mismatch!")))
-    }
+    require_event_role(&event, &user, EventRole::can_manage_event, db).await?;
+    event_drop(event_id, &Actor::from(&user), state, db).await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    Ok(Redirect::to("/"))
 }
 
 #[get("/event/<event_id>")]
 async fn get_event(event_id: EventId, session_id: MaybeSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
     let event = load_event_info(event_id, gdb).await?;
     let user = user_info_opt(session_id.0.as_ref(), state).await.map_err(anyhow_to_custom_error)?;
-    let is_event_owner = is_event_owner(&event, user.as_ref());
-    let files = files::list_files(event_id, state).await?;
-    let is_local_server = state.read().await.app_config.is_local_server();
-    let event_url = if is_local_server {
-        format!("http://localhost:8000/event/{event_id}")
+    let role = event_role(&event, user.as_ref(), gdb).await?;
+    let can_edit_event = role.is_some_and(|r| r.can_edit_event());
+    let can_manage_event = role.is_some_and(|r| r.can_manage_event());
+    let can_manage_changes = role.is_some_and(|r| r.can_manage_changes());
+    let files = if event.files_public || role.is_some() {
+        files::list_files(event_id, state).await?
     } else {
-        format!("https://qxqx.org/event/{event_id}")
+        vec![]
     };
+    let server_url = state.read().await.app_config.server_url();
+    let event_url = format!("{server_url}/event/{event_id}");
     let event_qrc_img_data = create_qrc(event_url.as_bytes()).map_err(anyhow_to_custom_error)?;
+    let bulletins = load_bulletins(event_id, state).await?;
+    let description_html = markdown_to_html(&event.description);
+    let (competition, stages) = load_event_competition(event_id, gdb).await.map_err(anyhow_to_custom_error)?.unzip();
     Ok(Template::render("event", context! {
         event_url,
         event_qrc_img_data,
         user,
-        is_event_owner,
+        role,
+        can_edit_event,
+        can_manage_event,
+        can_manage_changes,
         event,
+        description_html,
         files,
+        bulletins,
+        competition,
+        stages,
     }))
 }
 
 #[get("/api/event/current")]
 async fn get_api_event_current(api_token: QxApiToken, db: &State<DbPool>) -> Result<Json<EventRecord>, Custom<String>> {
-    let event = load_event_info_for_api_token(&api_token, db).await?;
+    let event = load_event_info_for_api_token(&api_token, ApiScope::Read, db).await?;
     Ok(Json(event))
 }
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -282,16 +598,14 @@ pub struct EventInfo {
 }
 #[post("/api/event/current", data = "<posted_event>")]
 async fn post_api_event_current(api_token: QxApiToken, posted_event: Json<EventInfo>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Json<EventRecord>, Custom<String>> {
-    let Ok( mut event_info) = load_event_info_for_api_token(&api_token, gdb).await else {
-        return Err(string_to_custom_error("Event not found"));
-    };
+    let mut event_info = load_event_info_for_api_token(&api_token, ApiScope::QeSync, gdb).await?;
     event_info.name = posted_event.name.clone();
     event_info.stage = posted_event.stage;
     event_info.stage_count = posted_event.stage_count;
     event_info.place = posted_event.place.clone();
     event_info.start_time = posted_event.start_time.into();
     debug!("Post event info, start00: {}", event_info.start_time.to_iso_string());
-    let event_id = save_event(&event_info, gdb).await.map_err(anyhow_to_custom_error)?;
+    let event_id = save_event(&event_info, &Actor::from(&api_token), gdb).await.map_err(anyhow_to_custom_error)?;
     let reloaded_event = load_event_info(event_id, gdb).await?;
 
     let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
@@ -300,38 +614,84 @@ async fn post_api_event_current(api_token: QxApiToken, posted_event: Json<EventI
     Ok(Json(reloaded_event))
 }
 
+async fn load_classes(edb: &SqlitePool) -> Result<Vec<ClassesRecord>, Custom<String>> {
+    sqlx::query_as::<_, ClassesRecord>("SELECT * FROM classes ORDER BY name")
+        .fetch_all(edb).await.map_err(sqlx_to_custom_error)
+}
+/// Class `class_name` or the first one when the name is not specified
+fn select_class(classes: &[ClassesRecord], class_name: Option<&str>) -> Result<ClassesRecord, Custom<String>> {
+    let classrec = if let Some(class_name) = class_name {
+        classes.iter().find(|c| c.name == class_name)
+            .ok_or_else(|| Custom(Status::BadRequest, format!("Class {class_name} not found")))?
+    } else {
+        classes.first().ok_or_else(|| Custom(Status::BadRequest, String::from("No classes defined")))?
+    };
+    Ok(classrec.clone())
+}
+/// Runs of class ordered by start time, runs of all classes if `class_name` is not specified
+async fn load_start_list(class_name: Option<&str>, edb: &SqlitePool) -> Result<Vec<RunsRecord>, Custom<String>> {
+    sqlx::query_as::<_, RunsRecord>("SELECT * FROM runs WHERE ?1 IS NULL OR class_name=?1 ORDER BY class_name, start_time")
+        .bind(class_name)
+        .fetch_all(edb).await.map_err(sqlx_to_custom_error)
+}
+/// Runs of class ordered by running time, runs without time are the last ones
+async fn load_results(class_name: Option<&str>, edb: &SqlitePool) -> Result<Vec<RunsRecord>, Custom<String>> {
+    let mut runs = sqlx::query_as::<_, RunsRecord>("SELECT * FROM runs WHERE ?1 IS NULL OR class_name=?1")
+        .bind(class_name)
+        .fetch_all(edb).await.map_err(sqlx_to_custom_error)?;
+    runs.sort_by_cached_key(|run| {
+        let msec = QxDateTime::msec_since_until(&run.start_time, &run.finish_time);
+        (run.class_name.clone(), msec.unwrap_or(i64::MAX))
+    });
+    Ok(runs)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResultRecord {
+    #[serde(flatten)]
+    pub run: RunsRecord,
+    pub time_msec: Option<i64>,
+    // position in class, runners with the same time share the position
+    pub position: Option<i64>,
+}
+fn results_with_positions(runs: Vec<RunsRecord>) -> Vec<ResultRecord> {
+    let mut results: Vec<ResultRecord> = Vec::with_capacity(runs.len());
+    for run in runs {
+        let time_msec = QxDateTime::msec_since_until(&run.start_time, &run.finish_time);
+        let position = time_msec.map(|time_msec| {
+            match results.last() {
+                Some(prev) if prev.run.class_name == run.class_name => {
+                    if prev.time_msec == Some(time_msec) {
+                        prev.position.unwrap_or(1)
+                    } else {
+                        results.iter().filter(|r| r.run.class_name == run.class_name).count() as i64 + 1
+                    }
+                }
+                _ => 1,
+            }
+        });
+        results.push(ResultRecord { run, time_msec, position });
+    }
+    results
+}
+
 #[get("/event/<event_id>/startlist?<class_name>")]
-async fn get_event_start_list(event_id: EventId, session_id: MaybeSessionId, class_name: Option<&str>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+async fn get_event_start_list(event_id: EventId, _access: EventRead<Runs>, session_id: MaybeSessionId, class_name: Option<&str>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
     info!("GET session_id: {session_id:?}");
     let event = load_event_info(event_id, gdb).await?;
     let user = user_info_opt(session_id.0.as_ref(), state).await.map_err(anyhow_to_custom_error)?;
     info!("GET user: {user:?}");
     let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
-    let classes = sqlx::query_as::<_, ClassesRecord>("SELECT * FROM classes ORDER BY name")
-        .fetch_all(&edb).await.map_err(sqlx_to_custom_error)?;
-    let class_name = if let Some(name) = class_name {
-        name.to_string()
-    } else if let Some(classrec) = classes.first() {
-        classrec.name.clone()
-    } else {
-        return Err(Custom(Status::BadRequest, String::from("Classes not found")));
-    };
-    let classrec = {
-        let Some(classrec) = classes.iter().find(|c| c.name == class_name) else {
-            return Err(Custom(Status::BadRequest, format!("Class {class_name} not found")));
-        };
-        classrec.clone()
-    };
+    let classes = load_classes(&edb).await?;
+    let classrec = select_class(&classes, class_name)?;
     let start00 = event.start_time;
-    let runs = sqlx::query_as::<_, RunsRecord>("SELECT * FROM runs WHERE class_name=? ORDER BY start_time")
-        .bind(&class_name)
-        .fetch_all(&edb).await.map_err(sqlx_to_custom_error)?;
+    let runs = load_start_list(Some(&classrec.name), &edb).await?;
     let changes = sqlx::query_as::<_, ChangesRecord>("SELECT changes.* FROM changes, runs
                  WHERE runs.class_name=?
                    AND changes.data_id=runs.run_id
                    AND changes.data_type=?
                    AND changes.status=?")
-        .bind(&class_name)
+        .bind(&classrec.name)
         .bind(RUN_UPDATE_REQUEST)
         .bind(PENDING)
         .fetch_all(&edb).await.map_err(sqlx_to_custom_error)?;
@@ -347,33 +707,14 @@ async fn get_event_start_list(event_id: EventId, session_id: MaybeSessionId, cla
 }
 
 #[get("/event/<event_id>/results?<class_name>")]
-async fn get_event_results(event_id: EventId, class_name: Option<&str>, session_id: MaybeSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+async fn get_event_results(event_id: EventId, _access: EventRead<Runs>, class_name: Option<&str>, session_id: MaybeSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
     let event = load_event_info(event_id, gdb).await?;
     let user = user_info_opt(session_id.0.as_ref(), state).await.map_err(anyhow_to_custom_error)?;
     let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
-    let classes = sqlx::query_as::<_, ClassesRecord>("SELECT * FROM classes ORDER BY name")
-        .fetch_all(&edb).await.map_err(sqlx_to_custom_error)?;
-    let class_name = if let Some(name) = class_name {
-        name.to_string()
-    } else if let Some(classrec) = classes.first() {
-        classrec.name.clone()
-    } else {
-        return Err(Custom(Status::BadRequest, String::from("No classes defined")));
-    };
-    let classrec = {
-        let Some(classrec) = classes.iter().find(|c| c.name == class_name) else {
-            return Err(Custom(Status::BadRequest, format!("Class {class_name} not found")));
-        };
-        classrec.clone()
-    };
+    let classes = load_classes(&edb).await?;
+    let classrec = select_class(&classes, class_name)?;
     let start00 = event.start_time;
-    let mut runs = sqlx::query_as::<_, RunsRecord>("SELECT * FROM runs WHERE class_name=?")
-        .bind(class_name)
-        .fetch_all(&edb).await.map_err(sqlx_to_custom_error)?;
-    runs.sort_by_key(|run| {
-        let msec = QxDateTime::msec_since_until(&run.start_time, &run.finish_time);
-        msec.unwrap_or(i64::MAX)
-    });
+    let runs = load_results(Some(&classrec.name), &edb).await?;
     Ok(Template::render("results", context! {
         event,
         user,
@@ -385,6 +726,73 @@ async fn get_event_results(event_id: EventId, class_name: Option<&str>, session_
 
 }
 
+#[get("/api/event/<event_id>")]
+async fn get_api_event(event_id: EventId, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<Json<EventDetail>, Custom<String>> {
+    let event = load_event_info(event_id, db).await?;
+    let bulletins = load_bulletins(event_id, state).await?;
+    Ok(Json(EventDetail { event, bulletins }))
+}
+#[derive(Serialize)]
+pub struct Bulletin {
+    #[serde(flatten)]
+    pub file: FileInfo,
+    pub url: String,
+}
+async fn load_bulletins(event_id: EventId, state: &State<SharedQxState>) -> Result<Vec<Bulletin>, Custom<String>> {
+    let bulletins = files::list_bulletins(event_id, state).await?.into_iter()
+        .map(|file| {
+            let url = format!("/event/{event_id}/bulletin/{}", RawStr::new(&file.name).percent_encode());
+            Bulletin { file, url }
+        })
+        .collect();
+    Ok(bulletins)
+}
+#[derive(Serialize)]
+pub struct EventDetail {
+    #[serde(flatten)]
+    pub event: EventRecord,
+    pub bulletins: Vec<Bulletin>,
+}
+#[get("/api/event/<event_id>/classes")]
+async fn get_api_event_classes(event_id: EventId, _access: EventRead<Runs>, state: &State<SharedQxState>) -> Result<Json<Vec<ClassesRecord>>, Custom<String>> {
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    Ok(Json(load_classes(&edb).await?))
+}
+#[get("/api/event/<event_id>/startlist?<class_name>")]
+async fn get_api_event_start_list(event_id: EventId, _access: EventRead<Runs>, class_name: Option<&str>, state: &State<SharedQxState>) -> Result<Json<Vec<RunsRecord>>, Custom<String>> {
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    Ok(Json(load_start_list(class_name, &edb).await?))
+}
+#[get("/api/event/<event_id>/results?<class_name>")]
+async fn get_api_event_results(event_id: EventId, _access: EventRead<Runs>, class_name: Option<&str>, state: &State<SharedQxState>) -> Result<Json<Vec<ResultRecord>>, Custom<String>> {
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    let runs = load_results(class_name, &edb).await?;
+    Ok(Json(results_with_positions(runs)))
+}
+
+#[test]
+fn test_results_with_positions() {
+    let run = |class_name: &str, time_sec: Option<i64>| {
+        let start_time = QxDateTime::parse_from_iso("2025-06-01T10:00:00+02:00").unwrap();
+        RunsRecord {
+            class_name: Some(class_name.to_string()),
+            start_time: Some(start_time),
+            finish_time: time_sec.and_then(|sec| start_time.0.checked_add_signed(TimeDelta::seconds(sec))).map(QxDateTime),
+            ..Default::default()
+        }
+    };
+    let results = results_with_positions(vec![
+        run("D21", Some(100)),
+        run("H21", Some(90)),
+        run("H21", Some(90)),
+        run("H21", Some(120)),
+        run("H21", None),
+    ]);
+    let positions = results.iter().map(|r| r.position).collect::<Vec<_>>();
+    assert_eq!(positions, vec![Some(1), Some(1), Some(1), Some(3), None]);
+    assert_eq!(results[3].time_msec, Some(120_000));
+}
+
 pub async fn import_start_list(event_id: EventId, edb: &SqlitePool, gdb: &State<DbPool>) -> anyhow::Result<()> {
     let data = sqlx::query_as::<_, (Vec<u8>,)>("SELECT data FROM files WHERE name=?")
         .bind(START_LIST_IOFXML3_FILE)
@@ -393,7 +801,7 @@ pub async fn import_start_list(event_id: EventId, edb: &SqlitePool, gdb: &State<
 
     let (start00, classes, runs) = parse_startlist_xml_data(data).await?;
 
-    sqlx::query("UPDATE events SET start_time=? WHERE id=?")
+    sqlx::query("UPDATE events SET start_time=?, ical_sequence=ical_sequence+1 WHERE id=?")
         .bind(start00)
         .bind(event_id)
         .execute(&gdb.0).await.map_err(sqlx_to_anyhow)?;
@@ -524,13 +932,21 @@ pub(crate) const DEMO_API_TOKEN: &str = "plelababamak";
 #[cfg(test)]
 pub(crate) const TEST_SESSION_ID: &str = "123abc";
 
-#[get("/event/create-demo")]
+/// Demo event API token is public, so it can be created only once and only on local server
+#[post("/event/create-demo")]
 async fn create_demo_event(state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    if !state.read().await.app_config.is_local_server() {
+        return Err(Custom(Status::Forbidden, "Demo event can be created on local server only".to_string()));
+    }
+    if resolve_api_token(DEMO_API_TOKEN, gdb).await.map_err(anyhow_to_custom_error)?.is_some() {
+        return Err(Custom(Status::Conflict, "Demo event already exists".to_string()));
+    }
     let mut event_info = EventRecord::new("fanda.vacek@gmail.com");
     event_info.name = String::from("Demo event");
     event_info.place = String::from("Deep forest 42");
-    event_info.api_token = QxApiToken(String::from(DEMO_API_TOKEN));
-    let event_id = save_event(&event_info, gdb).await.map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+    let event_id = save_event(&event_info, &Actor::Anonymous, gdb).await.map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+    create_api_token(event_id, "Demo", DEMO_API_TOKEN, &ApiScopes(vec![ApiScope::QeSync]), None, gdb).await
+        .map_err(anyhow_to_custom_error)?;
     {
         // upload demo start list
         let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
@@ -546,7 +962,7 @@ async fn create_demo_event(state: &State<SharedQxState>, gdb: &State<DbPool>) ->
         let data = crate::oc::load_oc_dir("tests/oc/data")
             .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
         for chngset in data {
-            crate::oc::add_oc_change_set(event_id, chngset, state).await.map_err(anyhow_to_custom_error)?;
+            crate::oc::add_oc_change_set(event_id, &event_info.tz(), chngset, state).await.map_err(anyhow_to_custom_error)?;
         }
     }
     Ok(Redirect::to(format!("/event/{event_id}")))
@@ -559,9 +975,15 @@ pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
             event_edit,
             event_delete,
             post_event,
+            post_event_clone,
+            post_api_event_clone,
             get_event,
             get_event_start_list,
             get_event_results,
+            get_api_event,
+            get_api_event_classes,
+            get_api_event_start_list,
+            get_api_event_results,
             get_api_event_current,
             post_api_event_current,
         ])
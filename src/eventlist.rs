@@ -0,0 +1,181 @@
+use anyhow::anyhow;
+use chrono::NaiveDate;
+use rocket::http::{RawStr, Status};
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{Build, Rocket, State};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+use crate::db::DbPool;
+use crate::event::{user_info_opt, EventRecord};
+use crate::util::{anyhow_to_custom_error, sqlx_to_anyhow};
+use crate::{MaybeSessionId, SharedQxState};
+
+pub(crate) const EVENT_LIST_PAGE_SIZE: u32 = 50;
+const EVENT_LIST_PAGE_SIZE_MAX: u32 = 200;
+
+#[derive(FromFormField, Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EventPeriod {
+    #[default]
+    All,
+    /// events starting today or later
+    Upcoming,
+    /// events started before today
+    Past,
+}
+impl EventPeriod {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventPeriod::All => "all",
+            EventPeriod::Upcoming => "upcoming",
+            EventPeriod::Past => "past",
+        }
+    }
+}
+
+/// Event list query, `from` and `to` are dates `YYYY-MM-DD` compared with event local start date
+#[derive(FromForm, Serialize, Debug)]
+pub(crate) struct EventFilter {
+    /// text searched in event name and place
+    pub q: Option<String>,
+    #[field(default = EventPeriod::All)]
+    pub period: EventPeriod,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// events owned by the user or where the user is a member
+    pub mine: bool,
+    #[field(default = 1)]
+    pub page: u32,
+    #[field(default = EVENT_LIST_PAGE_SIZE)]
+    pub per_page: u32,
+}
+impl EventFilter {
+    fn search_text(&self) -> Option<&str> {
+        self.q.as_deref().map(str::trim).filter(|s| !s.is_empty())
+    }
+    pub(crate) fn validate(&self, user_email: Option<&str>) -> Result<(), Custom<String>> {
+        parse_date(&self.from).and_then(|_| parse_date(&self.to)).map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+        if self.mine && user_email.is_none() {
+            return Err(Custom(Status::Unauthorized, "User must be logged in to list own events".to_string()));
+        }
+        Ok(())
+    }
+    fn per_page(&self) -> u32 {
+        self.per_page.clamp(1, EVENT_LIST_PAGE_SIZE_MAX)
+    }
+    /// Query string of the filter showing page `page`
+    pub(crate) fn to_query_string(&self, page: u32) -> String {
+        let mut params = vec![];
+        if let Some(q) = self.search_text() {
+            params.push(format!("q={}", RawStr::new(q).percent_encode()));
+        }
+        if self.period != EventPeriod::All {
+            params.push(format!("period={}", self.period.as_str()));
+        }
+        if let Some(from) = non_empty(&self.from) {
+            params.push(format!("from={}", RawStr::new(from).percent_encode()));
+        }
+        if let Some(to) = non_empty(&self.to) {
+            params.push(format!("to={}", RawStr::new(to).percent_encode()));
+        }
+        if self.mine {
+            params.push("mine=true".to_string());
+        }
+        if self.per_page != EVENT_LIST_PAGE_SIZE {
+            params.push(format!("per_page={}", self.per_page()));
+        }
+        params.push(format!("page={page}"));
+        params.join("&")
+    }
+}
+
+fn non_empty(s: &Option<String>) -> Option<&str> {
+    s.as_deref().map(str::trim).filter(|s| !s.is_empty())
+}
+fn parse_date(s: &Option<String>) -> anyhow::Result<Option<String>> {
+    let Some(s) = non_empty(s) else {
+        return Ok(None);
+    };
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|e| anyhow!("Invalid date: {s}, expected YYYY-MM-DD, error: {e}"))?;
+    Ok(Some(date.format("%Y-%m-%d").to_string()))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct EventListPage {
+    pub events: Vec<EventRecord>,
+    // number of events matching the filter
+    pub total: i64,
+    pub page: u32,
+    pub per_page: u32,
+    pub page_count: u32,
+}
+
+fn push_where(qb: &mut QueryBuilder<'_, Sqlite>, filter: &EventFilter, user_email: Option<&str>) -> anyhow::Result<()> {
+    qb.push(" WHERE 1=1");
+    if let Some(q) = filter.search_text() {
+        let pattern = format!("%{}%", q.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+        qb.push(" AND (name LIKE ").push_bind(pattern.clone()).push(" ESCAPE '\\'")
+            .push(" OR place LIKE ").push_bind(pattern).push(" ESCAPE '\\')");
+    }
+    // start time is stored with offset of the event time zone, so the date prefix is the event local date
+    match filter.period {
+        EventPeriod::All => {}
+        EventPeriod::Upcoming => { qb.push(" AND substr(start_time, 1, 10) >= date('now')"); }
+        EventPeriod::Past => { qb.push(" AND substr(start_time, 1, 10) < date('now')"); }
+    }
+    if let Some(from) = parse_date(&filter.from)? {
+        qb.push(" AND substr(start_time, 1, 10) >= ").push_bind(from);
+    }
+    if let Some(to) = parse_date(&filter.to)? {
+        qb.push(" AND substr(start_time, 1, 10) <= ").push_bind(to);
+    }
+    if filter.mine {
+        let email = user_email.ok_or_else(|| anyhow!("User must be logged in to list own events"))?.to_string();
+        qb.push(" AND (owner=").push_bind(email.clone())
+            .push(" OR id IN (SELECT event_id FROM event_members WHERE user_email=").push_bind(email).push("))");
+    }
+    Ok(())
+}
+
+/// One page of events matching the filter, upcoming events are sorted from the nearest one, other from the latest one
+pub(crate) async fn list_events(filter: &EventFilter, user_email: Option<&str>, gdb: &SqlitePool) -> anyhow::Result<EventListPage> {
+    let mut qb = QueryBuilder::new("SELECT COUNT(*) FROM events");
+    push_where(&mut qb, filter, user_email)?;
+    let (total,): (i64,) = qb.build_query_as().fetch_one(gdb).await.map_err(sqlx_to_anyhow)?;
+
+    let per_page = filter.per_page();
+    let page_count = (total as u64).div_ceil(per_page as u64).max(1) as u32;
+    let page = filter.page.clamp(1, page_count);
+    let mut qb = QueryBuilder::new("SELECT * FROM events");
+    push_where(&mut qb, filter, user_email)?;
+    qb.push(match filter.period {
+        EventPeriod::Upcoming => " ORDER BY julianday(start_time), id",
+        _ => " ORDER BY julianday(start_time) DESC, id DESC",
+    });
+    qb.push(" LIMIT ").push_bind(per_page as i64)
+        .push(" OFFSET ").push_bind(((page - 1) * per_page) as i64);
+    let events = qb.build_query_as::<EventRecord>().fetch_all(gdb).await.map_err(sqlx_to_anyhow)?;
+    Ok(EventListPage {
+        events,
+        total,
+        page,
+        per_page,
+        page_count,
+    })
+}
+
+#[get("/api/events?<filter..>")]
+async fn get_api_events(filter: EventFilter, session_id: MaybeSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Json<EventListPage>, Custom<String>> {
+    let user = user_info_opt(session_id.0.as_ref(), state).await.map_err(anyhow_to_custom_error)?;
+    let user_email = user.as_ref().map(|user| user.email.as_str());
+    filter.validate(user_email)?;
+    let page = list_events(&filter, user_email, &gdb.0).await.map_err(anyhow_to_custom_error)?;
+    Ok(Json(page))
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket.mount("/", routes![
+            get_api_events,
+        ])
+}
@@ -1,36 +1,45 @@
-use sqlx::{FromRow, SqlitePool};
+use sqlx::{FromRow, SqliteExecutor, SqlitePool};
 use rocket::{Build, Data, Rocket, State};
 use rocket::data::ToByteUnit;
-use rocket::http::{ContentType, Status};
+use rocket::http::{ContentType, Header, RawStr, Status};
 use rocket::response::status::{Custom};
 use rocket::serde::json::Json;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use crate::apitoken::ApiScope;
+use crate::audit::{audit, Actor, FILE_DELETE, FILE_PUBLIC, FILE_UPLOAD};
 use crate::db::{get_event_db, DbPool};
-use crate::event::{import_runs_from_db_file, load_event_info_for_api_token, EventId, RUNS_CSV_JSON_FILE, START_LIST_IOFXML3_FILE};
+use crate::access::{EventRead, EventWrite, Files};
+use crate::event::{import_runs_from_db_file, import_start_list, load_event_info, load_event_info_for_api_token, EventId, RUNS_CSV_JSON_FILE, START_LIST_IOFXML3_FILE};
 use crate::{QxApiToken, SharedQxState};
 use crate::util::{anyhow_to_custom_error, sqlx_to_anyhow, sqlx_to_custom_error, unzip_data};
 
+const INLINE_BULLETIN_EXTENSIONS: [&str; 5] = ["pdf", "png", "jpg", "jpeg", "txt"];
+
 #[derive(Serialize, Deserialize, FromRow)]
 pub struct FileInfo {
     pub id: i64,
     pub name: String,
     pub size: i64,
     pub created: chrono::DateTime<chrono::Utc>,
+    // public file is event bulletin
+    #[serde(default)]
+    pub public: bool,
 }
 pub async fn list_files(event_id: EventId, state: &State<SharedQxState>) -> Result<Vec<FileInfo>, Custom<String>> {
     println!("listing files of event: {event_id}");
     let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
-    let files = sqlx::query_as::<_, FileInfo>("SELECT id, name, LENGTH(data) AS size, created FROM files ORDER BY name")
+    let files = sqlx::query_as::<_, FileInfo>("SELECT id, name, LENGTH(data) AS size, created, public FROM files ORDER BY name")
         .fetch_all(&edb).await.map_err(sqlx_to_custom_error)?;
     Ok(files)
 }
 #[get("/api/event/<event_id>/file")]
-async fn get_files(event_id: EventId, state: &State<SharedQxState>) -> Result<Json<Vec<FileInfo>>, Custom<String>> {
+async fn get_files(event_id: EventId, _access: EventRead<Files>, state: &State<SharedQxState>) -> Result<Json<Vec<FileInfo>>, Custom<String>> {
     let files = list_files(event_id, state).await?;
     Ok(Json(files))
 }
 #[get("/api/event/<event_id>/file/<file_id>")]
-async fn get_file(event_id: EventId, file_id: i64, state: &State<SharedQxState>) -> Result<Vec<u8>, Custom<String>> {
+async fn get_file(event_id: EventId, file_id: i64, _access: EventRead<Files>, state: &State<SharedQxState>) -> Result<Vec<u8>, Custom<String>> {
     let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
     let files = sqlx::query_as::<_, (Vec<u8>,)>("SELECT data FROM files WHERE id=?")
         .bind(file_id)
@@ -38,7 +47,7 @@ async fn get_file(event_id: EventId, file_id: i64, state: &State<SharedQxState>)
     files.map(|d| d.0 ).map_err(sqlx_to_custom_error)
 }
 #[get("/event/<event_id>/file/<file_name>")]
-async fn get_file_by_name(event_id: EventId, file_name: &str, state: &State<SharedQxState>) -> Result<Vec<u8>, Custom<String>> {
+async fn get_file_by_name(event_id: EventId, file_name: &str, _access: EventRead<Files>, state: &State<SharedQxState>) -> Result<Vec<u8>, Custom<String>> {
     let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
     let files = sqlx::query_as::<_, (Vec<u8>,)>("SELECT data FROM files WHERE name=?")
         .bind(file_name)
@@ -47,19 +56,88 @@ async fn get_file_by_name(event_id: EventId, file_name: &str, state: &State<Shar
 }
 
 #[delete("/api/event/<event_id>/file/<file_id>")]
-async fn delete_file(event_id: EventId, file_id: i64, state: &State<SharedQxState>) -> Result<(), Custom<String>> {
+async fn delete_file(event_id: EventId, file_id: i64, access: EventWrite<Files>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<(), Custom<String>> {
     let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
-    let res = sqlx::query("DELETE FROM files WHERE id=?")
+    let file: Option<(String, i64)> = sqlx::query_as("DELETE FROM files WHERE id=? RETURNING name, LENGTH(data)")
         .bind(file_id)
-        .execute(&edb).await.map_err(sqlx_to_custom_error)?;
-    if res.rows_affected() == 0 {
-        Err(Custom(Status::NotFound, format!("File id={file_id} not found")))
-    } else {
-        Ok(())
+        .fetch_optional(&edb).await.map_err(sqlx_to_custom_error)?;
+    let Some((name, size)) = file else {
+        return Err(Custom(Status::NotFound, format!("File id={file_id} not found")));
+    };
+    audit(event_id, &access.actor, FILE_DELETE, &name, Some(json!({"id": file_id, "size": size}).to_string()), None, &gdb.0).await
+        .map_err(anyhow_to_custom_error)?;
+    Ok(())
+}
+
+/// Public files, they are readable even if event files are not public
+pub async fn list_bulletins(event_id: EventId, state: &State<SharedQxState>) -> Result<Vec<FileInfo>, Custom<String>> {
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    let files = sqlx::query_as::<_, FileInfo>("SELECT id, name, LENGTH(data) AS size, created, public FROM files WHERE public ORDER BY name")
+        .fetch_all(&edb).await.map_err(sqlx_to_custom_error)?;
+    Ok(files)
+}
+#[derive(Responder)]
+struct BulletinResponse {
+    data: (ContentType, Vec<u8>),
+    disposition: Header<'static>,
+    nosniff: Header<'static>,
+}
+#[get("/event/<event_id>/bulletin/<file_name>")]
+async fn get_bulletin(event_id: EventId, file_name: &str, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<BulletinResponse, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let edb = get_event_db(event.id, state).await.map_err(anyhow_to_custom_error)?;
+    let data = sqlx::query_as::<_, (Vec<u8>,)>("SELECT data FROM files WHERE name=? AND public")
+        .bind(file_name)
+        .fetch_optional(&edb).await.map_err(sqlx_to_custom_error)?
+        .ok_or_else(|| Custom(Status::NotFound, format!("Bulletin {file_name} not found")))?.0;
+    // anyone can read bulletins, so only types which cannot run script in the browser are shown inline
+    let inline_type = file_name.rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .filter(|ext| INLINE_BULLETIN_EXTENSIONS.contains(&ext.as_str()))
+        .and_then(|ext| ContentType::from_extension(&ext));
+    let file_name = RawStr::new(file_name).percent_encode();
+    let (content_type, disposition) = match inline_type {
+        Some(content_type) => (content_type, format!("inline; filename*=UTF-8''{file_name}")),
+        None => (ContentType::Binary, format!("attachment; filename*=UTF-8''{file_name}")),
+    };
+    Ok(BulletinResponse {
+        data: (content_type, data),
+        disposition: Header::new("Content-Disposition", disposition),
+        nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+    })
+}
+#[post("/api/event/<event_id>/bulletin?<name>", data = "<data>")]
+async fn upload_bulletin(event_id: EventId, name: &str, access: EventWrite<Files>, data: Data<'_>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Json<i64>, Custom<String>> {
+    let name = name.trim();
+    if name.is_empty() || name.contains('/') {
+        return Err(Custom(Status::BadRequest, format!("Invalid bulletin file name: {name}")));
     }
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    let data = data.open(50.mebibytes()).into_bytes().await.map_err(|e| Custom(Status::PayloadTooLarge, e.to_string()))?.into_inner();
+    let file_id = sqlx::query_as::<_, (i64,)>("INSERT OR REPLACE INTO files (name, data, public) VALUES (?, ?, 1) RETURNING id")
+        .bind(name)
+        .bind(&data)
+        .fetch_one(&edb).await.map_err(sqlx_to_custom_error)?.0;
+    audit(event_id, &access.actor, FILE_UPLOAD, name, None, Some(json!({"id": file_id, "size": data.len(), "public": true}).to_string()), &gdb.0).await
+        .map_err(anyhow_to_custom_error)?;
+    Ok(Json(file_id))
+}
+#[post("/api/event/<event_id>/file/<file_id>/public?<public>")]
+async fn set_file_public(event_id: EventId, file_id: i64, public: bool, access: EventWrite<Files>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<(), Custom<String>> {
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    let file: Option<(String,)> = sqlx::query_as("UPDATE files SET public=? WHERE id=? RETURNING name")
+        .bind(public)
+        .bind(file_id)
+        .fetch_optional(&edb).await.map_err(sqlx_to_custom_error)?;
+    let Some((name,)) = file else {
+        return Err(Custom(Status::NotFound, format!("File id={file_id} not found")));
+    };
+    audit(event_id, &access.actor, FILE_PUBLIC, &name, None, Some(json!({"id": file_id, "public": public}).to_string()), &gdb.0).await
+        .map_err(anyhow_to_custom_error)?;
+    Ok(())
 }
 
-pub(crate) async fn save_file_to_db(name: &str, data: &[u8], edb: &SqlitePool) -> anyhow::Result<i64> {
+pub(crate) async fn save_file_to_db(name: &str, data: &[u8], edb: impl SqliteExecutor<'_>) -> anyhow::Result<i64> {
     let q = sqlx::query_as::<_, (i64,)>("INSERT OR REPLACE INTO files (name, data) VALUES (?, ?) RETURNING id")
         .bind(name)
         .bind(data);
@@ -72,9 +150,19 @@ pub(crate) async fn load_file_from_db(name: &str, edb: &SqlitePool) -> anyhow::R
         .await.map_err(sqlx_to_anyhow)?.0;
     Ok(data)
 }
+#[post("/api/event/<event_id>/upload/startlist", data = "<data>")]
+async fn upload_start_list(event_id: EventId, access: EventWrite<Files>, data: Data<'_>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Json<i64>, Custom<String>> {
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    let data = data.open(50.mebibytes()).into_bytes().await.map_err(|e| Custom(Status::PayloadTooLarge, e.to_string()))?.into_inner();
+    let file_id = save_file_to_db(START_LIST_IOFXML3_FILE, &data, &edb).await.map_err(anyhow_to_custom_error)?;
+    audit(event_id, &access.actor, FILE_UPLOAD, START_LIST_IOFXML3_FILE, None, Some(json!({"id": file_id, "size": data.len()}).to_string()), &gdb.0).await
+        .map_err(anyhow_to_custom_error)?;
+    import_start_list(event_id, &edb, gdb).await.map_err(anyhow_to_custom_error)?;
+    Ok(Json(file_id))
+}
 #[post("/api/event/current/file?<name>", data = "<data>")]
 pub async fn upload_file(qx_api_token: QxApiToken, name: &str, data: Data<'_>, content_type: &ContentType, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<String, Custom<String>> {
-    let event_info = load_event_info_for_api_token(&qx_api_token, gdb).await?;
+    let event_info = load_event_info_for_api_token(&qx_api_token, ApiScope::QeSync, gdb).await?;
     let edb = get_event_db(event_info.id, state).await.map_err(anyhow_to_custom_error)?;
     let data = data.open(50.mebibytes()).into_bytes().await.map_err(|e| Custom(Status::PayloadTooLarge, e.to_string()))?.into_inner();
     let data = if content_type == &ContentType::ZIP {
@@ -83,6 +171,8 @@ pub async fn upload_file(qx_api_token: QxApiToken, name: &str, data: Data<'_>, c
         data
     };
     let file_id = save_file_to_db(name, &data, &edb).await.map_err(anyhow_to_custom_error)?;
+    audit(event_info.id, &Actor::from(&qx_api_token), FILE_UPLOAD, name, None, Some(json!({"id": file_id, "size": data.len()}).to_string()), &gdb.0).await
+        .map_err(anyhow_to_custom_error)?;
     if name == START_LIST_IOFXML3_FILE {
         // import_start_list(event_info.id, &edb, gdb).await.map_err(anyhow_to_custom_error)?;
     }
@@ -97,6 +187,10 @@ pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
             get_file_by_name,
             get_file,
             upload_file,
+            upload_start_list,
             delete_file,
+            get_bulletin,
+            upload_bulletin,
+            set_file_public,
         ])
 }
\ No newline at end of file
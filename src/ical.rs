@@ -0,0 +1,150 @@
+use rocket::http::ContentType;
+use rocket::response::status::Custom;
+use rocket::{Build, Rocket, State};
+use sqlx::FromRow;
+use crate::db::DbPool;
+use crate::event::EventId;
+use crate::qxdatetime::QxDateTime;
+use crate::util::sqlx_to_custom_error;
+use crate::SharedQxState;
+
+// content lines longer than this should be folded, RFC 5545 3.1
+const MAX_LINE_OCTETS: usize = 75;
+
+#[derive(FromRow, Debug)]
+struct CalendarEventRecord {
+    id: EventId,
+    name: String,
+    place: String,
+    stage: i64,
+    stage_count: i64,
+    start_time: QxDateTime,
+    ical_sequence: i64,
+}
+
+/// Escape TEXT property value, RFC 5545 3.3.11
+fn escape_text(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Fold content line to lines of max 75 octets, UTF-8 characters are not split
+fn push_line(ics: &mut String, line: &str) {
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > MAX_LINE_OCTETS {
+            ics.push_str("\r\n ");
+            // the leading space counts to the line length
+            len = 1;
+        }
+        ics.push(c);
+        len += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+fn utc_date_time(dt: &QxDateTime) -> String {
+    dt.0.naive_utc().format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn events_to_ics(events: &[CalendarEventRecord], server_url: &str, calendar_name: &str) -> String {
+    let host = server_url.split("://").last().unwrap_or(server_url).trim_end_matches('/');
+    let dtstamp = utc_date_time(&QxDateTime::now());
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, "PRODID:-//Quick Box//qxhttpd//EN");
+    push_line(&mut ics, "CALSCALE:GREGORIAN");
+    push_line(&mut ics, "METHOD:PUBLISH");
+    push_line(&mut ics, &format!("X-WR-CALNAME:{}", escape_text(calendar_name)));
+    for event in events {
+        let summary = if event.stage_count > 1 {
+            format!("{} E{}", event.name, event.stage)
+        } else {
+            event.name.clone()
+        };
+        push_line(&mut ics, "BEGIN:VEVENT");
+        // UID must not change when the event is updated, so it is derived from event ID only
+        push_line(&mut ics, &format!("UID:event-{}@{host}", event.id));
+        push_line(&mut ics, &format!("DTSTAMP:{dtstamp}"));
+        push_line(&mut ics, &format!("SEQUENCE:{}", event.ical_sequence));
+        push_line(&mut ics, &format!("DTSTART:{}", utc_date_time(&event.start_time)));
+        push_line(&mut ics, &format!("SUMMARY:{}", escape_text(&summary)));
+        if !event.place.is_empty() {
+            push_line(&mut ics, &format!("LOCATION:{}", escape_text(&event.place)));
+        }
+        if event.stage_count > 1 {
+            push_line(&mut ics, &format!("DESCRIPTION:{}", escape_text(&format!("Stage {} of {}", event.stage, event.stage_count))));
+        }
+        push_line(&mut ics, &format!("URL:{server_url}/event/{}", event.id));
+        push_line(&mut ics, "END:VEVENT");
+    }
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+/// iCalendar feed of all events or of events owned by `owner`
+#[get("/events.ics?<owner>")]
+async fn get_events_ics(owner: Option<&str>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<(ContentType, String), Custom<String>> {
+    let events: Vec<CalendarEventRecord> = sqlx::query_as("SELECT * FROM events WHERE ?1 IS NULL OR owner=?1 ORDER BY julianday(start_time)")
+        .bind(owner)
+        .fetch_all(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    let server_url = state.read().await.app_config.server_url();
+    let calendar_name = match owner {
+        Some(owner) => format!("Quick Exchange events of {owner}"),
+        None => "Quick Exchange events".to_string(),
+    };
+    let ics = events_to_ics(&events, &server_url, &calendar_name);
+    Ok((ContentType::new("text", "calendar").with_params(("charset", "utf-8")), ics))
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket.mount("/", routes![
+            get_events_ics,
+        ])
+}
+
+#[test]
+fn test_escape_and_fold() {
+    assert_eq!(escape_text("Cup; sprint, day 1\\2\nnight"), "Cup\\; sprint\\, day 1\\\\2\\nnight");
+    let mut ics = String::new();
+    let line = format!("SUMMARY:{}", "ž".repeat(60));
+    push_line(&mut ics, &line);
+    let lines = ics.split("\r\n").filter(|l| !l.is_empty()).collect::<Vec<_>>();
+    assert!(lines.len() > 1);
+    assert!(lines.iter().all(|l| l.len() <= MAX_LINE_OCTETS));
+    assert!(lines.iter().skip(1).all(|l| l.starts_with(' ')));
+    let unfolded = lines.iter().enumerate().map(|(i, l)| if i == 0 { *l } else { &l[1..] }).collect::<String>();
+    assert_eq!(unfolded, line);
+}
+
+#[test]
+fn test_events_to_ics() {
+    let event = CalendarEventRecord {
+        id: 3,
+        name: "Cup".to_string(),
+        place: "Deep forest".to_string(),
+        stage: 2,
+        stage_count: 3,
+        start_time: QxDateTime::parse_from_iso("2025-06-01T10:00:00+02:00").unwrap(),
+        ical_sequence: 4,
+    };
+    let ics = events_to_ics(&[event], "https://qxqx.org", "Events");
+    assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(ics.contains("UID:event-3@qxqx.org\r\n"));
+    assert!(ics.contains("DTSTART:20250601T080000Z\r\n"));
+    assert!(ics.contains("SUMMARY:Cup E2\r\n"));
+    assert!(ics.contains("SEQUENCE:4\r\n"));
+    assert!(ics.ends_with("END:VCALENDAR\r\n"));
+}
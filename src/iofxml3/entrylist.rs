@@ -0,0 +1,141 @@
+use serde::Serialize;
+use crate::qxdatetime::QxDateTime;
+
+// IOF XML 3.0 EntryList, only the elements QuickEvent reads on entries import
+
+#[derive(Serialize, Debug)]
+pub struct EntryList {
+    #[serde(rename = "@xmlns")]
+    pub xmlns: String,
+    #[serde(rename = "@iofVersion")]
+    pub iof_version: String,
+    #[serde(rename = "@createTime")]
+    pub create_time: String,
+    #[serde(rename = "@creator")]
+    pub creator: String,
+    #[serde(rename = "Event")]
+    pub event: EntryListEvent,
+    #[serde(rename = "PersonEntry")]
+    pub person_entry: Vec<PersonEntry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EntryListEvent {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "StartTime")]
+    pub start_time: EntryListDateAndTime,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EntryListDateAndTime {
+    #[serde(rename = "Date")]
+    pub date: String,
+    #[serde(rename = "Time")]
+    pub time: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PersonEntry {
+    #[serde(rename = "Id")]
+    pub id: i64,
+    #[serde(rename = "Person")]
+    pub person: EntryPerson,
+    #[serde(rename = "Organisation", skip_serializing_if = "Option::is_none")]
+    pub organisation: Option<EntryOrganisation>,
+    #[serde(rename = "ControlCard", skip_serializing_if = "Option::is_none")]
+    pub control_card: Option<EntryControlCard>,
+    #[serde(rename = "Class")]
+    pub class: EntryClass,
+    #[serde(rename = "EntryTime")]
+    pub entry_time: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EntryPerson {
+    #[serde(rename = "Id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<EntryPersonId>,
+    #[serde(rename = "Name")]
+    pub name: EntryPersonName,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EntryPersonId {
+    #[serde(rename = "@type")]
+    pub id_type: String,
+    #[serde(rename = "$text")]
+    pub text: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EntryPersonName {
+    #[serde(rename = "Family")]
+    pub family: String,
+    #[serde(rename = "Given")]
+    pub given: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EntryOrganisation {
+    #[serde(rename = "Name")]
+    pub name: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EntryControlCard {
+    #[serde(rename = "@punchingSystem")]
+    pub punching_system: String,
+    #[serde(rename = "$text")]
+    pub text: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EntryClass {
+    #[serde(rename = "Name")]
+    pub name: String,
+}
+
+impl EntryList {
+    pub fn new(event_name: &str, start_time: &QxDateTime, person_entry: Vec<PersonEntry>) -> Self {
+        Self {
+            xmlns: "http://www.orienteering.org/datastandard/3.0".to_string(),
+            iof_version: "3.0".to_string(),
+            create_time: QxDateTime::now().trimmed_to_sec().to_iso_string(),
+            creator: format!("qxhttpd {}", env!("CARGO_PKG_VERSION")),
+            event: EntryListEvent {
+                name: event_name.to_string(),
+                start_time: EntryListDateAndTime {
+                    date: start_time.0.format("%Y-%m-%d").to_string(),
+                    time: start_time.0.format("%H:%M:%S%:z").to_string(),
+                },
+            },
+            person_entry,
+        }
+    }
+    pub fn to_xml(&self) -> anyhow::Result<String> {
+        let xml = quick_xml::se::to_string_with_root("EntryList", self)?;
+        Ok(format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{xml}"))
+    }
+}
+
+#[test]
+fn test_entry_list_xml() {
+    let start_time = QxDateTime::parse_from_iso("2025-06-01T10:00:00+02:00").unwrap();
+    let entry_list = EntryList::new("Cup & Race", &start_time, vec![PersonEntry {
+        id: 1,
+        person: EntryPerson {
+            id: Some(EntryPersonId { id_type: "CZE".to_string(), text: "ABC1234".to_string() }),
+            name: EntryPersonName { family: "Doe".to_string(), given: "John".to_string() },
+        },
+        organisation: None,
+        control_card: Some(EntryControlCard { punching_system: "SI".to_string(), text: "123456".to_string() }),
+        class: EntryClass { name: "H21".to_string() },
+        entry_time: "2025-05-01T12:00:00+02:00".to_string(),
+    }]);
+    let xml = entry_list.to_xml().unwrap();
+    assert!(xml.contains("<Name>Cup &amp; Race</Name>"));
+    assert!(xml.contains("<Date>2025-06-01</Date><Time>10:00:00+02:00</Time>"));
+    assert!(xml.contains(r#"<Id type="CZE">ABC1234</Id>"#));
+    assert!(xml.contains(r#"<ControlCard punchingSystem="SI">123456</ControlCard>"#));
+    assert!(!xml.contains("<Organisation>"));
+}
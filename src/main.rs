@@ -1,29 +1,26 @@
 #[macro_use] extern crate rocket;
 
-use sqlx::Sqlite;
-use sqlx::Encode;
 use std::sync::Arc;
-use crate::event::{user_info_opt, EventId, EventRecord};
-use std::fmt::{Debug, Display, Formatter};
+use crate::event::{user_info_opt, EventId};
+use crate::eventlist::{list_events, EventFilter};
+use std::fmt::{Debug};
 use std::collections::{HashMap};
 use std::sync::atomic::AtomicU64;
 use rocket::fs::{FileServer};
-use rocket::{request, tokio, State};
-use rocket::http::{CookieJar, Status};
-use rocket::response::{status};
+use rocket::{request, tokio, Build, Rocket, State};
+use rocket::http::{Status};
 use rocket::response::status::{Custom};
 use rocket_dyn_templates::{Template, context, handlebars};
-use rocket::serde::Serialize;
-use serde::{Deserialize};
 use sqlx::SqlitePool;
-use crate::auth::{UserInfo, QX_SESSION_ID};
+use crate::apitoken::{resolve_api_token, ApiScopes};
+use crate::auth::{load_session, UserInfo, QX_SESSION_ID};
 use crate::changes::{ChangesRecord};
 use crate::db::{DbPool, DbPoolFairing};
-use crate::qxdatetime::{dtstr, obtime, obtimems};
+use crate::personaltoken::{bearer_token, is_personal_token_session, resolve_bearer_session, PersonalScope};
+use crate::qxdatetime::{dtstr, obtime, obtimems, QxDateTime};
 use crate::util::anyhow_to_custom_error;
 use async_broadcast::{broadcast};
 use rocket_dyn_templates::handlebars::{Handlebars, Helper};
-use sqlx::sqlite::{SqliteArgumentValue};
 
 #[cfg(test)]
 use crate::event::TEST_SESSION_ID;
@@ -31,6 +28,7 @@ use crate::event::TEST_SESSION_ID;
 mod tests;
 mod db;
 mod auth;
+mod oauth;
 mod oc;
 mod event;
 mod files;
@@ -39,40 +37,124 @@ mod iofxml3;
 mod qxdatetime;
 mod runs;
 mod changes;
+mod members;
+mod apitoken;
+mod access;
+mod csrf;
+mod ratelimit;
+mod admin;
+mod audit;
+mod runlink;
+mod personaltoken;
+mod competition;
+mod archive;
+mod trash;
+mod eventlist;
+mod entries;
+mod ical;
 
+#[derive(Clone, Copy, Debug)]
+struct SessionLimits {
+    // absolute session lifetime in minutes
+    max_age: i64,
+    // session idle timeout in minutes
+    idle_timeout: i64,
+}
+impl Default for SessionLimits {
+    fn default() -> Self {
+        Self {
+            max_age: 7 * 24 * 60,
+            idle_timeout: 12 * 60,
+        }
+    }
+}
 struct AppConfig {
     server_address: String,
     server_port: u16,
     db_path: String,
+    session_limits: SessionLimits,
+    // emails of server administrators
+    admins: Vec<String>,
+    // deleted events can be restored within this period
+    trash_retention_days: i64,
 }
 impl AppConfig {
+    pub fn is_admin(&self, email: &str) -> bool {
+        self.admins.iter().any(|admin| admin == email)
+    }
     pub fn is_local_server(&self) -> bool {
         self.server_address == "127.0.0.1"
     }
+    pub fn server_url(&self) -> String {
+        if self.is_local_server() {
+            format!("http://localhost:{}", self.server_port)
+        } else {
+            "https://qxqx.org".to_string()
+        }
+    }
 }
 #[derive(Clone, Debug)]
 struct QxSession {
     user_info: UserInfo,
+    created: QxDateTime,
+    last_seen: QxDateTime,
 }
 #[derive(Eq, Hash, PartialEq, Clone, Debug)]
 struct QxSessionId(String);
+
+/// Session ID from the request cookie, valid only if the session exists in the sessions cache or in the DB,
+/// or session of personal token with `scope` sent as bearer token
+async fn resolve_session_id(request: &request::Request<'_>, scope: PersonalScope) -> Option<QxSessionId> {
+    if bearer_token(request).is_some() {
+        return resolve_bearer_session(request, scope).await.ok().flatten();
+    }
+    request.local_cache_async(async {
+        let cookies = request.cookies();
+        let session_id = if cfg!(test) {
+            // didn't find a way, how to use private cookies with tests
+            cookies.get(QX_SESSION_ID).map(|cookie| cookie.value().to_string())
+        } else {
+            cookies.get_private(QX_SESSION_ID).map(|cookie| cookie.value().to_string())
+        };
+        let session_id = QxSessionId(session_id?);
+        if is_personal_token_session(&session_id) {
+            return None;
+        }
+        let state = request.guard::<&State<SharedQxState>>().await.succeeded()?;
+        let db = request.guard::<&State<DbPool>>().await.succeeded()?;
+        match load_session(&session_id, state, db).await {
+            Ok(Some(_)) => Some(session_id),
+            Ok(None) => {
+                // session expired or deleted
+                cookies.remove_private(QX_SESSION_ID);
+                None
+            }
+            Err(e) => {
+                error!("Load session error: {e}");
+                None
+            }
+        }
+    }).await.clone()
+}
+/// Outcome of session guard, personal token sent as bearer token must have `scope`
+async fn session_id_outcome(request: &request::Request<'_>, scope: PersonalScope) -> request::Outcome<QxSessionId, ()> {
+    if let Some(session_id) = resolve_session_id(request, scope).await {
+        return request::Outcome::Success(session_id);
+    }
+    if let Err(status) = resolve_bearer_session(request, scope).await {
+        return request::Outcome::Error((status, ()));
+    }
+    if request.cookies().get(QX_SESSION_ID).is_some() {
+        // session cookie was sent, but the session is expired
+        return request::Outcome::Error((Status::Unauthorized, ()));
+    }
+    request::Outcome::Forward(Status::Unauthorized)
+}
 #[rocket::async_trait]
 impl<'r> request::FromRequest<'r> for QxSessionId {
     type Error = ();
     async fn from_request(request: &'r request::Request<'_>) -> request::Outcome<Self, ()> {
-        let cookies = request
-            .guard::<&CookieJar<'_>>()
-            .await
-            .expect("request cookies");
-        if cfg!(test) {
-            // didn't find a way, how to use private cookies with tests
-            if let Some(cookie) = cookies.get(QX_SESSION_ID) {
-                return request::Outcome::Success(Self(cookie.value().to_string()));
-            }
-        } else if let Some(cookie) = cookies.get_private(QX_SESSION_ID) {
-            return request::Outcome::Success(Self(cookie.value().to_string()));
-        }
-        request::Outcome::Forward(Status::Unauthorized)
+        session_id_outcome(request, PersonalScope::Manage).await
     }
 }
 
@@ -83,41 +165,39 @@ pub struct MaybeSessionId(Option<QxSessionId>);
 impl<'r> request::FromRequest<'r> for MaybeSessionId {
     type Error = ();
     async fn from_request(request: &'r request::Request<'_>) -> request::Outcome<Self, ()> {
-        let cookies = request
-            .guard::<&CookieJar<'_>>()
-            .await
-            .expect("request cookies");
-        if let Some(cookie) = cookies.get_private(QX_SESSION_ID) {
-            return request::Outcome::Success(Self(Some(QxSessionId(cookie.value().to_string()))));
-        }
-        request::Outcome::Success(Self(None))
-    }
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Default, Clone, Debug)]
-struct QxApiToken(String);
-
-impl QxApiToken {
-    pub fn from_str(s: &str) -> Self {
-        QxApiToken(s.to_owned())
+        request::Outcome::Success(Self(resolve_session_id(request, PersonalScope::Read).await))
     }
 }
 
-impl_sqlx_text_type_encode_decode!(QxApiToken);
-
-impl Display for QxApiToken {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
-    }
+/// API token from the `qx-api-token` header resolved to its event and scopes
+#[derive(Clone, Debug)]
+struct QxApiToken {
+    event_id: EventId,
+    name: String,
+    scopes: ApiScopes,
 }
 #[rocket::async_trait]
 impl<'r> request::FromRequest<'r> for QxApiToken {
     type Error = ();
     async fn from_request(request: &'r request::Request<'_>) -> request::Outcome<QxApiToken, ()> {
-        if let Some(api_token) = request.headers().get_one("qx-api-token") {
-            return request::Outcome::Success(QxApiToken(api_token.to_string()));
+        let Some(api_token) = request.headers().get_one("qx-api-token") else {
+            return request::Outcome::Forward(Status::Unauthorized);
+        };
+        let Some(db) = request.guard::<&State<DbPool>>().await.succeeded() else {
+            return request::Outcome::Error((Status::InternalServerError, ()));
+        };
+        match resolve_api_token(api_token, db).await {
+            Ok(Some(api_token)) => request::Outcome::Success(api_token),
+            Ok(None) => {
+                warn!("Unauthorized request with invalid API token, client IP: {:?}", request.client_ip());
+                ratelimit::record_failed_lookup(request);
+                request::Outcome::Error((Status::Unauthorized, ()))
+            }
+            Err(e) => {
+                error!("Resolve API token error: {e}");
+                request::Outcome::Error((Status::InternalServerError, ()))
+            }
         }
-        request::Outcome::Forward(Status::Unauthorized)
     }
 }
 
@@ -127,6 +207,7 @@ struct OpenEvent {
 }
 struct QxState {
     app_config: AppConfig,
+    // cache of sessions stored in DB, sessions are loaded lazily by session ID request guards
     sessions: HashMap<QxSessionId, QxSession>,
     open_events: HashMap<EventId, OpenEvent>,
     changes_sender: async_broadcast::Sender<(EventId, ChangesRecord)>,
@@ -159,26 +240,38 @@ impl QxState {
     //     Ok(())
     // }
 }
-type SharedQxState = tokio::sync::RwLock<QxState>;
+type SharedQxState = Arc<tokio::sync::RwLock<QxState>>;
 
-#[get("/")]
-async fn index(sid: MaybeSessionId, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<Template, Custom<String>> {
+#[get("/?<filter..>")]
+async fn index(filter: EventFilter, sid: MaybeSessionId, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<Template, Custom<String>> {
     let user = user_info_opt(sid.0.as_ref(), state).await.map_err(anyhow_to_custom_error)?;
-    let pool = &db.0;
-    let events: Vec<EventRecord> = sqlx::query_as("SELECT * FROM events")
-        .fetch_all(pool)
-        .await
-        .map_err(|e| status::Custom(Status::InternalServerError, e.to_string()))?;
-    let is_local_server = state.read().await.app_config.is_local_server();
+    let user_email = user.as_ref().map(|user| user.email.as_str());
+    filter.validate(user_email)?;
+    let events = list_events(&filter, user_email, &db.0).await.map_err(anyhow_to_custom_error)?;
+    let prev_page_query = (events.page > 1).then(|| filter.to_query_string(events.page - 1));
+    let next_page_query = (events.page < events.page_count).then(|| filter.to_query_string(events.page + 1));
+    let (is_local_server, is_admin) = {
+        let state = state.read().await;
+        let app_config = &state.app_config;
+        (app_config.is_local_server(), user.as_ref().is_some_and(|user| app_config.is_admin(&user.email)))
+    };
     Ok(Template::render("index", context! {
         user,
+        filter,
         events,
+        prev_page_query,
+        next_page_query,
+        is_admin,
         show_create_demo: is_local_server,
     }))
 }
 #[launch]
 fn rocket() -> _ {
-    let rocket = rocket::build()
+    build_rocket(rocket::build())
+}
+/// Server built with configuration `rocket`, tests can pass their own figment
+fn build_rocket(rocket: Rocket<Build>) -> Rocket<Build> {
+    let rocket = rocket
         // .attach(Template::fairing())
         .attach(Template::custom(|engines| {
             let handlebars = &mut engines.handlebars;
@@ -196,7 +289,9 @@ fn rocket() -> _ {
             handlebars.register_helper("dtstr",
                                        Box::new(|h: &Helper, _r: &Handlebars, _: &handlebars::Context, _rc: &mut handlebars::RenderContext, out: &mut dyn handlebars::Output| -> handlebars::HelperResult {
                                            let val = h.param(0).ok_or(handlebars::RenderErrorReason::ParamNotFoundForIndex("dtstr", 0))?.value();
-                                           let s = dtstr(val.as_str());
+                                           // optional second param is IANA time zone the value is displayed in
+                                           let time_zone = h.param(1).and_then(|tz| tz.value().as_str());
+                                           let s = dtstr(val.as_str(), time_zone);
                                            out.write(&s)?;
                                            Ok(())
                                        }));
@@ -253,23 +348,47 @@ fn rocket() -> _ {
     let rocket = runs::extend(rocket);
     let rocket = changes::extend(rocket);
     let rocket = files::extend(rocket);
+    let rocket = members::extend(rocket);
+    let rocket = apitoken::extend(rocket);
+    let rocket = csrf::extend(rocket);
+    let rocket = ratelimit::extend(rocket);
+    let rocket = admin::extend(rocket);
+    let rocket = audit::extend(rocket);
+    let rocket = runlink::extend(rocket);
+    let rocket = personaltoken::extend(rocket);
+    let rocket = competition::extend(rocket);
+    let rocket = archive::extend(rocket);
+    let rocket = trash::extend(rocket);
+    let rocket = eventlist::extend(rocket);
+    let rocket = entries::extend(rocket);
+    let rocket = ical::extend(rocket);
 
     let figment = rocket.figment();
     let server_address = figment.extract_inner::<String>("address").expect("server address");
     let server_port = figment.extract_inner::<u16>("port").expect("Server port");
     let db_path = figment.extract_inner::<String>("db_path").expect("db_path");
-    
-    let cfg = AppConfig{ server_address, server_port, db_path };
+    let session_limits = {
+        let default_limits = SessionLimits::default();
+        SessionLimits {
+            max_age: figment.extract_inner::<i64>("session_max_age").unwrap_or(default_limits.max_age),
+            idle_timeout: figment.extract_inner::<i64>("session_idle_timeout").unwrap_or(default_limits.idle_timeout),
+        }
+    };
+    let admins = figment.extract_inner::<Vec<String>>("admins").unwrap_or_default();
+    let trash_retention_days = figment.extract_inner::<i64>("trash_retention_days").unwrap_or(trash::DEFAULT_RETENTION_DAYS);
+
+    let cfg = AppConfig{ server_address, server_port, db_path, session_limits, admins, trash_retention_days };
     #[cfg(test)]
     {
         let mut state = QxState::new(cfg);
-        state.sessions.insert(QxSessionId(TEST_SESSION_ID.into()), QxSession { user_info: UserInfo::create_test_user_info() });
-        rocket.manage(SharedQxState::new(state))
+        let now = QxDateTime::now().trimmed_to_sec();
+        state.sessions.insert(QxSessionId(TEST_SESSION_ID.into()), QxSession { user_info: UserInfo::create_test_user_info(), created: now, last_seen: now });
+        rocket.manage(SharedQxState::new(tokio::sync::RwLock::new(state)))
     }
     #[cfg(not(test))]
     {
         let state = QxState::new(cfg);
-        rocket.manage(SharedQxState::new(state))
+        rocket.manage(SharedQxState::new(tokio::sync::RwLock::new(state)))
     }
 }
 
@@ -0,0 +1,353 @@
+use std::fmt::{Display, Formatter};
+use chrono::TimeDelta;
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::response::status::Custom;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{Build, Rocket, State};
+use rocket_dyn_templates::{context, Template};
+use sqlx::{Encode, FromRow, Sqlite};
+use sqlx::sqlite::SqliteArgumentValue;
+use crate::audit::{audit, Actor, EVENT_OWNER};
+use crate::auth::{generate_random_string, UserInfo};
+use crate::db::DbPool;
+use crate::event::{load_event_info, user_info, EventId, EventRecord};
+use crate::qxdatetime::QxDateTime;
+use crate::util::{anyhow_to_custom_error, sqlx_to_anyhow, sqlx_to_custom_error};
+use crate::{impl_sqlx_text_type_encode_decode, QxSessionId, SharedQxState};
+
+const INVITATION_VALID_DAYS: i64 = 7;
+
+const OWNER: &str = "Owner";
+const EDITOR: &str = "Editor";
+const JUDGE: &str = "Judge";
+const START_OFFICIAL: &str = "StartOfficial";
+const VIEWER: &str = "Viewer";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EventRole {
+    Owner,
+    Editor,
+    Judge,
+    StartOfficial,
+    Viewer,
+}
+impl_sqlx_text_type_encode_decode!(EventRole);
+
+impl EventRole {
+    /// Unknown role stored in DB is decoded as the least privileged one
+    pub fn from_str(s: &str) -> Self {
+        Self::try_from_str(s).unwrap_or_else(|| {
+            warn!("Unknown event role: {s}, using {VIEWER}");
+            Self::Viewer
+        })
+    }
+    fn try_from_str(s: &str) -> Option<Self> {
+        [Self::Owner, Self::Editor, Self::Judge, Self::StartOfficial, Self::Viewer].into_iter()
+            .find(|role| role.to_string() == s)
+    }
+    /// Edit event info, upload start list, manage API tokens
+    pub fn can_edit_event(&self) -> bool {
+        matches!(self, Self::Owner | Self::Editor)
+    }
+    /// Delete event, invite and remove members
+    pub fn can_manage_event(&self) -> bool {
+        matches!(self, Self::Owner)
+    }
+    /// Resolve or delete change requests of other users
+    pub fn can_manage_changes(&self) -> bool {
+        matches!(self, Self::Owner | Self::Editor | Self::Judge)
+    }
+}
+
+impl Display for EventRole {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventRole::Owner => f.write_str(OWNER),
+            EventRole::Editor => f.write_str(EDITOR),
+            EventRole::Judge => f.write_str(JUDGE),
+            EventRole::StartOfficial => f.write_str(START_OFFICIAL),
+            EventRole::Viewer => f.write_str(VIEWER),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct EventMemberRecord {
+    pub id: i64,
+    pub event_id: EventId,
+    pub user_email: String,
+    pub role: EventRole,
+}
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct EventInvitationRecord {
+    pub id: i64,
+    pub event_id: EventId,
+    pub token: String,
+    pub role: EventRole,
+    pub invited_email: Option<String>,
+    pub invited_by: String,
+    pub created: QxDateTime,
+    pub expires: QxDateTime,
+    // accepting user becomes the event owner, previous owner stays as co-owner
+    pub transfer: bool,
+}
+
+/// Role of user in event, main event organizer stored in `events.owner` is always `Owner`
+pub async fn load_event_role(event: &EventRecord, user: Option<&UserInfo>, db: &State<DbPool>) -> anyhow::Result<Option<EventRole>> {
+    let Some(user) = user else {
+        return Ok(None);
+    };
+    if user.email == event.owner {
+        return Ok(Some(EventRole::Owner));
+    }
+    let role: Option<(EventRole,)> = sqlx::query_as("SELECT role FROM event_members WHERE event_id=? AND user_email=?")
+        .bind(event.id)
+        .bind(&user.email)
+        .fetch_optional(&db.0)
+        .await.map_err(sqlx_to_anyhow)?;
+    Ok(role.map(|r| r.0))
+}
+
+pub async fn event_role(event: &EventRecord, user: Option<&UserInfo>, db: &State<DbPool>) -> Result<Option<EventRole>, Custom<String>> {
+    load_event_role(event, user, db).await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))
+}
+
+/// Check that user has a role in event satisfying the `is_allowed` predicate
+pub async fn require_event_role(
+    event: &EventRecord,
+    user: &UserInfo,
+    is_allowed: fn(&EventRole) -> bool,
+    db: &State<DbPool>
+) -> Result<EventRole, Custom<String>> {
+    match event_role(event, Some(user), db).await? {
+        Some(role) if is_allowed(&role) => Ok(role),
+        Some(role) => Err(Custom(Status::Forbidden, format!("Event role {role} is not sufficient for this action"))),
+        None => Err(Custom(Status::Forbidden, "User is not member of event".to_string())),
+    }
+}
+
+#[get("/event/<event_id>/members")]
+async fn get_members(event_id: EventId, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_manage_event, gdb).await?;
+    let members: Vec<EventMemberRecord> = sqlx::query_as("SELECT * FROM event_members WHERE event_id=? ORDER BY role, user_email")
+        .bind(event_id)
+        .fetch_all(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    let invitations: Vec<EventInvitationRecord> = sqlx::query_as("SELECT * FROM event_invitations WHERE event_id=? ORDER BY created")
+        .bind(event_id)
+        .fetch_all(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    let server_url = state.read().await.app_config.server_url();
+    let is_main_owner = user.email == event.owner;
+    Ok(Template::render("members", context! {
+        user,
+        event,
+        members,
+        invitations,
+        server_url,
+        is_main_owner,
+        roles: [EventRole::Owner, EventRole::Editor, EventRole::Judge, EventRole::StartOfficial, EventRole::Viewer],
+    }))
+}
+
+#[derive(Debug, FromForm)]
+struct InvitationFormValues<'v> {
+    role: &'v str,
+    email: &'v str,
+}
+#[post("/event/<event_id>/members/invite", data = "<form>")]
+async fn post_invitation(event_id: EventId, form: Form<InvitationFormValues<'_>>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_manage_event, gdb).await?;
+    let role = EventRole::try_from_str(form.role)
+        .ok_or(Custom(Status::BadRequest, format!("Invalid role: {}", form.role)))?;
+    let invited_email = Some(form.email.trim()).filter(|s| !s.is_empty());
+    let created = QxDateTime::now().trimmed_to_sec();
+    let expires = created.0.checked_add_signed(TimeDelta::days(INVITATION_VALID_DAYS)).map(QxDateTime).unwrap_or(created);
+    sqlx::query("INSERT INTO event_invitations (event_id, token, role, invited_email, invited_by, created, expires) VALUES (?, ?, ?, ?, ?, ?, ?)")
+        .bind(event_id)
+        .bind(generate_random_string(24))
+        .bind(role)
+        .bind(invited_email)
+        .bind(&user.email)
+        .bind(created)
+        .bind(expires)
+        .execute(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    Ok(Redirect::to(format!("/event/{event_id}/members")))
+}
+
+#[derive(Debug, FromForm)]
+struct TransferFormValues<'v> {
+    #[field(validate = contains('@'))]
+    email: &'v str,
+}
+#[post("/event/<event_id>/members/transfer", data = "<form>")]
+async fn post_transfer_invitation(event_id: EventId, form: Form<TransferFormValues<'_>>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    if user.email != event.owner {
+        return Err(Custom(Status::Forbidden, "Only event owner can transfer the event".to_string()));
+    }
+    let invited_email = form.email.trim();
+    if invited_email == event.owner {
+        return Err(Custom(Status::BadRequest, "User is already event owner".to_string()));
+    }
+    let created = QxDateTime::now().trimmed_to_sec();
+    let expires = created.0.checked_add_signed(TimeDelta::days(INVITATION_VALID_DAYS)).map(QxDateTime).unwrap_or(created);
+    sqlx::query("INSERT INTO event_invitations (event_id, token, role, invited_email, invited_by, created, expires, transfer) VALUES (?, ?, ?, ?, ?, ?, ?, 1)")
+        .bind(event_id)
+        .bind(generate_random_string(24))
+        .bind(EventRole::Owner)
+        .bind(invited_email)
+        .bind(&user.email)
+        .bind(created)
+        .bind(expires)
+        .execute(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    info!("User {} invited {invited_email} to take over event {event_id}", user.email);
+    Ok(Redirect::to(format!("/event/{event_id}/members")))
+}
+
+#[derive(Debug, FromForm)]
+struct IdFormValues {
+    id: i64,
+}
+#[post("/event/<event_id>/members/remove", data = "<form>")]
+async fn remove_member(event_id: EventId, form: Form<IdFormValues>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_manage_event, gdb).await?;
+    sqlx::query("DELETE FROM event_members WHERE id=? AND event_id=?")
+        .bind(form.id)
+        .bind(event_id)
+        .execute(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    Ok(Redirect::to(format!("/event/{event_id}/members")))
+}
+#[post("/event/<event_id>/invitations/cancel", data = "<form>")]
+async fn cancel_invitation(event_id: EventId, form: Form<IdFormValues>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_manage_event, gdb).await?;
+    sqlx::query("DELETE FROM event_invitations WHERE id=? AND event_id=?")
+        .bind(form.id)
+        .bind(event_id)
+        .execute(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    Ok(Redirect::to(format!("/event/{event_id}/members")))
+}
+
+async fn load_valid_invitation(event_id: EventId, token: &str, user: &UserInfo, gdb: &State<DbPool>) -> Result<EventInvitationRecord, Custom<String>> {
+    let invitation: EventInvitationRecord = sqlx::query_as("SELECT * FROM event_invitations WHERE event_id=? AND token=?")
+        .bind(event_id)
+        .bind(token)
+        .fetch_optional(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?
+        .ok_or(Custom(Status::NotFound, "Invitation not found".to_string()))?;
+    if invitation.expires.0 < QxDateTime::now().0 {
+        return Err(Custom(Status::Gone, "Invitation expired".to_string()));
+    }
+    if let Some(invited_email) = &invitation.invited_email && invited_email != &user.email {
+        return Err(Custom(Status::Forbidden, format!("Invitation is for different user: {invited_email}")));
+    }
+    Ok(invitation)
+}
+
+#[get("/event/<event_id>/invitation/<token>")]
+async fn get_invitation(event_id: EventId, token: &str, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    let invitation = load_valid_invitation(event_id, token, &user, gdb).await?;
+    let current_role = event_role(&event, Some(&user), gdb).await?;
+    Ok(Template::render("invitation", context! {
+        user,
+        event,
+        invitation,
+        current_role,
+    }))
+}
+
+#[post("/event/<event_id>/invitation/<token>")]
+async fn accept_invitation(event_id: EventId, token: &str, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let user = user_info(&session_id, state).await?;
+    let invitation = load_valid_invitation(event_id, token, &user, gdb).await?;
+    let event = load_event_info(event_id, gdb).await?;
+    let mut tx = gdb.0.begin().await.map_err(sqlx_to_custom_error)?;
+    let res = sqlx::query("DELETE FROM event_invitations WHERE id=?")
+        .bind(invitation.id)
+        .execute(&mut *tx)
+        .await.map_err(sqlx_to_custom_error)?;
+    if res.rows_affected() == 0 {
+        return Err(Custom(Status::Gone, "Invitation already used".to_string()));
+    }
+    if invitation.transfer {
+        // invitation is valid only while its author is still the event owner
+        let res = sqlx::query("UPDATE events SET owner=? WHERE id=? AND owner=?")
+            .bind(&user.email)
+            .bind(event_id)
+            .bind(&invitation.invited_by)
+            .execute(&mut *tx)
+            .await.map_err(sqlx_to_custom_error)?;
+        if res.rows_affected() == 0 {
+            return Err(Custom(Status::Conflict, "Event owner has changed since the invitation was created".to_string()));
+        }
+        sqlx::query("DELETE FROM event_members WHERE event_id=? AND user_email=?")
+            .bind(event_id)
+            .bind(&user.email)
+            .execute(&mut *tx)
+            .await.map_err(sqlx_to_custom_error)?;
+        sqlx::query("INSERT INTO event_members (event_id, user_email, role) VALUES (?, ?, ?)
+                     ON CONFLICT(event_id, user_email) DO UPDATE SET role=excluded.role")
+            .bind(event_id)
+            .bind(&invitation.invited_by)
+            .bind(EventRole::Owner)
+            .execute(&mut *tx)
+            .await.map_err(sqlx_to_custom_error)?;
+        audit(event_id, &Actor::from(&user), EVENT_OWNER, &event.name, Some(invitation.invited_by.clone()), Some(user.email.clone()), &mut *tx).await
+            .map_err(anyhow_to_custom_error)?;
+        tx.commit().await.map_err(sqlx_to_custom_error)?;
+        info!("User {} took over event {event_id} from {}", user.email, invitation.invited_by);
+        return Ok(Redirect::to(format!("/event/{event_id}")));
+    }
+    sqlx::query("INSERT INTO event_members (event_id, user_email, role) VALUES (?, ?, ?)
+                 ON CONFLICT(event_id, user_email) DO UPDATE SET role=excluded.role")
+        .bind(event_id)
+        .bind(&user.email)
+        .bind(invitation.role)
+        .execute(&mut *tx)
+        .await.map_err(sqlx_to_custom_error)?;
+    tx.commit().await.map_err(sqlx_to_custom_error)?;
+    info!("User {} accepted invitation to event {event_id} as {}", user.email, invitation.role);
+    Ok(Redirect::to(format!("/event/{event_id}")))
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket.mount("/", routes![
+            get_members,
+            post_invitation,
+            post_transfer_invitation,
+            remove_member,
+            cancel_invitation,
+            get_invitation,
+            accept_invitation,
+        ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_event_role_from_str() {
+        assert_eq!(EventRole::from_str("Editor"), EventRole::Editor);
+        assert_eq!(EventRole::from_str("SuperUser"), EventRole::Viewer);
+        assert!(!EventRole::from_str("SuperUser").can_edit_event());
+    }
+}
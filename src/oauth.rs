@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+use anyhow::{anyhow, Context};
+use reqwest::header::{ACCEPT, AUTHORIZATION, USER_AGENT};
+use reqwest::Url;
+use rocket::serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::auth::UserInfo;
+
+/// Names of user info claims, the first non-empty claim is used
+#[derive(Deserialize, Clone, Debug)]
+pub struct ClaimMapping {
+    pub name: Vec<String>,
+    pub email: Vec<String>,
+    pub picture: Vec<String>,
+}
+impl ClaimMapping {
+    fn new(name: &[&str], email: &[&str], picture: &[&str]) -> Self {
+        fn to_vec(claims: &[&str]) -> Vec<String> {
+            claims.iter().map(|s| s.to_string()).collect()
+        }
+        Self { name: to_vec(name), email: to_vec(email), picture: to_vec(picture) }
+    }
+}
+
+/// Provider as configured in Rocket.toml `[default.oauth.<name>]` section.
+/// Known providers `Google`, `Microsoft` and `GitHub` have all the URLs predefined,
+/// any other OpenID Connect provider, like Keycloak or local mock IdP, must define them explicitly.
+/// `Microsoft` requires `tenant`, email claim can be set to any value by admin of other tenants.
+#[derive(Deserialize, Clone, Debug)]
+pub struct OAuthProviderConfig {
+    pub provider: Option<String>,
+    pub display_name: Option<String>,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    // Azure AD tenant ID or domain, substituted to Microsoft URLs
+    pub tenant: Option<String>,
+    pub auth_uri: Option<String>,
+    pub token_uri: Option<String>,
+    pub userinfo_uri: Option<String>,
+    // GitHub does not return private email in user info
+    pub emails_uri: Option<String>,
+    pub scopes: Option<Vec<String>>,
+    pub claims: Option<ClaimMapping>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct OAuthProvider {
+    pub name: String,
+    pub display_name: String,
+    #[serde(skip)]
+    client_id: String,
+    #[serde(skip)]
+    client_secret: String,
+    #[serde(skip)]
+    redirect_uri: String,
+    #[serde(skip)]
+    auth_uri: String,
+    #[serde(skip)]
+    token_uri: String,
+    #[serde(skip)]
+    userinfo_uri: String,
+    #[serde(skip)]
+    emails_uri: Option<String>,
+    #[serde(skip)]
+    scopes: Vec<String>,
+    #[serde(skip)]
+    claims: ClaimMapping,
+}
+
+const TENANT: &str = "{tenant}";
+// tenants allowing sign in of users from any organization
+const MULTI_TENANTS: &[&str] = &["common", "organizations"];
+
+struct ProviderPreset {
+    display_name: &'static str,
+    auth_uri: &'static str,
+    token_uri: &'static str,
+    userinfo_uri: &'static str,
+    emails_uri: Option<&'static str>,
+    scopes: &'static [&'static str],
+    claims: ClaimMapping,
+}
+fn provider_preset(provider: &str) -> Option<ProviderPreset> {
+    match provider.to_lowercase().as_str() {
+        "google" => Some(ProviderPreset {
+            display_name: "Google",
+            auth_uri: "https://accounts.google.com/o/oauth2/v2/auth",
+            token_uri: "https://oauth2.googleapis.com/token",
+            userinfo_uri: "https://www.googleapis.com/oauth2/v2/userinfo",
+            emails_uri: None,
+            scopes: &["profile", "email"],
+            claims: ClaimMapping::new(&["name"], &["email"], &["picture"]),
+        }),
+        "microsoft" => Some(ProviderPreset {
+            display_name: "Microsoft",
+            auth_uri: "https://login.microsoftonline.com/{tenant}/oauth2/v2.0/authorize",
+            token_uri: "https://login.microsoftonline.com/{tenant}/oauth2/v2.0/token",
+            userinfo_uri: "https://graph.microsoft.com/oidc/userinfo",
+            emails_uri: None,
+            scopes: &["openid", "profile", "email"],
+            claims: ClaimMapping::new(&["name"], &["email"], &[]),
+        }),
+        "github" => Some(ProviderPreset {
+            display_name: "GitHub",
+            auth_uri: "https://github.com/login/oauth/authorize",
+            token_uri: "https://github.com/login/oauth/access_token",
+            userinfo_uri: "https://api.github.com/user",
+            emails_uri: Some("https://api.github.com/user/emails"),
+            scopes: &["read:user", "user:email"],
+            claims: ClaimMapping::new(&["name", "login"], &["email"], &["avatar_url"]),
+        }),
+        _ => None,
+    }
+}
+
+impl OAuthProvider {
+    pub fn from_config(name: &str, cfg: OAuthProviderConfig) -> anyhow::Result<Self> {
+        let preset = cfg.provider.as_deref().and_then(provider_preset);
+        let tenant = cfg.tenant.as_deref();
+        let uri = |configured: Option<String>, preset: Option<&str>, key: &str| -> anyhow::Result<String> {
+            if let Some(uri) = configured {
+                return Ok(uri);
+            }
+            let uri = preset.ok_or(anyhow!("OAuth provider {name} must define {key}"))?;
+            if !uri.contains(TENANT) {
+                return Ok(uri.to_string());
+            }
+            match tenant {
+                Some(tenant) if !tenant.is_empty() && !MULTI_TENANTS.contains(&tenant.to_lowercase().as_str()) => Ok(uri.replace(TENANT, tenant)),
+                _ => Err(anyhow!("OAuth provider {name} must define single tenant")),
+            }
+        };
+        Ok(Self {
+            name: name.to_string(),
+            display_name: cfg.display_name
+                .or(preset.as_ref().map(|p| p.display_name.to_string()))
+                .unwrap_or(name.to_string()),
+            auth_uri: uri(cfg.auth_uri, preset.as_ref().map(|p| p.auth_uri), "auth_uri")?,
+            token_uri: uri(cfg.token_uri, preset.as_ref().map(|p| p.token_uri), "token_uri")?,
+            userinfo_uri: uri(cfg.userinfo_uri, preset.as_ref().map(|p| p.userinfo_uri), "userinfo_uri")?,
+            emails_uri: cfg.emails_uri.or(preset.as_ref().and_then(|p| p.emails_uri.map(|s| s.to_string()))),
+            scopes: cfg.scopes
+                .or(preset.as_ref().map(|p| p.scopes.iter().map(|s| s.to_string()).collect()))
+                .unwrap_or(vec!["openid".to_string(), "profile".to_string(), "email".to_string()]),
+            claims: cfg.claims
+                .or(preset.map(|p| p.claims))
+                .unwrap_or(ClaimMapping::new(&["name", "preferred_username"], &["email"], &["picture"])),
+            client_id: cfg.client_id,
+            client_secret: cfg.client_secret,
+            redirect_uri: cfg.redirect_uri,
+        })
+    }
+    /// Login start URI, it must have the same host as `redirect_uri`,
+    /// because the OAuth state cookie is checked in the redirect callback
+    pub fn login_uri(&self) -> anyhow::Result<String> {
+        let redirect_uri = Url::parse(&self.redirect_uri)?;
+        Ok(format!("{}/login/{}", redirect_uri.origin().ascii_serialization(), self.name))
+    }
+    pub fn authorize_url(&self, state: &str) -> anyhow::Result<String> {
+        let scope = self.scopes.join(" ");
+        let url = Url::parse_with_params(&self.auth_uri, &[
+            ("response_type", "code"),
+            ("client_id", self.client_id.as_str()),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("scope", scope.as_str()),
+            ("state", state),
+        ])?;
+        Ok(url.to_string())
+    }
+    pub async fn exchange_code(&self, code: &str) -> anyhow::Result<String> {
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            access_token: String,
+        }
+        let response = reqwest::Client::new()
+            .post(&self.token_uri)
+            .header(ACCEPT, "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.redirect_uri.as_str()),
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+            ])
+            .send()
+            .await.context("failed to complete token request")?
+            .error_for_status()?;
+        let token: TokenResponse = response.json().await.context("failed to deserialize token response")?;
+        Ok(token.access_token)
+    }
+    async fn get_json(&self, uri: &str, access_token: &str) -> anyhow::Result<Value> {
+        let response = reqwest::Client::new()
+            .get(uri)
+            .header(AUTHORIZATION, format!("Bearer {access_token}"))
+            .header(ACCEPT, "application/json")
+            .header(USER_AGENT, env!("CARGO_PKG_NAME"))
+            .send()
+            .await.context("failed to complete request")?
+            .error_for_status()?;
+        response.json().await.context("failed to deserialize response")
+    }
+    pub async fn fetch_user_info(&self, access_token: &str) -> anyhow::Result<UserInfo> {
+        let mut claims = self.get_json(&self.userinfo_uri, access_token).await?;
+        if let Some(emails_uri) = &self.emails_uri && claim_value(&claims, &self.claims.email).is_empty() {
+            let emails = self.get_json(emails_uri, access_token).await?;
+            if let (Some(email), Some(map)) = (primary_email(&emails), claims.as_object_mut()) {
+                for claim in &self.claims.email {
+                    map.insert(claim.clone(), Value::from(email.clone()));
+                }
+            }
+        }
+        user_info_from_claims(&claims, &self.claims)
+    }
+}
+
+fn claim_value(claims: &Value, names: &[String]) -> String {
+    names.iter()
+        .filter_map(|name| claims.get(name).and_then(|v| v.as_str()))
+        .find(|s| !s.is_empty())
+        .unwrap_or_default()
+        .to_string()
+}
+fn primary_email(emails: &Value) -> Option<String> {
+    let emails = emails.as_array()?;
+    emails.iter()
+        .find(|e| e.get("primary").and_then(|v| v.as_bool()).unwrap_or(false) && e.get("verified").and_then(|v| v.as_bool()).unwrap_or(false))
+        .and_then(|e| e.get("email").and_then(|v| v.as_str()))
+        .map(|s| s.to_string())
+}
+pub fn user_info_from_claims(claims: &Value, mapping: &ClaimMapping) -> anyhow::Result<UserInfo> {
+    let email = claim_value(claims, &mapping.email);
+    if email.is_empty() {
+        return Err(anyhow!("User email must be set"));
+    };
+    // some providers send the claim as string
+    match claims.get("email_verified") {
+        Some(Value::Bool(false)) => return Err(anyhow!("User email is not verified")),
+        Some(Value::String(verified)) if verified == "false" => return Err(anyhow!("User email is not verified")),
+        _ => {}
+    }
+    Ok(UserInfo {
+        name: claim_value(claims, &mapping.name),
+        email,
+        picture: claim_value(claims, &mapping.picture),
+    })
+}
+
+pub struct OAuthProviders(pub Vec<OAuthProvider>);
+impl OAuthProviders {
+    pub fn from_config(config: BTreeMap<String, OAuthProviderConfig>) -> anyhow::Result<Self> {
+        let providers = config.into_iter()
+            .map(|(name, cfg)| OAuthProvider::from_config(&name, cfg))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self(providers))
+    }
+    pub fn find(&self, name: &str) -> Option<&OAuthProvider> {
+        self.0.iter().find(|p| p.name == name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use super::*;
+
+    fn config(provider: Option<&str>) -> OAuthProviderConfig {
+        OAuthProviderConfig {
+            provider: provider.map(|s| s.to_string()),
+            display_name: None,
+            client_id: "client-id".to_string(),
+            client_secret: "client-secret".to_string(),
+            redirect_uri: "http://localhost:8000/auth/idp".to_string(),
+            tenant: None,
+            auth_uri: None,
+            token_uri: None,
+            userinfo_uri: None,
+            emails_uri: None,
+            scopes: None,
+            claims: None,
+        }
+    }
+
+    #[test]
+    fn test_custom_provider_must_define_uris() {
+        assert!(OAuthProvider::from_config("idp", config(None)).is_err());
+        let cfg = OAuthProviderConfig {
+            auth_uri: Some("http://localhost:8080/realms/qx/auth".to_string()),
+            token_uri: Some("http://localhost:8080/realms/qx/token".to_string()),
+            userinfo_uri: Some("http://localhost:8080/realms/qx/userinfo".to_string()),
+            ..config(None)
+        };
+        let provider = OAuthProvider::from_config("idp", cfg).unwrap();
+        assert_eq!(provider.display_name, "idp");
+        assert_eq!(provider.login_uri().unwrap(), "http://localhost:8000/login/idp");
+        let url = provider.authorize_url("xyz").unwrap();
+        assert!(url.starts_with("http://localhost:8080/realms/qx/auth?response_type=code&client_id=client-id"));
+        assert!(url.contains("scope=openid+profile+email"));
+        assert!(url.ends_with("state=xyz"));
+    }
+
+    #[test]
+    fn test_claim_mapping() {
+        let github = OAuthProvider::from_config("github", config(Some("GitHub"))).unwrap();
+        let info = user_info_from_claims(&json!({
+            "login": "jdoe",
+            "name": null,
+            "email": "john@doe",
+            "avatar_url": "https://avatars.example/jdoe",
+        }), &github.claims).unwrap();
+        assert_eq!(info.name, "jdoe");
+        assert_eq!(info.email, "john@doe");
+        assert_eq!(info.picture, "https://avatars.example/jdoe");
+
+        let google = OAuthProvider::from_config("google", config(Some("Google"))).unwrap();
+        assert!(user_info_from_claims(&json!({"name": "John Doe"}), &google.claims).is_err());
+        assert!(user_info_from_claims(&json!({"email": "john@doe", "email_verified": true}), &google.claims).is_ok());
+        assert!(user_info_from_claims(&json!({"email": "john@doe", "email_verified": false}), &google.claims).is_err());
+        assert!(user_info_from_claims(&json!({"email": "john@doe", "email_verified": "false"}), &google.claims).is_err());
+    }
+
+    #[test]
+    fn test_microsoft_requires_tenant() {
+        assert!(OAuthProvider::from_config("ms", config(Some("Microsoft"))).is_err());
+        let cfg = |tenant: &str| OAuthProviderConfig { tenant: Some(tenant.to_string()), ..config(Some("Microsoft")) };
+        assert!(OAuthProvider::from_config("ms", cfg("common")).is_err());
+        assert!(OAuthProvider::from_config("ms", cfg("organizations")).is_err());
+        let provider = OAuthProvider::from_config("ms", cfg("club.onmicrosoft.com")).unwrap();
+        assert!(provider.authorize_url("xyz").unwrap().starts_with("https://login.microsoftonline.com/club.onmicrosoft.com/oauth2/v2.0/authorize?"));
+        assert_eq!(provider.token_uri, "https://login.microsoftonline.com/club.onmicrosoft.com/oauth2/v2.0/token");
+    }
+
+    #[test]
+    fn test_primary_email() {
+        let emails = json!([
+            {"email": "john@work", "primary": false, "verified": true},
+            {"email": "john@doe", "primary": true, "verified": true},
+        ]);
+        assert_eq!(primary_email(&emails), Some("john@doe".to_string()));
+    }
+}
@@ -8,10 +8,12 @@ use rocket::serde::{Deserialize, Serialize};
 use rocket::{Build, Rocket, State};
 use rocket_dyn_templates::{Template};
 use sqlx::{FromRow};
+use crate::apitoken::ApiScope;
 use crate::db::{DbPool};
 use crate::{impl_sqlx_json_text_type_encode_decode, QxApiToken, SharedQxState};
 use crate::event::{load_event_info, load_event_info_for_api_token, EventId, SiId};
 use crate::qxdatetime::QxDateTime;
+use chrono_tz::Tz;
 use crate::util::{anyhow_to_custom_error};
 use sqlx::sqlite::SqliteArgumentValue;
 use sqlx::{Encode, Sqlite};
@@ -94,15 +96,16 @@ pub(crate) fn load_oc_dir(data_dir: &str) -> anyhow::Result<Vec<OCheckListChange
 #[test]
 fn test_load_oc() {
     let data = load_oc_dir("tests/oc/data").unwrap();
+    let tz = crate::qxdatetime::parse_time_zone("Europe/Prague").unwrap();
     for change_set in data {
-        let change_dt = QxDateTime::parse_from_string(&change_set.Created, Some(QxDateTime::now().0.offset())).unwrap();
+        let change_dt = QxDateTime::parse_from_string(&change_set.Created, Some(&tz)).unwrap();
         for chng in change_set.Data {
             debug!("{:?}", serde_json::to_string(&chng).unwrap());
             let is_dns = || {
                 let Some(chnglog) = &chng.ChangeLog else { return false };
                 chnglog.get("DNS").is_some()
             };
-            let (run_id, run_chng) = RunChange::try_from_oc_change(&chng, change_dt).unwrap();
+            let (run_id, run_chng) = RunChange::try_from_oc_change(&chng, change_dt, &tz).unwrap();
             debug!("{:?}\n", serde_json::to_string(&run_chng).unwrap());
             assert!(run_id > 0);
             if chng.Runner.StartTime.is_some() && !is_dns() {
@@ -112,9 +115,8 @@ fn test_load_oc() {
     }
 }
 
-pub(crate) async fn add_oc_change_set(event_id: EventId, change_set: OCheckListChangeSet, state: &State<SharedQxState>) -> anyhow::Result<()> {
-    let now = QxDateTime::now();
-    let change_dt = QxDateTime::parse_from_string(&change_set.Created, Some(now.0.offset()))?;
+pub(crate) async fn add_oc_change_set(event_id: EventId, tz: &Tz, change_set: OCheckListChangeSet, state: &State<SharedQxState>) -> anyhow::Result<()> {
+    let change_dt = QxDateTime::parse_from_string(&change_set.Created, Some(tz))?;
     for chng in change_set.Data {
         let data_type = DataType::OcChange;
         let data = ChangeData::OcChange(chng.clone());
@@ -130,7 +132,7 @@ pub(crate) async fn add_oc_change_set(event_id: EventId, change_set: OCheckListC
             created: QxDateTime::now(),
             lock_number: None,
         }, state).await?;
-        match RunChange::try_from_oc_change(&chng, change_dt) {
+        match RunChange::try_from_oc_change(&chng, change_dt, tz) {
             Ok((run_id, run_chng)) => {
                 let data_type = DataType::RunUpdateRequest;
                 let data = ChangeData::RunUpdateRequest(run_chng);
@@ -158,7 +160,7 @@ pub(crate) async fn add_oc_change_set(event_id: EventId, change_set: OCheckListC
 
 #[post("/api/event/current/oc", data = "<change_set_yaml>")]
 async fn post_oc_change_set(api_token: QxApiToken, change_set_yaml: &str, state: &State<SharedQxState>, db: &State<DbPool>) -> Result<(), Custom<String>> {
-    let event = load_event_info_for_api_token(&api_token, db).await?;
+    let event = load_event_info_for_api_token(&api_token, ApiScope::OcUpload, db).await?;
     let change_set: OCheckListChangeSet = match serde_yaml::from_str(change_set_yaml) {
         Ok(change_set) => {
             change_set
@@ -169,9 +171,10 @@ async fn post_oc_change_set(api_token: QxApiToken, change_set_yaml: &str, state:
             return Err(Custom(Status::InternalServerError, e.to_string()));
         }
     };
-    add_oc_change_set(event.id, change_set, state).await.map_err(anyhow_to_custom_error)?;
+    add_oc_change_set(event.id, &event.tz(), change_set, state).await.map_err(anyhow_to_custom_error)?;
     Ok(())
 }
+#[allow(dead_code)]
 #[derive(Serialize, FromRow, Clone, Debug)]
 struct OCOutRecord {
     id: i64,
@@ -0,0 +1,315 @@
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
+use chrono::TimeDelta;
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::response::status::Custom;
+use rocket::{request, Build, Rocket, State};
+use rocket_dyn_templates::{context, Template};
+use serde::{Deserialize, Serialize};
+use sqlx::{Encode, FromRow, Sqlite};
+use sqlx::sqlite::SqliteArgumentValue;
+use crate::apitoken::{generate_api_token, generate_salt, hash_api_token, token_prefix, LAST_USED_UPDATE_INTERVAL_SEC};
+use crate::auth::UserInfo;
+use crate::db::DbPool;
+use crate::event::user_info;
+use crate::qxdatetime::QxDateTime;
+use crate::util::{sqlx_to_anyhow, sqlx_to_custom_error};
+use crate::{impl_sqlx_json_text_type_encode_decode, ratelimit, session_id_outcome, QxSession, QxSessionId, SharedQxState};
+
+// sessions resolved from personal tokens live in the sessions cache only, they are not stored in DB
+const PERSONAL_TOKEN_SESSION_PREFIX: &str = "pat:";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum PersonalScope {
+    /// read data accessible to user
+    Read,
+    /// create and delete own change requests, implies read
+    Changes,
+    /// act as user in everything, implies all other scopes
+    Manage,
+}
+impl PersonalScope {
+    pub const ALL: [PersonalScope; 3] = [PersonalScope::Read, PersonalScope::Changes, PersonalScope::Manage];
+    fn try_from_str(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scope| scope.to_string() == s)
+    }
+}
+impl Display for PersonalScope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PersonalScope::Read => f.write_str("read"),
+            PersonalScope::Changes => f.write_str("changes"),
+            PersonalScope::Manage => f.write_str("manage"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default, Debug)]
+pub struct PersonalScopes(pub Vec<PersonalScope>);
+impl_sqlx_json_text_type_encode_decode!(PersonalScopes);
+impl PersonalScopes {
+    pub fn contains(&self, scope: PersonalScope) -> bool {
+        self.0.iter().any(|s| match s {
+            PersonalScope::Manage => true,
+            PersonalScope::Changes => scope != PersonalScope::Manage,
+            PersonalScope::Read => scope == PersonalScope::Read,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, FromRow, Clone, Debug)]
+pub struct PersonalTokenRecord {
+    pub id: i64,
+    pub user_email: String,
+    pub user_name: String,
+    pub user_picture: String,
+    pub name: String,
+    pub token_prefix: String,
+    #[serde(skip)]
+    token_salt: String,
+    #[serde(skip)]
+    token_hash: String,
+    pub scopes: PersonalScopes,
+    pub created: QxDateTime,
+    pub expires: Option<QxDateTime>,
+    pub last_used: Option<QxDateTime>,
+    pub revoked: bool,
+}
+impl PersonalTokenRecord {
+    fn is_valid(&self, now: &QxDateTime) -> bool {
+        !self.revoked && self.expires.is_none_or(|expires| expires.0 > now.0)
+    }
+    fn matches(&self, token: &str) -> bool {
+        hash_api_token(token, &self.token_salt) == self.token_hash
+    }
+    fn user_info(&self) -> UserInfo {
+        UserInfo {
+            name: self.user_name.clone(),
+            email: self.user_email.clone(),
+            picture: self.user_picture.clone(),
+        }
+    }
+}
+
+pub fn is_personal_token_session(session_id: &QxSessionId) -> bool {
+    session_id.0.starts_with(PERSONAL_TOKEN_SESSION_PREFIX)
+}
+
+/// Token from `Authorization: Bearer <token>` header
+pub fn bearer_token<'r>(request: &'r request::Request<'_>) -> Option<&'r str> {
+    request.headers().get_one("Authorization")?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+async fn resolve_personal_token(token: &str, db: &State<DbPool>) -> anyhow::Result<Option<PersonalTokenRecord>> {
+    let candidates: Vec<PersonalTokenRecord> = sqlx::query_as("SELECT * FROM personal_tokens WHERE token_prefix=?")
+        .bind(token_prefix(token))
+        .fetch_all(&db.0)
+        .await.map_err(sqlx_to_anyhow)?;
+    let now = QxDateTime::now();
+    let Some(rec) = candidates.into_iter().find(|rec| rec.matches(token) && rec.is_valid(&now)) else {
+        return Ok(None);
+    };
+    if rec.last_used.is_none_or(|last_used| now.0.signed_duration_since(last_used.0).num_seconds() > LAST_USED_UPDATE_INTERVAL_SEC) {
+        sqlx::query("UPDATE personal_tokens SET last_used=? WHERE id=?")
+            .bind(now.trimmed_to_sec())
+            .bind(rec.id)
+            .execute(&db.0)
+            .await.map_err(sqlx_to_anyhow)?;
+    }
+    Ok(Some(rec))
+}
+
+/// Session of personal token sent as bearer token, the token is verified on every request,
+/// so revoked tokens stop working immediately.
+/// Returns `Ok(None)` if there is no bearer token and error status if the token is invalid or it does not have `scope`.
+pub(crate) async fn resolve_bearer_session(request: &request::Request<'_>, scope: PersonalScope) -> Result<Option<QxSessionId>, Status> {
+    let session = request.local_cache_async(async {
+        let Some(token) = bearer_token(request) else {
+            return Ok(None);
+        };
+        let (Some(state), Some(db)) = (
+            request.guard::<&State<SharedQxState>>().await.succeeded(),
+            request.guard::<&State<DbPool>>().await.succeeded(),
+        ) else {
+            return Err(Status::InternalServerError);
+        };
+        let rec = match resolve_personal_token(token, db).await {
+            Ok(Some(rec)) => rec,
+            Ok(None) => {
+                warn!("Unauthorized request with invalid personal token, client IP: {:?}", request.client_ip());
+                ratelimit::record_failed_lookup(request);
+                return Err(Status::Unauthorized);
+            }
+            Err(e) => {
+                error!("Resolve personal token error: {e}");
+                return Err(Status::InternalServerError);
+            }
+        };
+        let session_id = QxSessionId(format!("{PERSONAL_TOKEN_SESSION_PREFIX}{}", rec.id));
+        let now = QxDateTime::now().trimmed_to_sec();
+        state.write().await.sessions.insert(session_id.clone(), QxSession { user_info: rec.user_info(), created: now, last_seen: now });
+        Ok(Some((session_id, rec)))
+    }).await;
+    match session {
+        Ok(Some((session_id, rec))) => {
+            if !rec.scopes.contains(scope) {
+                warn!("Personal token '{}' of user {} is missing scope: {scope}", rec.name, rec.user_email);
+                return Err(Status::Forbidden);
+            }
+            Ok(Some(session_id.clone()))
+        }
+        Ok(None) => Ok(None),
+        Err(status) => Err(*status),
+    }
+}
+
+/// Personal token scope required by route
+pub trait TokenScope: Send + Sync + 'static {
+    const SCOPE: PersonalScope;
+}
+pub struct ReadScope;
+impl TokenScope for ReadScope {
+    const SCOPE: PersonalScope = PersonalScope::Read;
+}
+pub struct ChangesScope;
+impl TokenScope for ChangesScope {
+    const SCOPE: PersonalScope = PersonalScope::Changes;
+}
+
+/// Session of logged-in user, personal token sent as bearer token must have scope `S`.
+/// Routes taking plain `QxSessionId` require the `manage` scope.
+pub struct ScopedSessionId<S: TokenScope> {
+    pub session_id: QxSessionId,
+    scope: PhantomData<S>,
+}
+
+#[rocket::async_trait]
+impl<'r, S: TokenScope> request::FromRequest<'r> for ScopedSessionId<S> {
+    type Error = ();
+    async fn from_request(request: &'r request::Request<'_>) -> request::Outcome<Self, ()> {
+        session_id_outcome(request, S::SCOPE).await.map(|session_id| Self { session_id, scope: PhantomData })
+    }
+}
+
+/// Personal tokens can be managed in browser session only, not by another personal token
+fn require_browser_session(session_id: &QxSessionId) -> Result<(), Custom<String>> {
+    if is_personal_token_session(session_id) {
+        return Err(Custom(Status::Forbidden, "Personal tokens cannot be managed using personal token".to_string()));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct PersonalTokenView {
+    #[serde(flatten)]
+    token: PersonalTokenRecord,
+    is_valid: bool,
+}
+#[get("/profile/tokens")]
+async fn get_personal_tokens(session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    require_browser_session(&session_id)?;
+    let user = user_info(&session_id, state).await?;
+    let tokens: Vec<PersonalTokenRecord> = sqlx::query_as("SELECT * FROM personal_tokens WHERE user_email=? ORDER BY id")
+        .bind(&user.email)
+        .fetch_all(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    let now = QxDateTime::now();
+    let tokens = tokens.into_iter()
+        .map(|token| PersonalTokenView { is_valid: token.is_valid(&now), token })
+        .collect::<Vec<_>>();
+    Ok(Template::render("personal-tokens", context! {
+        user,
+        tokens,
+        scopes: PersonalScope::ALL,
+    }))
+}
+
+#[derive(Debug, FromForm)]
+struct PersonalTokenFormValues<'v> {
+    #[field(validate = len(1..))]
+    name: &'v str,
+    scopes: Vec<&'v str>,
+    expires_days: Option<i64>,
+}
+#[post("/profile/tokens/create", data = "<form>")]
+async fn post_personal_token(form: Form<PersonalTokenFormValues<'_>>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    require_browser_session(&session_id)?;
+    let user = user_info(&session_id, state).await?;
+    let scopes = form.scopes.iter()
+        .map(|s| PersonalScope::try_from_str(s).ok_or(Custom(Status::BadRequest, format!("Invalid personal token scope: {s}"))))
+        .collect::<Result<Vec<_>, _>>()?;
+    if scopes.is_empty() {
+        return Err(Custom(Status::BadRequest, "At least one personal token scope must be selected".to_string()));
+    }
+    let expires = form.expires_days
+        .and_then(|days| QxDateTime::now().trimmed_to_sec().0.checked_add_signed(TimeDelta::days(days)))
+        .map(QxDateTime);
+    let token = generate_api_token();
+    let salt = generate_salt();
+    sqlx::query("INSERT INTO personal_tokens (user_email, user_name, user_picture, name, token_prefix, token_salt, token_hash, scopes, created, expires)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+        .bind(&user.email)
+        .bind(&user.name)
+        .bind(&user.picture)
+        .bind(form.name)
+        .bind(token_prefix(&token))
+        .bind(&salt)
+        .bind(hash_api_token(&token, &salt))
+        .bind(PersonalScopes(scopes))
+        .bind(QxDateTime::now().trimmed_to_sec())
+        .bind(expires)
+        .execute(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    info!("Personal token '{}' created by user: {}", form.name, user.email);
+    Ok(Template::render("personal-token-created", context! {
+        user,
+        name: form.name,
+        token,
+    }))
+}
+
+#[derive(Debug, FromForm)]
+struct RevokeFormValues {
+    id: i64,
+}
+#[post("/profile/tokens/revoke", data = "<form>")]
+async fn revoke_personal_token(form: Form<RevokeFormValues>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    require_browser_session(&session_id)?;
+    let user = user_info(&session_id, state).await?;
+    sqlx::query("UPDATE personal_tokens SET revoked=1 WHERE id=? AND user_email=?")
+        .bind(form.id)
+        .bind(&user.email)
+        .execute(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    state.write().await.sessions.remove(&QxSessionId(format!("{PERSONAL_TOKEN_SESSION_PREFIX}{}", form.id)));
+    Ok(Redirect::to("/profile/tokens"))
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket.mount("/", routes![
+        get_personal_tokens,
+        post_personal_token,
+        revoke_personal_token,
+    ])
+}
+
+#[test]
+fn test_personal_scopes() {
+    let scopes = PersonalScopes(vec![PersonalScope::Read]);
+    assert!(scopes.contains(PersonalScope::Read));
+    assert!(!scopes.contains(PersonalScope::Changes));
+    let scopes = PersonalScopes(vec![PersonalScope::Changes]);
+    assert!(scopes.contains(PersonalScope::Read));
+    assert!(scopes.contains(PersonalScope::Changes));
+    assert!(!scopes.contains(PersonalScope::Manage));
+    let scopes = PersonalScopes(vec![PersonalScope::Manage]);
+    assert!(PersonalScope::ALL.into_iter().all(|scope| scopes.contains(scope)));
+    for scope in PersonalScope::ALL {
+        assert_eq!(PersonalScope::try_from_str(&scope.to_string()), Some(scope));
+    }
+}
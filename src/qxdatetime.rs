@@ -1,5 +1,6 @@
 use anyhow::anyhow;
-use chrono::{DateTime, FixedOffset, MappedLocalTime, NaiveDateTime, SecondsFormat, TimeDelta};
+use chrono::{DateTime, FixedOffset, MappedLocalTime, NaiveDateTime, SecondsFormat, TimeDelta, TimeZone};
+use chrono_tz::Tz;
 use rocket::serde::{Deserialize, Serialize};
 use sqlx::{Encode, Sqlite};
 use sqlx::sqlite::SqliteArgumentValue;
@@ -27,11 +28,19 @@ impl QxDateTime {
             *self
         }
     }
-    pub fn from_local_timezone(local_dt: NaiveDateTime, offset: &FixedOffset) -> Option<QxDateTime> {
-        let fixed_offset = FixedOffset::east_opt(offset.local_minus_utc())?;
-        match local_dt.and_local_timezone(fixed_offset) {
-            MappedLocalTime::Single(dt) => {Some(QxDateTime(dt))}
-            MappedLocalTime::Ambiguous(_, _) => None,
+    pub fn now_in_time_zone(tz: &Tz) -> Self {
+        Self::now().in_time_zone(tz)
+    }
+    /// Same instant with UTC offset of time zone `tz`
+    pub fn in_time_zone(&self, tz: &Tz) -> Self {
+        QxDateTime(self.0.with_timezone(tz).fixed_offset())
+    }
+    /// Local time in time zone `tz`, time repeated on DST end is resolved to the earlier one,
+    /// time skipped on DST start does not exist
+    pub fn from_local_timezone<T: TimeZone>(local_dt: NaiveDateTime, tz: &T) -> Option<QxDateTime> {
+        match local_dt.and_local_timezone(tz.clone()) {
+            MappedLocalTime::Single(dt) => Some(QxDateTime(dt.fixed_offset())),
+            MappedLocalTime::Ambiguous(earliest, _) => Some(QxDateTime(earliest.fixed_offset())),
             MappedLocalTime::None => None,
         }
     }
@@ -50,7 +59,7 @@ impl QxDateTime {
         // println!("{datetime_str} -> {dt:?}");
         Ok(Self::from_fixed_offset(dt))
     }
-    pub(crate) fn parse_from_string(datetime_str: &str, local_time_offset: Option<&FixedOffset>) -> Result<Self, anyhow::Error> {
+    pub(crate) fn parse_from_string<T: TimeZone>(datetime_str: &str, local_time_zone: Option<&T>) -> Result<Self, anyhow::Error> {
         // ISO 8601 / RFC 3339 date & time format, https://docs.rs/chrono/latest/chrono/format/strftime/index.html
         for format in [
             "%Y-%m-%dT%H:%M:%S%.f%:z",
@@ -60,15 +69,14 @@ impl QxDateTime {
                 return Ok(Self::from_fixed_offset(dt));
             }
         }
-        if let Some(local_offset) = local_time_offset {
+        if let Some(local_time_zone) = local_time_zone {
             for format in [
                 "%Y-%m-%dT%H:%M:%S%.f",
                 "%Y-%m-%d %H:%M:%S%.f",
             ] {
-                if let Ok(dt) = NaiveDateTime::parse_from_str(datetime_str, format) {
-                    if let Some(dt) = Self::from_local_timezone(dt, local_offset) {
-                        return Ok(dt);
-                    }
+                if let Ok(dt) = NaiveDateTime::parse_from_str(datetime_str, format)
+                    && let Some(dt) = Self::from_local_timezone(dt, local_time_zone) {
+                    return Ok(dt);
                 }
             }
         }
@@ -144,6 +152,24 @@ fn test_parse_from_string() {
     }
 }
 #[test]
+fn test_time_zone() {
+    let tz = parse_time_zone("Europe/Prague").unwrap();
+    let winter = NaiveDateTime::parse_from_str("2025-03-01 10:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    let summer = NaiveDateTime::parse_from_str("2025-06-01 10:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    assert_eq!(QxDateTime::from_local_timezone(winter, &tz).unwrap().to_iso_string(), "2025-03-01T10:00:00+01:00");
+    assert_eq!(QxDateTime::from_local_timezone(summer, &tz).unwrap().to_iso_string(), "2025-06-01T10:00:00+02:00");
+    // skipped and repeated hour on DST start and end
+    let skipped = NaiveDateTime::parse_from_str("2025-03-30 02:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    assert!(QxDateTime::from_local_timezone(skipped, &tz).is_none());
+    let repeated = NaiveDateTime::parse_from_str("2025-10-26 02:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    assert_eq!(QxDateTime::from_local_timezone(repeated, &tz).unwrap().to_iso_string(), "2025-10-26T02:30:00+02:00");
+
+    let utc_dt = QxDateTime::parse_from_iso("2025-06-01T08:00:00Z").unwrap();
+    assert_eq!(utc_dt.in_time_zone(&tz).to_iso_string(), "2025-06-01T10:00:00+02:00");
+    assert_eq!(dtstr(Some("2025-06-01T08:00:00Z"), Some("Europe/Prague")), "2025-06-01 10:00:00");
+    assert!(parse_time_zone("Mars/Olympus").is_err());
+}
+#[test]
 fn test_parse_qxdatetime() {
     for (dtstr, dtstr2) in &[
         ("1970-03-05 14:32:45+00:00", "1970-03-05T14:32:45Z"),
@@ -171,12 +197,20 @@ pub(crate) fn obtimems(msec_since_midnight: i64) -> String {
     let sec = sec % 60;
     format!("{min}:{sec:0>2}.{msec:0>3}")
 }
-pub(crate) fn dtstr(iso_date_str: Option<&str>) -> String {
+/// Parse IANA time zone name like `Europe/Prague`
+pub(crate) fn parse_time_zone(time_zone: &str) -> anyhow::Result<Tz> {
+    time_zone.parse::<Tz>().map_err(|e| anyhow!("Invalid time zone: {time_zone}, error: {e}"))
+}
+/// Display date time string, converted to `time_zone` if it is specified
+pub(crate) fn dtstr(iso_date_str: Option<&str>, time_zone: Option<&str>) -> String {
     let Some(s) = iso_date_str else {
         return "---".to_string()
     };
     if let Ok(dt) = QxDateTime::parse_from_iso(s) {
-        dt.to_display_string()
+        match time_zone.and_then(|tz| parse_time_zone(tz).ok()) {
+            Some(tz) => dt.in_time_zone(&tz).to_display_string(),
+            None => dt.to_display_string(),
+        }
 
     } else {
         s.to_string()
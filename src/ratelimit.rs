@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Method};
+use rocket::{request, Build, Data, Request, Rocket};
+use serde::Deserialize;
+use crate::personaltoken::bearer_token;
+
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+const RATE_LIMITED_PATH: &str = "/rate-limited";
+// stale entries are removed when map grows over this size
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Rate limits, configured in `[default.rate_limit]` section of Rocket.toml
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    enabled: bool,
+    // max requests per minute from one client IP address
+    ip_requests_per_minute: u32,
+    // max requests per minute with one API token
+    token_requests_per_minute: u32,
+    // failed API token or password checks from one IP address before it is locked out
+    max_failed_lookups: u32,
+    // lockout duration in seconds
+    lockout_sec: u64,
+}
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ip_requests_per_minute: 600,
+            token_requests_per_minute: 300,
+            max_failed_lookups: 10,
+            lockout_sec: 15 * 60,
+        }
+    }
+}
+
+#[derive(Hash, Eq, PartialEq, Clone, Debug)]
+enum RateLimitKey {
+    Ip(IpAddr),
+    // only token prefix is used as a key, not to keep API tokens in memory
+    Token(String),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Window {
+    start: Instant,
+    count: u32,
+}
+impl Window {
+    /// Count hit in window of `length`, returns hits count in current window
+    fn hit(&mut self, now: Instant, length: Duration) -> u32 {
+        if now.duration_since(self.start) >= length {
+            self.start = now;
+            self.count = 0;
+        }
+        self.count += 1;
+        self.count
+    }
+    fn retry_after(&self, now: Instant, length: Duration) -> Duration {
+        length.saturating_sub(now.duration_since(self.start))
+    }
+}
+
+fn prune<K>(map: &mut HashMap<K, Window>, now: Instant, length: Duration) {
+    if map.len() > PRUNE_THRESHOLD {
+        map.retain(|_, w| now.duration_since(w.start) < length);
+    }
+}
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    requests: Mutex<HashMap<RateLimitKey, Window>>,
+    failed_lookups: Mutex<HashMap<IpAddr, Window>>,
+    locked_out: Mutex<HashMap<IpAddr, Instant>>,
+}
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            requests: Default::default(),
+            failed_lookups: Default::default(),
+            locked_out: Default::default(),
+        }
+    }
+    fn lockout_duration(&self) -> Duration {
+        Duration::from_secs(self.config.lockout_sec)
+    }
+    /// Returns time to wait, when request limit for `key` is exceeded
+    fn check_request(&self, key: RateLimitKey, limit: u32, now: Instant) -> Result<(), Duration> {
+        let mut requests = self.requests.lock().expect("rate limiter lock");
+        prune(&mut requests, now, RATE_LIMIT_WINDOW);
+        let window = requests.entry(key).or_insert(Window { start: now, count: 0 });
+        if window.hit(now, RATE_LIMIT_WINDOW) > limit {
+            return Err(window.retry_after(now, RATE_LIMIT_WINDOW));
+        }
+        Ok(())
+    }
+    fn check_lockout(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut locked_out = self.locked_out.lock().expect("rate limiter lock");
+        match locked_out.get(&ip) {
+            Some(until) if *until > now => Err(*until - now),
+            Some(_) => {
+                locked_out.remove(&ip);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+    fn check(&self, ip: IpAddr, token: Option<&str>, now: Instant) -> Result<(), Duration> {
+        self.check_lockout(ip, now)?;
+        self.check_request(RateLimitKey::Ip(ip), self.config.ip_requests_per_minute, now)?;
+        if let Some(token) = token {
+            let prefix = token.chars().take(8).collect();
+            self.check_request(RateLimitKey::Token(prefix), self.config.token_requests_per_minute, now)?;
+        }
+        Ok(())
+    }
+    /// Count failed API token or password check, client is locked out after too many failures
+    pub fn record_failed_lookup(&self, ip: IpAddr) {
+        if self.config.enabled {
+            self.record_failed_lookup_at(ip, Instant::now());
+        }
+    }
+    fn record_failed_lookup_at(&self, ip: IpAddr, now: Instant) {
+        let lockout_duration = self.lockout_duration();
+        let mut failed_lookups = self.failed_lookups.lock().expect("rate limiter lock");
+        prune(&mut failed_lookups, now, lockout_duration);
+        let window = failed_lookups.entry(ip).or_insert(Window { start: now, count: 0 });
+        if window.hit(now, lockout_duration) >= self.config.max_failed_lookups {
+            failed_lookups.remove(&ip);
+            warn!("Client {ip} locked out for {} sec after repeated failed lookups", self.config.lockout_sec);
+            self.locked_out.lock().expect("rate limiter lock").insert(ip, now + lockout_duration);
+        }
+    }
+}
+
+/// Record failed lookup of request client, client IP respects `ip_header` config when running behind proxy
+pub fn record_failed_lookup(request: &Request<'_>) {
+    if let (Some(limiter), Some(ip)) = (request.rocket().state::<RateLimiter>(), request.client_ip()) {
+        limiter.record_failed_lookup(ip);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct RetryAfter(u64);
+
+#[rocket::async_trait]
+impl<'r> request::FromRequest<'r> for RetryAfter {
+    type Error = ();
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request::Outcome::Success(*request.local_cache(|| RetryAfter(1)))
+    }
+}
+
+#[derive(Responder)]
+#[response(status = 429)]
+struct TooManyRequests {
+    inner: &'static str,
+    retry_after: Header<'static>,
+}
+
+#[get("/rate-limited")]
+fn rate_limited(retry_after: RetryAfter) -> TooManyRequests {
+    TooManyRequests {
+        inner: "Too many requests",
+        retry_after: Header::new("Retry-After", retry_after.0.to_string()),
+    }
+}
+
+pub struct RateLimitFairing;
+
+#[rocket::async_trait]
+impl Fairing for RateLimitFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limiter",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let (Some(limiter), Some(ip)) = (request.rocket().state::<RateLimiter>(), request.client_ip()) else {
+            return;
+        };
+        if !limiter.config.enabled {
+            return;
+        }
+        let token = request.headers().get_one("qx-api-token").or_else(|| bearer_token(request));
+        if let Err(retry_after) = limiter.check(ip, token, Instant::now()) {
+            let retry_after = retry_after.as_secs().max(1);
+            debug!("Rate limit exceeded, client: {ip}, retry after: {retry_after} sec");
+            request.local_cache(|| RetryAfter(retry_after));
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(RATE_LIMITED_PATH).expect("valid URI"));
+        }
+    }
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    let config = rocket.figment().extract_inner::<RateLimitConfig>("rate_limit").unwrap_or_default();
+    rocket
+        .manage(RateLimiter::new(config))
+        .attach(RateLimitFairing)
+        .mount("/", routes![rate_limited])
+}
+
+#[test]
+fn test_rate_limit() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        ip_requests_per_minute: 2,
+        token_requests_per_minute: 3,
+        ..Default::default()
+    });
+    let now = Instant::now();
+    let ip1: IpAddr = "10.0.0.1".parse().unwrap();
+    let ip2: IpAddr = "10.0.0.2".parse().unwrap();
+    assert!(limiter.check(ip1, None, now).is_ok());
+    assert!(limiter.check(ip1, None, now).is_ok());
+    assert!(limiter.check(ip1, None, now).is_err());
+    assert!(limiter.check(ip1, None, now + RATE_LIMIT_WINDOW).is_ok());
+    // token limit is shared by all IPs
+    assert!(limiter.check(ip2, Some("token1234"), now).is_ok());
+    assert!(limiter.check(ip2, Some("token1234"), now).is_ok());
+    let ip3: IpAddr = "10.0.0.3".parse().unwrap();
+    assert!(limiter.check(ip3, Some("token1234"), now).is_ok());
+    assert!(limiter.check(ip3, Some("token1234"), now).is_err());
+}
+
+#[test]
+fn test_failed_lookup_lockout() {
+    let limiter = RateLimiter::new(RateLimitConfig {
+        max_failed_lookups: 3,
+        lockout_sec: 60,
+        ..Default::default()
+    });
+    let now = Instant::now();
+    let ip: IpAddr = "10.0.0.1".parse().unwrap();
+    limiter.record_failed_lookup_at(ip, now);
+    limiter.record_failed_lookup_at(ip, now);
+    assert!(limiter.check(ip, None, now).is_ok());
+    limiter.record_failed_lookup_at(ip, now);
+    let retry_after = limiter.check(ip, None, now).unwrap_err();
+    assert_eq!(retry_after, Duration::from_secs(60));
+    assert!(limiter.check(ip, None, now + Duration::from_secs(61)).is_ok());
+}
@@ -0,0 +1,248 @@
+use std::net::IpAddr;
+use anyhow::anyhow;
+use hmac::{Hmac, Mac};
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::Serialize;
+use rocket::{Build, Rocket, State};
+use rocket_dyn_templates::{context, Template};
+use sha2::Sha256;
+use crate::changes::{add_change, ChangeData, ChangeStatus, ChangesRecord, DataType, PENDING, RUN_UPDATE_REQUEST};
+use crate::db::{get_event_db, DbPool};
+use crate::event::{load_event_info, user_info, EventId, EventRecord};
+use crate::members::{require_event_role, EventRole};
+use crate::qxdatetime::QxDateTime;
+use crate::ratelimit::RateLimiter;
+use crate::runs::{ClassesRecord, RunChange, RunsRecord};
+use crate::util::{anyhow_to_custom_error, create_qrc, sqlx_to_anyhow, sqlx_to_custom_error};
+use crate::{QxSessionId, SharedQxState};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// signature is truncated to 16 bytes to keep the QR codes small
+const SIGNATURE_LEN: usize = 16;
+// max pending change requests submitted by run link, to prevent flooding the organizers
+const MAX_PENDING_RUN_LINK_REQUESTS: i64 = 5;
+
+fn run_mac(secret: &str, event_id: EventId, run_id: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts key of any size");
+    mac.update(format!("{event_id}:{run_id}").as_bytes());
+    mac
+}
+fn sign_run(secret: &str, event_id: EventId, run_id: i64) -> String {
+    hex::encode(&run_mac(secret, event_id, run_id).finalize().into_bytes()[..SIGNATURE_LEN])
+}
+fn verify_run_signature(secret: &str, event_id: EventId, run_id: i64, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    signature.len() == SIGNATURE_LEN && run_mac(secret, event_id, run_id).verify_truncated_left(&signature).is_ok()
+}
+
+/// Pseudonymous user ID of change requests submitted by run link
+fn run_link_user_id(run_id: i64) -> String {
+    format!("run-link:{run_id}")
+}
+
+/// Secret key of event run links, it is generated on first use
+async fn run_link_secret(event_id: EventId, gdb: &State<DbPool>) -> anyhow::Result<String> {
+    let secret: Option<(Option<String>,)> = sqlx::query_as("SELECT run_link_secret FROM events WHERE id=?")
+        .bind(event_id)
+        .fetch_optional(&gdb.0)
+        .await.map_err(sqlx_to_anyhow)?;
+    let Some((secret,)) = secret else {
+        return Err(anyhow!("Event id: {event_id} not found"));
+    };
+    if let Some(secret) = secret {
+        return Ok(secret);
+    }
+    sqlx::query("UPDATE events SET run_link_secret=? WHERE id=? AND run_link_secret IS NULL")
+        .bind(hex::encode(rand::random::<[u8; 32]>()))
+        .bind(event_id)
+        .execute(&gdb.0)
+        .await.map_err(sqlx_to_anyhow)?;
+    // read it back, secret might be set by concurrent request
+    let secret: (String,) = sqlx::query_as("SELECT run_link_secret FROM events WHERE id=?")
+        .bind(event_id)
+        .fetch_one(&gdb.0)
+        .await.map_err(sqlx_to_anyhow)?;
+    Ok(secret.0)
+}
+
+#[derive(Serialize, Debug)]
+struct RunLinkView {
+    run: RunsRecord,
+    link: String,
+    qrc_img_data: String,
+}
+
+#[get("/event/<event_id>/run-links?<class_name>")]
+async fn get_run_links(event_id: EventId, class_name: Option<&str>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let user = user_info(&session_id, state).await?;
+    require_event_role(&event, &user, EventRole::can_edit_event, gdb).await?;
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    let classes = sqlx::query_as::<_, ClassesRecord>("SELECT * FROM classes ORDER BY name")
+        .fetch_all(&edb).await.map_err(sqlx_to_custom_error)?;
+    let class_name = class_name.map(|s| s.to_string())
+        .or_else(|| classes.first().map(|c| c.name.clone()))
+        .unwrap_or_default();
+    let runs = sqlx::query_as::<_, RunsRecord>("SELECT * FROM runs WHERE class_name=? ORDER BY start_time")
+        .bind(&class_name)
+        .fetch_all(&edb).await.map_err(sqlx_to_custom_error)?;
+    let secret = run_link_secret(event_id, gdb).await.map_err(anyhow_to_custom_error)?;
+    let server_url = state.read().await.app_config.server_url();
+    let run_links = runs.into_iter()
+        .map(|run| {
+            let signature = sign_run(&secret, event_id, run.run_id);
+            let link = format!("{server_url}/event/{event_id}/run/{}/report?sig={signature}", run.run_id);
+            let qrc_img_data = create_qrc(link.as_bytes())?;
+            Ok(RunLinkView { run, link, qrc_img_data })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(anyhow_to_custom_error)?;
+    Ok(Template::render("run-links", context! {
+        user,
+        event,
+        classes,
+        class_name,
+        run_links,
+    }))
+}
+
+/// Check run link signature, failed checks count for client lockout as invalid API tokens do
+async fn verify_run_link(
+    event_id: EventId,
+    run_id: i64,
+    sig: &str,
+    client_ip: Option<IpAddr>,
+    rate_limiter: &State<RateLimiter>,
+    gdb: &State<DbPool>
+) -> Result<EventRecord, Custom<String>> {
+    let event = load_event_info(event_id, gdb).await?;
+    let secret = run_link_secret(event_id, gdb).await.map_err(anyhow_to_custom_error)?;
+    if !verify_run_signature(&secret, event_id, run_id, sig) {
+        warn!("Invalid run link signature, event id: {event_id}, run id: {run_id}, client IP: {client_ip:?}");
+        if let Some(ip) = client_ip {
+            rate_limiter.record_failed_lookup(ip);
+        }
+        return Err(Custom(Status::Forbidden, "Invalid link".to_string()));
+    }
+    Ok(event)
+}
+
+async fn load_run(run_id: i64, event_id: EventId, state: &State<SharedQxState>) -> Result<RunsRecord, Custom<String>> {
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    sqlx::query_as::<_, RunsRecord>("SELECT * FROM runs WHERE run_id=?")
+        .bind(run_id)
+        .fetch_optional(&edb).await.map_err(sqlx_to_custom_error)?
+        .ok_or(Custom(Status::NotFound, format!("Run id: {run_id} not found")))
+}
+
+#[get("/event/<event_id>/run/<run_id>/report?<sig>")]
+async fn get_run_report(
+    event_id: EventId,
+    run_id: i64,
+    sig: &str,
+    client_ip: Option<IpAddr>,
+    rate_limiter: &State<RateLimiter>,
+    state: &State<SharedQxState>,
+    gdb: &State<DbPool>
+) -> Result<Template, Custom<String>> {
+    let event = verify_run_link(event_id, run_id, sig, client_ip, rate_limiter, gdb).await?;
+    let run = load_run(run_id, event_id, state).await?;
+    Ok(Template::render("run-report", context! {
+        event,
+        run,
+        sig,
+        submitted: false,
+    }))
+}
+
+#[derive(Debug, FromForm)]
+struct RunReportFormValues<'v> {
+    si_id: Option<i64>,
+    first_name: &'v str,
+    last_name: &'v str,
+    note: &'v str,
+}
+fn non_empty(s: &str) -> Option<String> {
+    Some(s.trim()).filter(|s| !s.is_empty()).map(|s| s.to_string())
+}
+
+#[post("/event/<event_id>/run/<run_id>/report?<sig>", data = "<form>")]
+#[allow(clippy::too_many_arguments)]
+async fn post_run_report(
+    event_id: EventId,
+    run_id: i64,
+    sig: &str,
+    form: Form<RunReportFormValues<'_>>,
+    client_ip: Option<IpAddr>,
+    rate_limiter: &State<RateLimiter>,
+    state: &State<SharedQxState>,
+    gdb: &State<DbPool>
+) -> Result<Template, Custom<String>> {
+    let event = verify_run_link(event_id, run_id, sig, client_ip, rate_limiter, gdb).await?;
+    let run = load_run(run_id, event_id, state).await?;
+    let user_id = run_link_user_id(run_id);
+    let edb = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
+    let pending: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM changes WHERE data_type=? AND data_id=? AND user_id=? AND status=?")
+        .bind(RUN_UPDATE_REQUEST)
+        .bind(run_id)
+        .bind(&user_id)
+        .bind(PENDING)
+        .fetch_one(&edb).await.map_err(sqlx_to_custom_error)?;
+    if pending.0 >= MAX_PENDING_RUN_LINK_REQUESTS {
+        return Err(Custom(Status::TooManyRequests, "Too many pending change requests for this run".to_string()));
+    }
+    let run_change = RunChange {
+        si_id: form.si_id.filter(|si_id| Some(*si_id) != run.si_id),
+        first_name: non_empty(form.first_name).filter(|s| Some(s) != run.first_name.as_ref()),
+        last_name: non_empty(form.last_name).filter(|s| Some(s) != run.last_name.as_ref()),
+        note: non_empty(form.note),
+        ..Default::default()
+    };
+    if run_change.fields_with_value().is_empty() {
+        return Err(Custom(Status::BadRequest, "Nothing to change".to_string()));
+    }
+    add_change(event_id, ChangesRecord {
+        id: 0,
+        source: "www".to_string(),
+        data_type: DataType::RunUpdateRequest,
+        data_id: Some(run_id),
+        data: ChangeData::RunUpdateRequest(run_change),
+        user_id: Some(user_id),
+        status: Some(ChangeStatus::Pending),
+        status_message: None,
+        created: QxDateTime::now(),
+        lock_number: None,
+    }, state).await.map_err(anyhow_to_custom_error)?;
+    Ok(Template::render("run-report", context! {
+        event,
+        run,
+        sig,
+        submitted: true,
+    }))
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket.mount("/", routes![
+            get_run_links,
+            get_run_report,
+            post_run_report,
+        ])
+}
+
+#[test]
+fn test_run_signature() {
+    let secret = "0123456789abcdef";
+    let signature = sign_run(secret, 1, 42);
+    assert_eq!(signature.len(), 2 * SIGNATURE_LEN);
+    assert!(verify_run_signature(secret, 1, 42, &signature));
+    assert!(!verify_run_signature(secret, 1, 43, &signature));
+    assert!(!verify_run_signature(secret, 2, 42, &signature));
+    assert!(!verify_run_signature("other-secret", 1, 42, &signature));
+    assert!(!verify_run_signature(secret, 1, 42, &signature[..16]));
+    assert!(!verify_run_signature(secret, 1, 42, "not-hex"));
+}
@@ -1,10 +1,12 @@
 use chrono::{NaiveDateTime, NaiveTime, TimeDelta};
+use chrono_tz::Tz;
 use qxhttpd_proc_macros::FieldsWithValue;
 use rocket::{Build, Rocket, State};
 use rocket::response::status::Custom;
 use rocket::serde::json::Json;
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow};
+use crate::access::{EventRead, Runs};
 use crate::db::{get_event_db};
 use crate::event::{EventId};
 use crate::qxdatetime::QxDateTime;
@@ -64,18 +66,19 @@ pub struct RunChange {
 }
 
 impl RunChange {
-    pub fn try_from_oc_change(oc: &OCheckListChange, change_set_created_time: QxDateTime) -> anyhow::Result<(i64, Self)> {
+    /// Times without UTC offset are local times in event time zone `tz`
+    pub fn try_from_oc_change(oc: &OCheckListChange, change_set_created_time: QxDateTime, tz: &Tz) -> anyhow::Result<(i64, Self)> {
         let run_id = oc.Runner.Id.parse::<i64>()?;
         let mut change = Self::default();
         if let Some(start_time) = &oc.Runner.StartTime {
             // start time can be 10:20:30 or 25-05-01T10:20:03+01:00 depending on version of OCheckList
             change.check_time = if start_time.len() == 8 {
                 let tm = NaiveTime::parse_from_str(start_time, "%H:%M:%S")?;
-                let dt = change_set_created_time.0.date_naive();
+                let dt = change_set_created_time.in_time_zone(tz).0.date_naive();
                 let dt = NaiveDateTime::new(dt, tm);
-                QxDateTime::from_local_timezone(dt, change_set_created_time.0.offset())
+                QxDateTime::from_local_timezone(dt, tz)
             } else {
-                QxDateTime::parse_from_string(start_time, Some(change_set_created_time.0.offset()))?.0
+                QxDateTime::parse_from_string(start_time, Some(tz))?.0
                     // estimate check time to be 2 minutes before start time
                     .checked_sub_signed(TimeDelta::minutes(2))
                     .map(QxDateTime)
@@ -85,7 +88,7 @@ impl RunChange {
         if let Some(change_log) = &oc.ChangeLog {
             if let Some(dtstr) = change_log.get("Late start") {
                 // take check time from change log
-                let dt = QxDateTime::parse_from_string(dtstr, None)?;
+                let dt = QxDateTime::parse_from_string(dtstr, Some(tz))?;
                 change. check_time = Some(dt);
             }
             if let Some(_dtstr) = change_log.get("DNS") {
@@ -147,12 +150,12 @@ impl RunsRecord {
 // }
 
 #[get("/api/event/<event_id>/runs?<run_id>&<class_name>")]
-async fn get_runs(event_id: EventId, class_name: Option<&str>, run_id: Option<i32>, state: &State<SharedQxState>) -> Result<Json<Vec<RunsRecord>>, Custom<String>> {
+async fn get_runs(event_id: EventId, _access: EventRead<Runs>, class_name: Option<&str>, run_id: Option<i32>, state: &State<SharedQxState>) -> Result<Json<Vec<RunsRecord>>, Custom<String>> {
     let db = get_event_db(event_id, state).await.map_err(anyhow_to_custom_error)?;
-    let run_id_filter = run_id.map(|id| format!("AND run_id={id}")).unwrap_or_default();
-    let class_filter = class_name.map(|n| format!("AND class_name='{n}'")).unwrap_or_default();
-    let qs = format!("SELECT * FROM runs WHERE run_id>0 {run_id_filter} {class_filter} ORDER BY run_id");
-    let runs = sqlx::query_as::<_, RunsRecord>(&qs)
+    let runs = sqlx::query_as::<_, RunsRecord>("SELECT * FROM runs WHERE run_id>0
+                 AND (?1 IS NULL OR run_id=?1) AND (?2 IS NULL OR class_name=?2) ORDER BY run_id")
+        .bind(run_id)
+        .bind(class_name)
         .fetch_all(&db).await.map_err(sqlx_to_custom_error)?;
     Ok(runs.into())
 }
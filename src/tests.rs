@@ -4,22 +4,49 @@ use crate::changes::rocket_uri_macro_add_run_update_request_change;
 use crate::changes::rocket_uri_macro_api_changes_delete;
 use crate::event::{START_LIST_IOFXML3_FILE, DEMO_API_TOKEN, TEST_SESSION_ID};
 use std::fs::OpenOptions;
-use std::io::{Read};
-use rocket::local::blocking::Client;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::ops::Deref;
+use rocket::figment::Figment;
+use rocket::local::blocking::{Client, LocalRequest, LocalResponse};
 use rocket::http::{ContentType, Cookie, Header, Status};
-use crate::event::{EventId, EventRecord, EventInfo};
+use tempfile::TempDir;
+use crate::event::{EventId, EventRecord, EventInfo, ResultRecord};
+use crate::audit::{AuditRecord, CHANGE_CREATE, CHANGE_DELETE, ENTRY_CANCEL, ENTRY_CREATE};
+use crate::eventlist::EventListPage;
 use crate::files::FileInfo;
 use crate::qxdatetime::QxDateTime;
-use crate::{util};
+use crate::{util, SharedQxState};
 use crate::auth::QX_SESSION_ID;
+use crate::csrf::{CSRF_COOKIE, CSRF_HEADER};
 use crate::changes::DataId;
-use crate::runs::{RunChange, RunsRecord};
+use crate::runs::{ClassesRecord, RunChange, RunsRecord};
 use crate::changes::rocket_uri_macro_add_run_updated_change;
 
 const EVENT_ID: EventId = 1;
+const TEST_CSRF_TOKEN: &str = "csrf-test-token";
 
-fn create_test_server() -> Client {
-    let rocket = super::rocket()
+/// Test client with DB files in temp dir, the dir is removed when the client is dropped
+struct TestClient {
+    client: Client,
+    db_dir: TempDir,
+}
+impl Deref for TestClient {
+    type Target = Client;
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+fn create_test_server() -> TestClient {
+    create_test_server_with(rocket::Config::figment())
+}
+fn create_test_server_with(figment: Figment) -> TestClient {
+    create_test_server_in(tempfile::tempdir().unwrap(), figment)
+}
+fn create_test_server_in(db_dir: TempDir, figment: Figment) -> TestClient {
+    let figment = figment.merge(("db_path", db_dir.path().to_str().unwrap()));
+    let rocket = super::build_rocket(rocket::custom(figment))
         // doesn't work, don't know why
         //.attach(rocket::fairing::AdHoc::on_ignite("Secret Key", |rocket| async {
         //    rocket.manage(rocket::Config {
@@ -34,11 +61,11 @@ fn create_test_server() -> Client {
     //    .path("/")
     //    .same_site(SameSite::Lax));
     {
-        let resp = client.get("/event/create-demo").dispatch();
+        let resp = client.post("/event/create-demo").dispatch();
         // println!("body: {:?}", resp.body());
         assert_eq!(resp.status(), Status::SeeOther);
     }
-    client
+    TestClient { client, db_dir }
 }
 #[test]
 fn update_event_data() {
@@ -80,6 +107,180 @@ fn update_event_data() {
     assert_eq!(event.stage_count, post_event.stage_count);
 }
 
+#[test]
+fn update_event_data_requires_qe_sync_scope() {
+    let client = create_test_server();
+    let resp = post_form(&client, "/event", "id=0&name=Scoped&place=Here&stage=1&stage_count=1&start_time=2025-06-01T10:00:00&time_zone=Europe/Prague");
+    assert_eq!(resp.status(), Status::Ok);
+    let event_id = EVENT_ID + 1;
+    let resp = post_form(&client, &format!("/event/{event_id}/tokens/create"), "name=reader&scopes=read");
+    assert_eq!(resp.status(), Status::Ok);
+    let body = resp.into_string().unwrap();
+    let token = extract_code(&body);
+
+    let resp = client.post("/api/event/current")
+        .header(Header::new("qx-api-token", token))
+        .json(&EventInfo {
+            name: "Foo".to_string(),
+            stage: 1,
+            stage_count: 1,
+            place: "Bar".to_string(),
+            start_time: QxDateTime::now().0,
+            classes: vec![],
+        })
+        .dispatch();
+    assert_eq!(resp.status(), Status::Forbidden);
+}
+
+/// Minimal OpenID Connect provider answering token and user info requests with given claims, returns its base URL
+fn start_mock_idp(userinfo: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            let _ = reader.read_line(&mut request_line);
+            let mut content_length = 0;
+            let mut authorization = String::new();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim_end().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    match name.to_lowercase().as_str() {
+                        "content-length" => content_length = value.trim().parse().unwrap_or(0),
+                        "authorization" => authorization = value.trim().to_string(),
+                        _ => {}
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            let _ = reader.read_exact(&mut body);
+            let body = String::from_utf8_lossy(&body);
+            let (status, json) = if request_line.starts_with("POST /token ") && body.contains("code=mock-code") {
+                ("200 OK", r#"{"access_token":"mock-access-token","token_type":"Bearer"}"#)
+            } else if request_line.starts_with("GET /userinfo ") && authorization == "Bearer mock-access-token" {
+                ("200 OK", userinfo)
+            } else {
+                ("401 Unauthorized", r#"{"error":"invalid_grant"}"#)
+            };
+            let _ = write!(stream, "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{json}", json.len());
+        }
+    });
+    url
+}
+fn create_test_server_with_idp(idp_url: &str) -> TestClient {
+    create_test_server_with(rocket::Config::figment()
+        .merge(("oauth.mock", serde_json::json!({
+            "client_id": "qx",
+            "client_secret": "secret",
+            "redirect_uri": "http://localhost:8000/auth/mock",
+            "auth_uri": format!("{idp_url}/auth"),
+            "token_uri": format!("{idp_url}/token"),
+            "userinfo_uri": format!("{idp_url}/userinfo"),
+        }))))
+}
+#[test]
+fn oauth_login_with_mock_idp() {
+    let idp_url = start_mock_idp(r#"{"sub":"42","name":"Mock User","email":"mock@idp.local","email_verified":true}"#);
+    let client = create_test_server_with_idp(&idp_url);
+
+    // callback without state cookie
+    let resp = client.get("/auth/mock?code=mock-code&state=forged").dispatch();
+    assert_eq!(resp.status(), Status::BadRequest);
+
+    let resp = client.get("/login/mock").dispatch();
+    assert_eq!(resp.status(), Status::SeeOther);
+    let location = resp.headers().get_one("Location").unwrap().to_string();
+    assert!(location.starts_with(&format!("{idp_url}/auth?response_type=code&client_id=qx")));
+    let oauth_state = location.split("state=").nth(1).unwrap().to_string();
+
+    let resp = client.get(format!("/auth/mock?code=mock-code&state={oauth_state}")).dispatch();
+    assert_eq!(resp.status(), Status::SeeOther);
+    assert_eq!(resp.headers().get_one("Location"), Some("/"));
+    assert!(resp.cookies().get_private(QX_SESSION_ID).is_some());
+    let state = client.rocket().state::<SharedQxState>().unwrap().blocking_read();
+    assert!(state.sessions.values().any(|session| session.user_info.email == "mock@idp.local" && session.user_info.name == "Mock User"));
+    drop(state);
+
+    // state cookie is removed after the callback
+    let resp = client.get(format!("/auth/mock?code=mock-code&state={oauth_state}")).dispatch();
+    assert_eq!(resp.status(), Status::BadRequest);
+}
+#[test]
+fn oauth_login_with_unverified_email() {
+    let idp_url = start_mock_idp(r#"{"sub":"42","name":"Mock User","email":"mock@idp.local","email_verified":false}"#);
+    let client = create_test_server_with_idp(&idp_url);
+
+    let resp = client.get("/login/mock").dispatch();
+    let location = resp.headers().get_one("Location").unwrap().to_string();
+    let oauth_state = location.split("state=").nth(1).unwrap().to_string();
+    let resp = client.get(format!("/auth/mock?code=mock-code&state={oauth_state}")).dispatch();
+    assert_eq!(resp.status(), Status::Unauthorized);
+    assert!(resp.cookies().get_private(QX_SESSION_ID).is_none());
+    let state = client.rocket().state::<SharedQxState>().unwrap().blocking_read();
+    assert!(!state.sessions.values().any(|session| session.user_info.email == "mock@idp.local"));
+}
+
+#[test]
+fn create_demo_event_once_on_local_server() {
+    let client = create_test_server();
+    let resp = client.post("/event/create-demo").dispatch();
+    assert_eq!(resp.status(), Status::Conflict);
+    let resp = client.get("/event/create-demo").dispatch();
+    assert_eq!(resp.status(), Status::NotFound);
+
+    let db_dir = tempfile::tempdir().unwrap();
+    let figment = rocket::Config::figment()
+        .merge(("address", "0.0.0.0"))
+        .merge(("db_path", db_dir.path().to_str().unwrap()));
+    let client = Client::tracked(super::build_rocket(rocket::custom(figment))).unwrap();
+    let resp = client.post("/event/create-demo").dispatch();
+    assert_eq!(resp.status(), Status::Forbidden);
+}
+
+#[test]
+fn get_runs_by_class_name() {
+    let client = create_test_server();
+    let runs = |class_name: Option<&str>| client.get(uri!(get_runs(event_id=EVENT_ID, run_id=None::<i32>, class_name=class_name)))
+        .dispatch()
+        .into_json::<Vec<RunsRecord>>()
+        .unwrap();
+    let all_runs = runs(None);
+    let class_name = all_runs.first().and_then(|run| run.class_name.clone()).unwrap();
+    let class_runs = runs(Some(&class_name));
+    assert!(!class_runs.is_empty() && class_runs.len() < all_runs.len());
+    assert!(class_runs.iter().all(|run| run.class_name.as_deref() == Some(class_name.as_str())));
+    // class name is not interpolated into SQL
+    assert!(runs(Some("x' OR '1'='1")).is_empty());
+}
+
+#[test]
+fn invalid_api_token() {
+    let client = create_test_server();
+
+    let resp = client.get("/api/event/current")
+        .header(Header::new("qx-api-token", "invalid-token"))
+        .dispatch();
+    assert_eq!(resp.status(), Status::Unauthorized);
+}
+
+#[test]
+fn admin_console_access() {
+    let client = create_test_server();
+
+    let resp = client.get("/admin").dispatch();
+    assert_eq!(resp.status(), Status::Unauthorized);
+
+    // test user is not listed in admins
+    let resp = client.get("/admin")
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .dispatch();
+    assert_eq!(resp.status(), Status::Forbidden);
+}
+
 #[test]
 fn upload_file() {
     let client = create_test_server();
@@ -108,12 +309,20 @@ fn upload_file() {
     let files = resp.into_json::<Vec<FileInfo>>().unwrap();
     assert!(files.iter().find(|f| f.name == file_name).is_some());
     
+    //delete file without authorization
+    let resp = client.delete(format!("/api/event/{EVENT_ID}/file/{file_id}")).dispatch();
+    assert_eq!(resp.status(), Status::Unauthorized);
+
     //delete not existing file
-    let resp = client.delete(format!("/api/event/{EVENT_ID}/file/42")).dispatch();
+    let resp = client.delete(format!("/api/event/{EVENT_ID}/file/42"))
+        .header(Header::new("qx-api-token", DEMO_API_TOKEN))
+        .dispatch();
     assert_eq!(resp.status(), Status::NotFound);
 
     //delete existing file
-    let resp = client.delete(format!("/api/event/{EVENT_ID}/file/{file_id}")).dispatch();
+    let resp = client.delete(format!("/api/event/{EVENT_ID}/file/{file_id}"))
+        .header(Header::new("qx-api-token", DEMO_API_TOKEN))
+        .dispatch();
     assert_eq!(resp.status(), Status::Ok);
 
     // list files
@@ -123,6 +332,477 @@ fn upload_file() {
     assert!(files.iter().find(|f| f.name == file_name).is_none());
 }
 
+#[test]
+fn run_link_invalid_signature() {
+    let client = create_test_server();
+
+    let resp = client.get(format!("/event/{EVENT_ID}/run/1/report?sig=0123456789abcdef0123456789abcdef")).dispatch();
+    assert_eq!(resp.status(), Status::Forbidden);
+
+    // run links list is available to event editors only
+    let resp = client.get(format!("/event/{EVENT_ID}/run-links"))
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .dispatch();
+    assert_eq!(resp.status(), Status::Forbidden);
+}
+
+#[test]
+fn personal_token() {
+    let client = create_test_server();
+
+    let resp = post_form(&client, "/profile/tokens/create", "name=script&scopes=changes");
+    assert_eq!(resp.status(), Status::Ok);
+    let body = resp.into_string().unwrap();
+    let token = extract_code(&body);
+    let bearer = Header::new("Authorization", format!("Bearer {token}"));
+
+    let resp = client.get(format!("/event/{EVENT_ID}/my-changes"))
+        .header(bearer.clone())
+        .dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+
+    let resp = client.post(uri!(add_run_update_request_change(event_id = EVENT_ID, data_id = Some(1))))
+        .header(bearer.clone())
+        .json(&RunChange { note: Some("foo".to_string()), ..Default::default() })
+        .dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+
+    // token scope is not sufficient
+    let resp = client.post(format!("/event/{EVENT_ID}/delete"))
+        .header(bearer.clone())
+        .dispatch();
+    assert_eq!(resp.status(), Status::Forbidden);
+    // routes without declared scope require manage scope, even for reading
+    let resp = client.get(format!("/event/{EVENT_ID}/members"))
+        .header(bearer)
+        .dispatch();
+    assert_eq!(resp.status(), Status::Forbidden);
+
+    let resp = post_form(&client, "/profile/tokens/create", "name=reader&scopes=read");
+    assert_eq!(resp.status(), Status::Ok);
+    let body = resp.into_string().unwrap();
+    let token = extract_code(&body);
+    let bearer = Header::new("Authorization", format!("Bearer {token}"));
+    let resp = client.get(format!("/event/{EVENT_ID}/my-changes"))
+        .header(bearer.clone())
+        .dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    let resp = client.post(uri!(add_run_update_request_change(event_id = EVENT_ID, data_id = Some(1))))
+        .header(bearer)
+        .json(&RunChange { note: Some("foo".to_string()), ..Default::default() })
+        .dispatch();
+    assert_eq!(resp.status(), Status::Forbidden);
+
+    let resp = client.get(format!("/event/{EVENT_ID}/my-changes"))
+        .header(Header::new("Authorization", "Bearer invalid-token"))
+        .dispatch();
+    assert_eq!(resp.status(), Status::Unauthorized);
+}
+
+#[test]
+fn transfer_ownership_by_non_owner() {
+    let client = create_test_server();
+
+    // test user is not owner of demo event
+    let resp = post_form(&client, &format!("/event/{EVENT_ID}/members/transfer"), "email=john@doe.com");
+    assert_eq!(resp.status(), Status::Forbidden);
+}
+
+#[test]
+fn clone_event_by_non_member() {
+    let client = create_test_server();
+
+    let resp = post_json(&client, &format!("/api/event/{EVENT_ID}/clone"), &serde_json::json!({"copy_runs": true}));
+    assert_eq!(resp.status(), Status::Forbidden);
+}
+
+#[test]
+fn clone_event_with_runs() {
+    let client = create_test_server();
+    let resp = post_form(&client, "/event", "id=0&name=Cloned&place=Here&stage=1&stage_count=2&start_time=2099-06-01T10:00:00&time_zone=Europe/Prague");
+    assert_eq!(resp.status(), Status::Ok);
+    let event_id = EVENT_ID + 1;
+    let mut data = vec![];
+    OpenOptions::new().read(true).open(format!("tests/{START_LIST_IOFXML3_FILE}")).unwrap().read_to_end(&mut data).unwrap();
+    let resp = post_data(&client, &format!("/api/event/{event_id}/upload/startlist"), data);
+    assert_eq!(resp.status(), Status::Ok);
+
+    let post_clone = |options: serde_json::Value| post_json(&client, &format!("/api/event/{event_id}/clone"), &options);
+    // failed clone does not leave half cloned event
+    let resp = post_clone(serde_json::json!({"copy_runs": true, "files": ["missing.txt"]}));
+    assert_eq!(resp.status(), Status::InternalServerError);
+    let resp = client.get(format!("/api/event/{}", event_id + 1)).dispatch();
+    assert_eq!(resp.status(), Status::NotFound);
+    // it is not moved to trash either
+    assert!(!client.db_dir.path().join("ev0003.sqlite").exists());
+    assert!(!client.db_dir.path().join("trash").exists());
+    let resp = client.get("/trash")
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .dispatch();
+    assert!(!resp.into_string().unwrap().contains("Cloned"));
+
+    let resp = post_clone(serde_json::json!({"copy_runs": true}));
+    assert_eq!(resp.status(), Status::Ok);
+    let cloned = resp.into_json::<serde_json::Value>().unwrap();
+    let cloned_id: EventId = cloned["event"]["id"].as_i64().unwrap();
+    assert_eq!(cloned["event"]["stage"], 2);
+
+    let get_json = |uri: String| client.get(uri)
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .dispatch()
+        .into_json::<serde_json::Value>()
+        .unwrap();
+    let class_names = |event_id: EventId| get_json(format!("/api/event/{event_id}/classes")).as_array().unwrap().iter()
+        .map(|class| class["name"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert!(!class_names(event_id).is_empty());
+    assert_eq!(class_names(cloned_id), class_names(event_id));
+    let runs = |event_id: EventId| serde_json::from_value::<Vec<RunsRecord>>(get_json(format!("/api/event/{event_id}/startlist"))).unwrap();
+    let (src_runs, cloned_runs) = (runs(event_id), runs(cloned_id));
+    assert_eq!(cloned_runs.len(), src_runs.len());
+    assert!(src_runs.iter().any(|run| run.start_time.is_some()));
+    assert!(cloned_runs.iter().all(|run| run.start_time.is_none() && run.check_time.is_none() && run.finish_time.is_none()));
+}
+
+#[test]
+fn delete_and_restore_event() {
+    let client = create_test_server();
+    let resp = post_form(&client, "/event", "id=0&name=Trashed&place=Here&stage=1&stage_count=1&start_time=2025-06-01T10:00:00%2B02:00&time_zone=Europe/Prague&files_public=true&runs_public=true&changes_public=true");
+    assert_eq!(resp.status(), Status::Ok);
+    let event_id = EVENT_ID + 1;
+    let body = resp.into_string().unwrap();
+    let api_token = extract_code(&body);
+    let upload_file = |name: &str| client.post(format!("/api/event/current/file?name={name}"))
+        .header(Header::new("qx-api-token", api_token.clone()))
+        .header(ContentType::Plain)
+        .body("data")
+        .dispatch()
+        .status();
+    assert_eq!(upload_file("a.txt"), Status::Ok);
+    let db_file = client.db_dir.path().join("ev0002.sqlite");
+    assert!(db_file.exists());
+
+    let resp = post_form(&client, &format!("/event/{event_id}/delete"), "");
+    assert_eq!(resp.status(), Status::SeeOther);
+    assert!(!db_file.exists());
+    let trash_files = std::fs::read_dir(client.db_dir.path().join("trash")).unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(trash_files.len(), 1);
+    assert!(trash_files[0].starts_with("ev0002-"));
+    // API token of deleted event is revoked
+    assert_eq!(upload_file("b.txt"), Status::Unauthorized);
+
+    let resp = client.get("/trash")
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    assert!(resp.into_string().unwrap().contains("Trashed"));
+
+    let resp = post_form(&client, "/trash/restore", "id=1");
+    assert_eq!(resp.status(), Status::SeeOther);
+    let resp = client.get(format!("/event/{event_id}")).dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    assert!(db_file.exists());
+    let resp = client.get(format!("/api/event/{event_id}/file")).dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    assert!(resp.into_json::<Vec<FileInfo>>().unwrap().iter().any(|file| file.name == "a.txt"));
+    // API token works again
+    assert_eq!(upload_file("b.txt"), Status::Ok);
+
+    // event can be restored only once
+    let resp = post_form(&client, "/trash/restore", "id=1");
+    assert_eq!(resp.status(), Status::NotFound);
+}
+
+#[test]
+fn restore_event_keeps_run_link_secret_and_competition() {
+    let client = create_test_server();
+    let resp = post_form(&client, "/event", "id=0&name=Staged&place=Here&stage=1&stage_count=2&start_time=2025-06-01T10:00:00&time_zone=Europe/Prague");
+    assert_eq!(resp.status(), Status::Ok);
+    let event_id = EVENT_ID + 1;
+    let mut data = vec![];
+    OpenOptions::new().read(true).open(format!("tests/{START_LIST_IOFXML3_FILE}")).unwrap().read_to_end(&mut data).unwrap();
+    let resp = post_data(&client, &format!("/api/event/{event_id}/upload/startlist"), data);
+    assert_eq!(resp.status(), Status::Ok);
+    let resp = post_form(&client, "/competition", &format!("event_id={event_id}&name=Cup&aggregation=TimeSum"));
+    assert_eq!(resp.status(), Status::SeeOther);
+    // signatures of run links are generated from the event secret
+    let run_links = || client.get(format!("/event/{event_id}/run-links"))
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .dispatch()
+        .into_string()
+        .unwrap();
+    // '=' is escaped in template
+    let links = run_links();
+    let sig = links.split("sig&#x3D;").nth(1).and_then(|s| s.split('"').next()).unwrap().to_string();
+
+    let resp = post_form(&client, &format!("/event/{event_id}/delete"), "");
+    assert_eq!(resp.status(), Status::SeeOther);
+    let resp = post_form(&client, "/trash/restore", "id=1");
+    assert_eq!(resp.status(), Status::SeeOther);
+
+    assert!(run_links().contains(&format!("sig&#x3D;{sig}\"")));
+    let resp = client.get("/competition/1")
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    assert!(resp.into_string().unwrap().contains(&format!("href=\"/event/{event_id}\"")));
+}
+
+#[test]
+fn restore_event_without_db_file() {
+    let client = create_test_server();
+    let resp = post_form(&client, "/event", "id=0&name=Trashed&place=Here&stage=1&stage_count=1&start_time=2025-06-01T10:00:00%2B02:00&time_zone=Europe/Prague");
+    assert_eq!(resp.status(), Status::Ok);
+    let event_id = EVENT_ID + 1;
+    // event DB is created on first access
+    let resp = client.get(format!("/event/{event_id}")).dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    let resp = post_form(&client, &format!("/event/{event_id}/delete"), "");
+    assert_eq!(resp.status(), Status::SeeOther);
+    std::fs::remove_dir_all(client.db_dir.path().join("trash")).unwrap();
+
+    let resp = post_form(&client, "/trash/restore", "id=1");
+    assert_eq!(resp.status(), Status::Gone);
+    // event stays in trash
+    let resp = client.get(format!("/api/event/{event_id}")).dispatch();
+    assert_eq!(resp.status(), Status::NotFound);
+    let resp = client.get("/trash")
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .dispatch();
+    assert!(resp.into_string().unwrap().contains("Trashed"));
+}
+
+#[test]
+fn orphan_event_db_is_moved_to_trash() {
+    let db_dir = tempfile::tempdir().unwrap();
+    std::fs::write(db_dir.path().join("ev0042.sqlite"), b"").unwrap();
+    let client = create_test_server_in(db_dir, rocket::Config::figment());
+    assert!(!client.db_dir.path().join("ev0042.sqlite").exists());
+    let trash_files = std::fs::read_dir(client.db_dir.path().join("trash")).unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(trash_files.len(), 1);
+    assert!(trash_files[0].starts_with("ev0042-"));
+}
+
+#[test]
+fn search_events() {
+    let client = create_test_server();
+    let resp = post_form(&client, "/event", "id=0&name=Past%20cup&place=Hill&stage=1&stage_count=1&start_time=2020-06-01T10:00:00&time_zone=Europe/Prague");
+    assert_eq!(resp.status(), Status::Ok);
+    let list_events = |query: &str| {
+        let resp = client.get(format!("/api/events?{query}"))
+            .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+            .dispatch();
+        assert_eq!(resp.status(), Status::Ok);
+        resp.into_json::<EventListPage>().unwrap()
+    };
+
+    let page = list_events("");
+    assert_eq!(page.total, 2);
+    let page = list_events("q=forest");
+    assert_eq!(page.events.iter().map(|e| e.id).collect::<Vec<_>>(), vec![EVENT_ID]);
+    let page = list_events("q=CUP&period=past");
+    assert_eq!(page.events.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(), vec!["Past cup"]);
+    let page = list_events("period=past&from=2020-06-01&to=2020-06-01");
+    assert_eq!(page.total, 1);
+    let page = list_events("mine=true");
+    assert_eq!(page.events.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(), vec!["Past cup"]);
+    let page = list_events("per_page=1&page=2");
+    assert_eq!((page.events.len(), page.page, page.page_count), (1, 2, 2));
+
+    let resp = client.get("/api/events?mine=true").dispatch();
+    assert_eq!(resp.status(), Status::Unauthorized);
+    let resp = client.get("/api/events?from=yesterday").dispatch();
+    assert_eq!(resp.status(), Status::BadRequest);
+    let resp = client.get("/?q=forest").dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    assert!(resp.into_string().unwrap().contains("Demo event"));
+}
+
+#[test]
+fn online_entries() {
+    let client = create_test_server();
+    // organizer cannot be a member of demo event, so new event is created
+    let resp = post_form(&client, "/event", "id=0&name=Entries&place=Here&stage=1&stage_count=1&start_time=2099-06-01T10:00:00&time_zone=Europe/Prague");
+    assert_eq!(resp.status(), Status::Ok);
+    let body = resp.into_string().unwrap();
+    let api_token = extract_code(&body);
+    let event_id = EVENT_ID + 1;
+    let resp = client.post("/api/event/current")
+        .header(Header::new("qx-api-token", api_token))
+        .json(&EventInfo {
+            name: "Entries".to_string(),
+            stage: 1,
+            stage_count: 1,
+            place: "Here".to_string(),
+            start_time: QxDateTime::parse_from_iso("2099-06-01T10:00:00+02:00").unwrap().0,
+            classes: vec![
+                serde_json::json!(["name", "length", "climb", "control_count", "start_time", "interval", "start_slot_count"]).as_array().unwrap().clone(),
+                serde_json::json!(["H21", 5000, 100, 15, 0, 0, 0]).as_array().unwrap().clone(),
+            ],
+        })
+        .dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+
+    let entry = "class_name=H21&first_name=John&last_name=Doe&registration=ABC1234&si_id=123456&club=Forest%20runners";
+    // entries are not open yet
+    assert_eq!(post_form(&client, &format!("/event/{event_id}/entries"), entry).status(), Status::BadRequest);
+    let resp = post_form(&client, &format!("/event/{event_id}/entries/classes"), "class_name=H21&deadline=2099-05-25T20:00&max_entries=1");
+    assert_eq!(resp.status(), Status::SeeOther);
+    assert_eq!(post_form(&client, &format!("/event/{event_id}/entries"), entry).status(), Status::SeeOther);
+    // class is full
+    let resp = post_form(&client, &format!("/event/{event_id}/entries"), "class_name=H21&first_name=Jane&last_name=Doe&registration=&club=");
+    assert_eq!(resp.status(), Status::Conflict);
+
+    let resp = client.get(format!("/api/event/{event_id}/startlist"))
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .dispatch();
+    let runs = resp.into_json::<Vec<RunsRecord>>().unwrap();
+    assert_eq!(runs.len(), 1);
+    assert!(runs[0].run_id < 0);
+
+    let resp = client.get(format!("/api/event/{event_id}/entries/iofxml3"))
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    let xml = resp.into_string().unwrap();
+    assert!(xml.contains("<PersonEntry>"));
+    assert!(xml.contains(r#"<Id type="CZE">ABC1234</Id>"#));
+    assert!(xml.contains("<Organisation><Name>Forest runners</Name></Organisation>"));
+
+    let resp = post_form(&client, &format!("/event/{event_id}/entries/1/cancel"), "");
+    assert_eq!(resp.status(), Status::SeeOther);
+    let resp = client.get(format!("/api/event/{event_id}/audit"))
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .dispatch();
+    let records = resp.into_json::<Vec<AuditRecord>>().unwrap();
+    assert!(records.iter().any(|rec| rec.action == ENTRY_CREATE && rec.target == "entry:1" && rec.new_value.as_ref().is_some_and(|v| v.contains("ABC1234"))));
+    assert!(records.iter().any(|rec| rec.action == ENTRY_CANCEL && rec.target == "entry:1" && rec.actor == "john@doe"));
+
+    // john is not member of demo event
+    let resp = post_form(&client, &format!("/event/{EVENT_ID}/entries/classes"), "class_name=H21&deadline=2099-05-25T20:00");
+    assert_eq!(resp.status(), Status::Forbidden);
+}
+
+#[test]
+fn events_ical_feed() {
+    let client = create_test_server();
+    let get_ics = |uri: &str| {
+        let resp = client.get(uri.to_string()).dispatch();
+        assert_eq!(resp.status(), Status::Ok);
+        resp.into_string().unwrap()
+    };
+    let sequence = |ics: &str| ics.split("SEQUENCE:").nth(1)
+        .and_then(|s| s.split("\r\n").next())
+        .and_then(|s| s.parse::<i64>().ok())
+        .unwrap();
+    let ics = get_ics("/events.ics");
+    assert!(ics.contains(&format!("UID:event-{EVENT_ID}@")));
+    let old_sequence = sequence(&ics);
+    assert!(ics.contains("LOCATION:Deep forest 42\r\n"));
+
+    let resp = client.post("/api/event/current")
+        .header(Header::new("qx-api-token", DEMO_API_TOKEN))
+        .json(&EventInfo {
+            name: "Renamed".to_string(),
+            stage: 1,
+            stage_count: 1,
+            place: "Deep forest 42".to_string(),
+            start_time: QxDateTime::now().0,
+            classes: vec![],
+        })
+        .dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    let ics = get_ics("/events.ics?owner=fanda.vacek@gmail.com");
+    assert!(ics.contains("SUMMARY:Renamed\r\n"));
+    assert!(sequence(&ics) > old_sequence);
+
+    let ics = get_ics("/events.ics?owner=nobody@example.com");
+    assert!(!ics.contains("BEGIN:VEVENT"));
+}
+
+#[test]
+fn event_metadata_and_bulletins() {
+    let client = create_test_server();
+    let event_form = "id=0&name=Meta&place=Here&stage=1&stage_count=1&start_time=2099-06-01T10:00:00&time_zone=Europe/Prague\
+        &organizer=Forest%20runners&description=**Bring**%20a%20compass%3Cscript%3Ealert(1)%3C%2Fscript%3E\
+        &latitude=50.0875&longitude=14.4214&contacts=Jane%20%7C%20jane%40example.com%20%7C%20%2B420123456789";
+    let resp = post_form(&client, "/event", &format!("{event_form}&links=Club%20%7C%20javascript:alert(1)"));
+    assert_eq!(resp.status(), Status::BadRequest);
+    let resp = post_form(&client, "/event", &format!("{event_form}&links=Club%20%7C%20https://example.com"));
+    assert_eq!(resp.status(), Status::Ok);
+    let event_id = EVENT_ID + 1;
+
+    for (name, body) in [("b.pdf", "%PDF-1.4"), ("x y.html", "<script>alert(1)</script>")] {
+        let resp = post_data(&client, &format!("/api/event/{event_id}/bulletin?name={}", name.replace(' ', "%20")), body);
+        assert_eq!(resp.status(), Status::Ok);
+    }
+    // bulletin is public without login
+    let resp = client.get(format!("/event/{event_id}/bulletin/b.pdf")).dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(resp.content_type(), Some(ContentType::PDF));
+    assert_eq!(resp.headers().get_one("X-Content-Type-Options"), Some("nosniff"));
+    // bulletin which could run script is downloaded only
+    let resp = client.get(format!("/event/{event_id}/bulletin/x%20y.html")).dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(resp.content_type(), Some(ContentType::Binary));
+    assert_eq!(resp.headers().get_one("Content-Disposition"), Some("attachment; filename*=UTF-8''x%20y.html"));
+    let resp = client.get(format!("/event/{event_id}/bulletin/missing.pdf")).dispatch();
+    assert_eq!(resp.status(), Status::NotFound);
+    let resp = client.get(format!("/event/{}/bulletin/b.pdf", event_id + 1)).dispatch();
+    assert_eq!(resp.status(), Status::NotFound);
+
+    let resp = client.get(format!("/api/event/{event_id}")).dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    let event = resp.into_json::<serde_json::Value>().unwrap();
+    assert_eq!(event["organizer"], "Forest runners");
+    assert_eq!(event["latitude"], 50.0875);
+    assert_eq!(event["links"][0]["url"], "https://example.com");
+    assert_eq!(event["contacts"][0]["email"], "jane@example.com");
+    assert_eq!(event["bulletins"][0]["name"], "b.pdf");
+    assert_eq!(event["bulletins"][0]["url"], format!("/event/{event_id}/bulletin/b.pdf"));
+
+    let resp = client.get(format!("/event/{event_id}")).dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    let html = resp.into_string().unwrap();
+    assert!(html.contains(&format!("href=\"/event/{event_id}/bulletin/x%20y.html\"")));
+    assert!(html.contains("<strong>Bring</strong>"));
+    assert!(!html.contains("<script>alert(1)"));
+}
+
+/// Request as logged-in test user with CSRF token
+fn with_session(request: LocalRequest<'_>) -> LocalRequest<'_> {
+    request
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .cookie(Cookie::build((CSRF_COOKIE, TEST_CSRF_TOKEN)))
+        .header(Header::new(CSRF_HEADER, TEST_CSRF_TOKEN))
+}
+/// Post form as logged-in test user with CSRF token
+fn post_form<'c>(client: &'c Client, uri: &str, body: &str) -> LocalResponse<'c> {
+    with_session(client.post(uri.to_string()))
+        .header(ContentType::Form)
+        .body(body)
+        .dispatch()
+}
+/// Post JSON as logged-in test user with CSRF token
+fn post_json<'c, T: serde::Serialize>(client: &'c Client, uri: &str, value: &T) -> LocalResponse<'c> {
+    with_session(client.post(uri.to_string()))
+        .json(value)
+        .dispatch()
+}
+/// Post raw data as logged-in test user with CSRF token
+fn post_data<'c>(client: &'c Client, uri: &str, data: impl AsRef<[u8]>) -> LocalResponse<'c> {
+    with_session(client.post(uri.to_string()))
+        .body(data)
+        .dispatch()
+}
+/// Text of the first `<code>` element, newly created tokens are shown in it
+fn extract_code(body: &str) -> String {
+    body.split("<code>").nth(1).and_then(|s| s.split("</code>").next()).unwrap().to_string()
+}
 fn upload_test_file(client: &Client, file_name: &str) {
     let mut file = OpenOptions::new().read(true).open(format!("tests/{file_name}")).unwrap();
     let mut data = vec![];
@@ -137,7 +817,7 @@ fn upload_test_file(client: &Client, file_name: &str) {
     assert_eq!(resp.status(), Status::Ok);
 }
 fn upload_start_list(client: &Client) {
-    upload_test_file(&client, START_LIST_IOFXML3_FILE);
+    upload_test_file(client, START_LIST_IOFXML3_FILE);
 }
 #[test]
 fn test_upload_start_list() {
@@ -147,6 +827,34 @@ fn test_upload_start_list() {
     let resp = client.get(format!("/event/{EVENT_ID}/startlist")).dispatch();
     assert_eq!(resp.status(), Status::Ok);
 }
+#[test]
+fn public_event_api() {
+    let client = create_test_server();
+    upload_start_list(&client);
+
+    let resp = client.get(format!("/api/event/{EVENT_ID}")).dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(resp.into_json::<EventRecord>().unwrap().id, EVENT_ID);
+
+    let resp = client.get(format!("/api/event/{}", EVENT_ID + 100)).dispatch();
+    assert_eq!(resp.status(), Status::NotFound);
+
+    let resp = client.get(format!("/api/event/{EVENT_ID}/classes")).dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    let classes = resp.into_json::<Vec<ClassesRecord>>().unwrap();
+    let class_name = &classes.first().unwrap().name;
+
+    let resp = client.get(format!("/api/event/{EVENT_ID}/startlist?class_name={class_name}")).dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    let runs = resp.into_json::<Vec<RunsRecord>>().unwrap();
+    assert!(!runs.is_empty());
+    assert!(runs.iter().all(|run| run.class_name.as_ref() == Some(class_name)));
+
+    let resp = client.get(format!("/api/event/{EVENT_ID}/results")).dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    let results = resp.into_json::<Vec<ResultRecord>>().unwrap();
+    assert!(results.len() >= runs.len());
+}
 
 #[test]
 fn add_start_list_change_request() {
@@ -165,12 +873,16 @@ fn add_start_list_change_request() {
         note: Some("foo".to_string()),
     };
 
-    // create run change request
+    // create run change request without CSRF token
     let resp = client.post(uri!(add_run_update_request_change(event_id = EVENT_ID, data_id = Some(RUN_ID))))
         .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
         .header(ContentType::JSON)
         .json(&run_change)
         .dispatch();
+    assert_eq!(resp.status(), Status::Forbidden);
+
+    // create run change request
+    let resp = post_json(&client, &uri!(add_run_update_request_change(event_id = EVENT_ID, data_id = Some(RUN_ID))).to_string(), &run_change);
     assert_eq!(resp.status(), Status::Ok);
     let change_id = resp.into_json::<i64>().unwrap();
 
@@ -195,12 +907,11 @@ fn add_start_list_change_request() {
     }
 
     // delete it
-    let resp = client.delete(uri!(
+    let resp = with_session(client.delete(uri!(
         api_changes_delete(
             event_id = EVENT_ID,
             change_id = change_id,
-        )))
-        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        ))))
         .dispatch();
     assert_eq!(resp.status(), Status::Ok);
 
@@ -220,6 +931,31 @@ fn add_start_list_change_request() {
     assert!(changes.is_empty());
 }
 
+#[test]
+fn change_request_is_audited() {
+    let client = create_test_server();
+    let resp = post_form(&client, "/event", "id=0&name=Audited&place=Here&stage=1&stage_count=1&start_time=2099-06-01T10:00:00&time_zone=Europe/Prague");
+    assert_eq!(resp.status(), Status::Ok);
+    let event_id = EVENT_ID + 1;
+
+    let resp = post_json(&client, &uri!(add_run_update_request_change(event_id = event_id, data_id = Some(1))).to_string(),
+                         &RunChange { note: Some("foo".to_string()), ..Default::default() });
+    assert_eq!(resp.status(), Status::Ok);
+    let change_id = resp.into_json::<i64>().unwrap();
+    let resp = with_session(client.delete(uri!(api_changes_delete(event_id = event_id, change_id = change_id))))
+        .dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+
+    let resp = client.get(format!("/api/event/{event_id}/audit"))
+        .cookie(Cookie::build((QX_SESSION_ID, TEST_SESSION_ID)))
+        .dispatch();
+    assert_eq!(resp.status(), Status::Ok);
+    let records = resp.into_json::<Vec<AuditRecord>>().unwrap();
+    let target = format!("change:{change_id}");
+    assert!(records.iter().any(|r| r.action == CHANGE_CREATE && r.target == target && r.actor == "john@doe"));
+    assert!(records.iter().any(|r| r.action == CHANGE_DELETE && r.target == target && r.old_value.is_some()));
+}
+
 #[test]
 fn post_qe3_change() {
     let client = create_test_server();
@@ -0,0 +1,357 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use chrono::TimeDelta;
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::response::status::Custom;
+use rocket::serde::Serialize;
+use rocket::{Build, Rocket, State};
+use rocket_dyn_templates::{context, Template};
+use sqlx::{FromRow, Sqlite, SqliteConnection, SqlitePool, Transaction};
+use crate::audit::{audit, summary, Actor, EVENT_RESTORE};
+use crate::db::{event_id_to_schema_name, DbPool};
+use crate::event::{user_info, EventId, EventRecord};
+use crate::qxdatetime::QxDateTime;
+use crate::util::{anyhow_to_custom_error, sqlx_to_anyhow, sqlx_to_custom_error};
+use crate::{QxSessionId, SharedQxState};
+
+const TRASH_DIR: &str = "trash";
+pub(crate) const DEFAULT_RETENTION_DAYS: i64 = 30;
+
+#[derive(Serialize, FromRow, Debug)]
+struct DeletedEventRecord {
+    id: i64,
+    event_id: EventId,
+    // JSON of the deleted events row
+    event: String,
+    db_file: Option<String>,
+    deleted: QxDateTime,
+    deleted_by: String,
+    // JSON array of API token IDs revoked when the event was deleted
+    api_token_ids: String,
+    // events columns not present in EventRecord
+    run_link_secret: Option<String>,
+    competition_id: Option<i64>,
+    ical_sequence: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct DeletedEventView {
+    id: i64,
+    event: EventRecord,
+    deleted: QxDateTime,
+    deleted_by: String,
+    expires: QxDateTime,
+}
+
+fn event_db_path(db_path: &str, event_id: EventId) -> PathBuf {
+    Path::new(db_path).join(format!("{}.sqlite", event_id_to_schema_name(event_id)))
+}
+fn trash_dir(db_path: &str) -> PathBuf {
+    Path::new(db_path).join(TRASH_DIR)
+}
+
+/// Move DB file to trash dir under unique name, mtime is reset so that retention starts now
+fn move_to_trash(file: &Path, db_path: &str) -> anyhow::Result<String> {
+    let dir = trash_dir(db_path);
+    std::fs::create_dir_all(&dir)?;
+    let stem = file.file_stem().and_then(|s| s.to_str()).unwrap_or("event");
+    let name = format!("{stem}-{}.sqlite", QxDateTime::now().0.format("%Y%m%dT%H%M%S%.3f"));
+    let trashed = dir.join(&name);
+    std::fs::rename(file, &trashed)?;
+    std::fs::File::options().write(true).open(&trashed)?.set_modified(SystemTime::now())?;
+    Ok(name)
+}
+
+/// Close event DB pool, it must not be used anymore after the DB file is moved
+async fn evict_event_db(event_id: EventId, state: &State<SharedQxState>) {
+    let open_event = state.write().await.open_events.remove(&event_id);
+    if let Some(open_event) = open_event {
+        open_event.db.close().await;
+    }
+}
+
+/// Remove DB of event which was not created completely, it is not moved to trash
+pub(crate) async fn remove_event_db(event_id: EventId, state: &State<SharedQxState>) -> anyhow::Result<()> {
+    evict_event_db(event_id, state).await;
+    let file = event_db_path(&state.read().await.app_config.db_path, event_id);
+    if file.exists() {
+        std::fs::remove_file(&file)?;
+    }
+    Ok(())
+}
+
+/// Record deleted event, columns not present in EventRecord are copied from its row, so it must be called before the row is deleted
+pub(crate) async fn insert_deleted_event(event: &EventRecord, api_token_ids: &[i64], actor: &Actor, conn: &mut SqliteConnection) -> anyhow::Result<i64> {
+    let id: (i64,) = sqlx::query_as("INSERT INTO deleted_events (event_id, event, deleted, deleted_by, api_token_ids, run_link_secret, competition_id, ical_sequence)
+                                    SELECT id, ?, ?, ?, ?, run_link_secret, competition_id, ical_sequence FROM events WHERE id=?
+                                    RETURNING id")
+        .bind(serde_json::to_string(event)?)
+        .bind(QxDateTime::now().trimmed_to_sec())
+        .bind(actor.to_string())
+        .bind(serde_json::to_string(api_token_ids)?)
+        .bind(event.id)
+        .fetch_one(conn)
+        .await.map_err(sqlx_to_anyhow)?;
+    Ok(id.0)
+}
+
+/// Move DB of deleted event to trash and commit the deletion,
+/// deletion is rolled back if the DB file cannot be moved and the file is moved back if the commit fails
+pub(crate) async fn trash_event_db(event_id: EventId, deleted_event_id: i64, mut tx: Transaction<'_, Sqlite>, state: &State<SharedQxState>, gdb: &State<DbPool>) -> anyhow::Result<()> {
+    evict_event_db(event_id, state).await;
+    let (db_path, retention_days) = {
+        let state = state.read().await;
+        (state.app_config.db_path.clone(), state.app_config.trash_retention_days)
+    };
+    let file = event_db_path(&db_path, event_id);
+    let db_file = if file.exists() {
+        Some(move_to_trash(&file, &db_path)?)
+    } else {
+        None
+    };
+    let res = match sqlx::query("UPDATE deleted_events SET db_file=? WHERE id=?")
+        .bind(&db_file)
+        .bind(deleted_event_id)
+        .execute(&mut *tx)
+        .await {
+        Ok(_) => tx.commit().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        if let Some(db_file) = &db_file
+            && let Err(e) = std::fs::rename(trash_dir(&db_path).join(db_file), &file) {
+            error!("Move DB file {db_file} of event id: {event_id} back from trash error: {e}");
+        }
+        return Err(sqlx_to_anyhow(e));
+    }
+    info!("Event id: {event_id} DB moved to trash as: {db_file:?}");
+    purge_trash(&db_path, retention_days, &gdb.0).await
+}
+
+fn expires(deleted: &QxDateTime, retention_days: i64) -> QxDateTime {
+    deleted.0.checked_add_signed(TimeDelta::days(retention_days)).map(QxDateTime).unwrap_or(*deleted)
+}
+
+/// Delete trash files, deleted event records and their revoked API tokens older than retention
+pub(crate) async fn purge_trash(db_path: &str, retention_days: i64, gdb: &SqlitePool) -> anyhow::Result<()> {
+    let now = QxDateTime::now();
+    let deleted_events: Vec<DeletedEventRecord> = sqlx::query_as("SELECT * FROM deleted_events")
+        .fetch_all(gdb)
+        .await.map_err(sqlx_to_anyhow)?;
+    for rec in deleted_events.iter().filter(|rec| expires(&rec.deleted, retention_days).0 <= now.0) {
+        let mut tx = gdb.begin().await.map_err(sqlx_to_anyhow)?;
+        for api_token_id in serde_json::from_str::<Vec<i64>>(&rec.api_token_ids)? {
+            sqlx::query("DELETE FROM api_tokens WHERE id=? AND revoked=1")
+                .bind(api_token_id)
+                .execute(&mut *tx)
+                .await.map_err(sqlx_to_anyhow)?;
+        }
+        sqlx::query("DELETE FROM deleted_events WHERE id=?")
+            .bind(rec.id)
+            .execute(&mut *tx)
+            .await.map_err(sqlx_to_anyhow)?;
+        tx.commit().await.map_err(sqlx_to_anyhow)?;
+    }
+    let dir = trash_dir(db_path);
+    if !dir.exists() {
+        return Ok(());
+    }
+    let retention = Duration::from_secs(retention_days.max(0) as u64 * 24 * 60 * 60);
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?;
+        let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if age >= retention {
+            info!("Purging trash file: {:?}", entry.path());
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// DB files left by events deleted before the trash existed would be opened again if their event ID was reused,
+/// they are moved to trash at startup
+pub(crate) async fn trash_orphan_event_dbs(db_path: &str, gdb: &SqlitePool) -> anyhow::Result<()> {
+    if !Path::new(db_path).exists() {
+        return Ok(());
+    }
+    let event_ids: Vec<(EventId,)> = sqlx::query_as("SELECT id FROM events")
+        .fetch_all(gdb)
+        .await.map_err(sqlx_to_anyhow)?;
+    for entry in std::fs::read_dir(db_path)? {
+        let path = entry?.path();
+        let Some(event_id) = path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("ev"))
+            .and_then(|name| name.strip_suffix(".sqlite"))
+            .and_then(|id| id.parse::<EventId>().ok()) else {
+            continue;
+        };
+        if !event_ids.iter().any(|id| id.0 == event_id) {
+            let name = move_to_trash(&path, db_path)?;
+            warn!("DB file of not existing event id: {event_id} moved to trash as: {name}");
+        }
+    }
+    Ok(())
+}
+
+/// Put event back to trash, when its DB file cannot be restored
+async fn undo_restore(rec: &DeletedEventRecord, api_token_ids: &[i64], gdb: &SqlitePool) -> anyhow::Result<()> {
+    let mut tx = gdb.begin().await.map_err(sqlx_to_anyhow)?;
+    sqlx::query("DELETE FROM events WHERE id=?")
+        .bind(rec.event_id)
+        .execute(&mut *tx)
+        .await.map_err(sqlx_to_anyhow)?;
+    for api_token_id in api_token_ids {
+        sqlx::query("UPDATE api_tokens SET revoked=1 WHERE id=?")
+            .bind(api_token_id)
+            .execute(&mut *tx)
+            .await.map_err(sqlx_to_anyhow)?;
+    }
+    sqlx::query("INSERT INTO deleted_events (id, event_id, event, db_file, deleted, deleted_by, api_token_ids, run_link_secret, competition_id, ical_sequence)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+        .bind(rec.id)
+        .bind(rec.event_id)
+        .bind(&rec.event)
+        .bind(&rec.db_file)
+        .bind(rec.deleted)
+        .bind(&rec.deleted_by)
+        .bind(&rec.api_token_ids)
+        .bind(&rec.run_link_secret)
+        .bind(rec.competition_id)
+        .bind(rec.ical_sequence)
+        .execute(&mut *tx)
+        .await.map_err(sqlx_to_anyhow)?;
+    tx.commit().await.map_err(sqlx_to_anyhow)?;
+    Ok(())
+}
+
+fn can_restore(rec: &DeletedEventRecord, event: &EventRecord, user_email: &str, is_admin: bool) -> bool {
+    is_admin || event.owner == user_email || rec.deleted_by == user_email
+}
+
+#[get("/trash")]
+async fn get_trash(session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Template, Custom<String>> {
+    let user = user_info(&session_id, state).await?;
+    let (is_admin, retention_days) = {
+        let state = state.read().await;
+        (state.app_config.is_admin(&user.email), state.app_config.trash_retention_days)
+    };
+    let deleted_events: Vec<DeletedEventRecord> = sqlx::query_as("SELECT * FROM deleted_events ORDER BY deleted DESC")
+        .fetch_all(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?;
+    let now = QxDateTime::now();
+    let deleted_events = deleted_events.into_iter()
+        .filter_map(|rec| {
+            let event: EventRecord = serde_json::from_str(&rec.event).ok()?;
+            let expires = expires(&rec.deleted, retention_days);
+            (expires.0 > now.0 && can_restore(&rec, &event, &user.email, is_admin)).then_some(DeletedEventView {
+                id: rec.id,
+                event,
+                deleted: rec.deleted,
+                deleted_by: rec.deleted_by,
+                expires,
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(Template::render("trash", context! {
+        user,
+        deleted_events,
+        retention_days,
+    }))
+}
+
+#[derive(Debug, FromForm)]
+struct RestoreFormValues {
+    id: i64,
+}
+#[post("/trash/restore", data = "<form>")]
+async fn restore_event(form: Form<RestoreFormValues>, session_id: QxSessionId, state: &State<SharedQxState>, gdb: &State<DbPool>) -> Result<Redirect, Custom<String>> {
+    let user = user_info(&session_id, state).await?;
+    let (is_admin, retention_days, db_path) = {
+        let state = state.read().await;
+        (state.app_config.is_admin(&user.email), state.app_config.trash_retention_days, state.app_config.db_path.clone())
+    };
+    let rec: DeletedEventRecord = sqlx::query_as("SELECT * FROM deleted_events WHERE id=?")
+        .bind(form.id)
+        .fetch_optional(&gdb.0)
+        .await.map_err(sqlx_to_custom_error)?
+        .ok_or(Custom(Status::NotFound, format!("Deleted event id: {} not found", form.id)))?;
+    let event: EventRecord = serde_json::from_str(&rec.event).map_err(|e| anyhow_to_custom_error(e.into()))?;
+    let api_token_ids: Vec<i64> = serde_json::from_str(&rec.api_token_ids).map_err(|e| anyhow_to_custom_error(e.into()))?;
+    if !can_restore(&rec, &event, &user.email, is_admin) {
+        return Err(Custom(Status::Forbidden, "Only event owner can restore the event".to_string()));
+    }
+    if expires(&rec.deleted, retention_days).0 <= QxDateTime::now().0 {
+        return Err(Custom(Status::Gone, "Retention period of deleted event is over".to_string()));
+    }
+    let file = event_db_path(&db_path, event.id);
+    if file.exists() {
+        return Err(Custom(Status::Conflict, format!("DB file of event id: {} already exists", event.id)));
+    }
+    let mut tx = gdb.0.begin().await.map_err(sqlx_to_custom_error)?;
+    let res = sqlx::query("DELETE FROM deleted_events WHERE id=?")
+        .bind(rec.id)
+        .execute(&mut *tx)
+        .await.map_err(sqlx_to_custom_error)?;
+    if res.rows_affected() == 0 {
+        return Err(Custom(Status::Gone, "Event already restored".to_string()));
+    }
+    // event keeps its ID, IDs are never reused for new events, competition might be deleted meanwhile
+    sqlx::query("INSERT INTO events (id, name, place, stage, stage_count, start_time, time_zone, owner, files_public, runs_public, changes_public,
+                                     organizer, description, links, latitude, longitude, contacts,
+                                     run_link_secret, competition_id, ical_sequence)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, (SELECT id FROM competitions WHERE id=?), ?)")
+        .bind(event.id)
+        .bind(&event.name)
+        .bind(&event.place)
+        .bind(event.stage)
+        .bind(event.stage_count)
+        .bind(event.start_time.0)
+        .bind(&event.time_zone)
+        .bind(&event.owner)
+        .bind(event.files_public)
+        .bind(event.runs_public)
+        .bind(event.changes_public)
+        .bind(&event.organizer)
+        .bind(&event.description)
+        .bind(&event.links)
+        .bind(event.latitude)
+        .bind(event.longitude)
+        .bind(&event.contacts)
+        .bind(&rec.run_link_secret)
+        .bind(rec.competition_id)
+        .bind(rec.ical_sequence)
+        .execute(&mut *tx)
+        .await.map_err(|e| Custom(Status::Conflict, format!("Restore event id: {} error: {e}", event.id)))?;
+    for api_token_id in &api_token_ids {
+        sqlx::query("UPDATE api_tokens SET revoked=0 WHERE id=? AND event_id=?")
+            .bind(api_token_id)
+            .bind(event.id)
+            .execute(&mut *tx)
+            .await.map_err(sqlx_to_custom_error)?;
+    }
+    audit(event.id, &Actor::from(&user), EVENT_RESTORE, &event.name, None, summary(&event), &mut *tx).await
+        .map_err(anyhow_to_custom_error)?;
+    tx.commit().await.map_err(sqlx_to_custom_error)?;
+    // DB file is moved after commit, it would be lost in trash if the commit failed
+    if let Some(db_file) = &rec.db_file {
+        evict_event_db(event.id, state).await;
+        if let Err(e) = std::fs::rename(trash_dir(&db_path).join(db_file), &file) {
+            if let Err(e) = undo_restore(&rec, &api_token_ids, &gdb.0).await {
+                error!("Undo restore of event id: {} error: {e}", event.id);
+            }
+            return Err(Custom(Status::Gone, format!("Restore DB file {db_file} error: {e}")));
+        }
+    }
+    info!("User {} restored event id: {}, name: {}", user.email, event.id, event.name);
+    Ok(Redirect::to(format!("/event/{}", event.id)))
+}
+
+pub fn extend(rocket: Rocket<Build>) -> Rocket<Build> {
+    rocket.mount("/", routes![
+            get_trash,
+            restore_event,
+        ])
+}
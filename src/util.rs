@@ -15,33 +15,14 @@ pub(crate) fn unzip_data(bytes: &[u8]) -> Result<Vec<u8>, String> {
     Ok(s)
 }
 
-#[cfg(test)]
-pub(crate) mod test {
-    use std::io::Read;
-    use flate2::bufread::ZlibEncoder;
-    use flate2::Compression;
-    use crate::util::{unzip_data};
-
-    pub(crate) fn zip_data(bytes: &[u8]) -> Result<Vec<u8>, String> {
-        let mut ret_vec = Vec::new();
-        let mut deflater = ZlibEncoder::new(bytes, Compression::fast());
-        deflater.read_to_end(&mut ret_vec).map_err(|e| e.to_string())?;
-        Ok(ret_vec)
-    }
-    
-    #[test]
-    fn test_zip() {
-        let data = b"foo bar baz";
-        let zdata = zip_data(data).unwrap();
-        let udata = unzip_data(&zdata).unwrap();
-        assert_eq!(udata, data);
-    }
+/// Render Markdown to HTML, raw HTML in Markdown is sanitized so that the result can be inserted to page unescaped
+pub(crate) fn markdown_to_html(markdown: &str) -> String {
+    let parser = pulldown_cmark::Parser::new_ext(markdown, pulldown_cmark::Options::ENABLE_TABLES | pulldown_cmark::Options::ENABLE_STRIKETHROUGH);
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    ammonia::clean(&html)
 }
 
-pub(crate) fn string_to_custom_error(err: &str) -> Custom<String> {
-    error!("Error: {err}\nbacktrace: {}", Backtrace::capture());
-    Custom(Status::InternalServerError, format!("Error: {err}"))
-}
 pub(crate) fn sqlx_to_custom_error(err: sqlx::Error) -> Custom<String> {
     error!("SQL Error: {err}\nbacktrace: {}", Backtrace::capture());
     Custom(Status::InternalServerError, format!("SQLx error: {err}"))
@@ -112,4 +93,37 @@ pub fn from_csv_json<T: DeserializeOwned>(data: Vec<Vec<Value>>) -> anyhow::Resu
         result.push(obj);
     }
     Ok(result)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::io::Read;
+    use flate2::bufread::ZlibEncoder;
+    use flate2::Compression;
+    use crate::util::{markdown_to_html, unzip_data};
+
+    pub(crate) fn zip_data(bytes: &[u8]) -> Result<Vec<u8>, String> {
+        let mut ret_vec = Vec::new();
+        let mut deflater = ZlibEncoder::new(bytes, Compression::fast());
+        deflater.read_to_end(&mut ret_vec).map_err(|e| e.to_string())?;
+        Ok(ret_vec)
+    }
+    
+    #[test]
+    fn test_zip() {
+        let data = b"foo bar baz";
+        let zdata = zip_data(data).unwrap();
+        let udata = unzip_data(&zdata).unwrap();
+        assert_eq!(udata, data);
+    }
+
+    #[test]
+    fn test_markdown_to_html() {
+        assert_eq!(markdown_to_html("**Start** at [forest](https://example.com)").trim(),
+                   r#"<p><strong>Start</strong> at <a href="https://example.com" rel="noopener noreferrer">forest</a></p>"#);
+        let html = markdown_to_html("<script>alert(1)</script>\n\n[x](javascript:alert(1)) <img src=x onerror=alert(1)>");
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("javascript:"));
+        assert!(!html.contains("onerror"));
+    }
+}
@@ -0,0 +1,21 @@
+// CSRF token is injected into every HTML page as <meta name="csrf-token"> by the server
+
+function csrfToken() {
+    const meta = document.querySelector('meta[name="csrf-token"]');
+    return meta ? meta.content : '';
+}
+
+function csrfHeaders(headers = {}) {
+    return { ...headers, 'X-CSRF-Token': csrfToken() };
+}
+
+// token must be the first form field, server checks only the beginning of the form data
+document.addEventListener('DOMContentLoaded', () => {
+    for (const form of document.querySelectorAll('form[method="post"]')) {
+        const input = document.createElement('input');
+        input.type = 'hidden';
+        input.name = 'csrf_token';
+        input.value = csrfToken();
+        form.prepend(input);
+    }
+});